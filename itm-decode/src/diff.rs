@@ -0,0 +1,506 @@
+use crate::source::InputOpt;
+use anyhow::{Context, Result};
+use itm::{tcp, DecoderError, TracePacket};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+pub struct DiffOpt {
+    #[structopt(flatten)]
+    input: InputOpt,
+
+    #[structopt(
+        long = "--tolerance",
+        requires = "freq",
+        help = "Also compare the timing of the events, relative to the first event of each capture, and report deviations of more than this many nanoseconds."
+    )]
+    tolerance: Option<u64>,
+
+    #[structopt(
+        long = "--context",
+        default_value = "3",
+        help = "Number of equal events to print before the first divergence."
+    )]
+    context: usize,
+
+    #[structopt(name = "A", parse(from_os_str))]
+    a: PathBuf,
+
+    #[structopt(name = "B", parse(from_os_str))]
+    b: PathBuf,
+}
+
+/// An exception trace or instrumentation packet, and when it was
+/// generated relative to the first event of its capture.
+struct Event {
+    time: Option<Duration>,
+    packet: TracePacket,
+}
+
+/// The events of a capture.
+struct Capture {
+    events: Vec<Event>,
+    malformed: u64,
+}
+
+impl Capture {
+    /// Decodes the capture at `path`.
+    fn decode(input: &InputOpt, path: &Path) -> Result<Self> {
+        let decoder = input.decoder(path)?;
+        let mut capture = Self {
+            events: vec![],
+            malformed: 0,
+        };
+
+        match input.timestamps_configuration(true)? {
+            Some(config) => {
+                for packets in decoder.timestamps(config) {
+                    let packets = match packets {
+                        Ok(packets) => packets,
+                        Err(DecoderError::Io(e)) if tcp::is_reconnect(&e) => continue,
+                        Err(e) => return Err(e).context("Decoder error"),
+                    };
+                    capture.malformed += packets.malformed_packets.len() as u64;
+                    for packet in packets.packets {
+                        capture.push(Some(packets.timestamp.offset()), packet);
+                    }
+                }
+            }
+            None => {
+                for packet in decoder.singles() {
+                    match packet {
+                        Ok(packet) => capture.push(None, packet),
+                        Err(DecoderError::MalformedPacket(_)) => capture.malformed += 1,
+                        Err(DecoderError::Io(e)) if tcp::is_reconnect(&e) => (),
+                        Err(DecoderError::Io(e)) => return Err(e).context("Decoder error"),
+                    }
+                }
+            }
+        }
+
+        if let Some(first) = capture.events.first().and_then(|e| e.time) {
+            for event in capture.events.iter_mut() {
+                event.time = event.time.map(|time| time.saturating_sub(first));
+            }
+        }
+
+        Ok(capture)
+    }
+
+    fn push(&mut self, time: Option<Duration>, packet: TracePacket) {
+        if let TracePacket::ExceptionTrace { .. } | TracePacket::Instrumentation { .. } = packet {
+            self.events.push(Event { time, packet });
+        }
+    }
+
+    /// Returns the number of events of each kind, and the number of
+    /// bytes written to each stimulus port.
+    fn counts(&self) -> BTreeMap<String, u64> {
+        let mut counts = BTreeMap::new();
+        for event in self.events.iter() {
+            match &event.packet {
+                TracePacket::ExceptionTrace { exception, action } => {
+                    *counts
+                        .entry(format!("{:?} {:?}", exception, action))
+                        .or_default() += 1;
+                }
+                TracePacket::Instrumentation { port, payload } => {
+                    *counts.entry(format!("port {} packets", port)).or_default() += 1;
+                    *counts.entry(format!("port {} bytes", port)).or_default() +=
+                        payload.len() as u64;
+                }
+                _ => unreachable!(),
+            }
+        }
+        counts.insert("malformed packets".to_string(), self.malformed);
+
+        counts
+    }
+}
+
+/// Number of events of each capture printed at the first divergence.
+const HUNK_EVENTS: usize = 10;
+
+/// Returns the index pairs of the equal elements of a longest common
+/// subsequence of `a` and `b`, in order.
+///
+/// Uses the linear space variant of Myers' algorithm, which takes
+/// O((N+M)D) time for N and M elements and D differences. See E. W.
+/// Myers, "An O(ND) Difference Algorithm and Its Variations", 1986.
+fn align<T>(a: &[T], b: &[T]) -> Vec<(usize, usize)>
+where
+    T: PartialEq,
+{
+    let mut pairs = vec![];
+    align_into(a, b, (0, 0), &mut pairs);
+    pairs
+}
+
+fn align_into<T>(a: &[T], b: &[T], offset: (usize, usize), pairs: &mut Vec<(usize, usize)>)
+where
+    T: PartialEq,
+{
+    let prefix = a.iter().zip(b).take_while(|(a, b)| a == b).count();
+    pairs.extend((0..prefix).map(|i| (offset.0 + i, offset.1 + i)));
+    let (a, b) = (&a[prefix..], &b[prefix..]);
+    let offset = (offset.0 + prefix, offset.1 + prefix);
+
+    let suffix = a
+        .iter()
+        .rev()
+        .zip(b.iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let (a, b) = (&a[..a.len() - suffix], &b[..b.len() - suffix]);
+
+    if !a.is_empty() && !b.is_empty() {
+        let (d, start, end) = middle_snake(a, b);
+        if d > 1 {
+            align_into(&a[..start.0], &b[..start.1], offset, pairs);
+            pairs.extend(
+                (0..end.0 - start.0).map(|i| (offset.0 + start.0 + i, offset.1 + start.1 + i)),
+            );
+            align_into(
+                &a[end.0..],
+                &b[end.1..],
+                (offset.0 + end.0, offset.1 + end.1),
+                pairs,
+            );
+        } else {
+            // NOTE(d) a single insertion or deletion: the shorter
+            // sequence is a subsequence of the longer one.
+            let (mut i, mut j) = (0, 0);
+            while i < a.len() && j < b.len() {
+                if a[i] == b[j] {
+                    pairs.push((offset.0 + i, offset.1 + j));
+                    j += 1;
+                    i += 1;
+                } else if a.len() > b.len() {
+                    i += 1;
+                } else {
+                    j += 1;
+                }
+            }
+        }
+    }
+
+    let (n, m) = (offset.0 + a.len(), offset.1 + b.len());
+    pairs.extend((0..suffix).map(|i| (n + i, m + i)));
+}
+
+/// Returns the number of differences between `a` and `b`, and the start
+/// and end of the middle snake of an optimal edit path.
+fn middle_snake<T>(a: &[T], b: &[T]) -> (usize, (usize, usize), (usize, usize))
+where
+    T: PartialEq,
+{
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let delta = n - m;
+    let max = (n + m + 1) / 2;
+
+    // NOTE(v) the furthest reaching x of each diagonal k, indexed by
+    // k + max + 1; backwards from the ends of `a` and `b`.
+    let mut forward = vec![0; 2 * max as usize + 3];
+    let mut backward = vec![0; 2 * max as usize + 3];
+    let v = |k: isize| (k + max + 1) as usize;
+
+    for d in 0..=max {
+        for k in (-d..=d).step_by(2) {
+            let mut x = if k == -d || (k != d && forward[v(k - 1)] < forward[v(k + 1)]) {
+                forward[v(k + 1)]
+            } else {
+                forward[v(k - 1)] + 1
+            };
+            let mut y = x - k;
+            let start = (x, y);
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            forward[v(k)] = x;
+
+            let c = delta - k;
+            if delta % 2 != 0 && (-(d - 1)..=d - 1).contains(&c) && x + backward[v(c)] >= n {
+                return (
+                    (2 * d - 1) as usize,
+                    (start.0 as usize, start.1 as usize),
+                    (x as usize, y as usize),
+                );
+            }
+        }
+
+        for k in (-d..=d).step_by(2) {
+            let mut x = if k == -d || (k != d && backward[v(k - 1)] < backward[v(k + 1)]) {
+                backward[v(k + 1)]
+            } else {
+                backward[v(k - 1)] + 1
+            };
+            let mut y = x - k;
+            let end = (x, y);
+            while x < n && y < m && a[(n - x - 1) as usize] == b[(m - y - 1) as usize] {
+                x += 1;
+                y += 1;
+            }
+            backward[v(k)] = x;
+
+            let c = delta - k;
+            if delta % 2 == 0 && (-d..=d).contains(&c) && x + forward[v(c)] >= n {
+                return (
+                    (2 * d) as usize,
+                    ((n - x) as usize, (m - y) as usize),
+                    ((n - end.0) as usize, (m - end.1) as usize),
+                );
+            }
+        }
+    }
+
+    unreachable!("an edit path has at most N + M differences")
+}
+
+fn print_event(side: &str, index: usize, event: Option<&Event>) {
+    match event {
+        Some(Event {
+            time: Some(time),
+            packet,
+        }) => println!("{} {:>8} {:>14?} {:?}", side, index, time, packet),
+        Some(Event { time: None, packet }) => println!("{} {:>8} {:?}", side, index, packet),
+        None => println!("{} {:>8} end of capture", side, index),
+    }
+}
+
+/// Prints the events of a capture in `range`, which are not in the
+/// other capture, or the end of the capture if there are none.
+fn print_hunk(side: &str, capture: &Capture, range: std::ops::Range<usize>) {
+    if range.is_empty() {
+        match capture.events.get(range.start) {
+            Some(_) => println!("{} {:>8} no events", side, range.start),
+            None => print_event(side, range.start, None),
+        }
+        return;
+    }
+
+    for i in range.clone().take(HUNK_EVENTS) {
+        print_event(side, i, capture.events.get(i));
+    }
+    if range.len() > HUNK_EVENTS {
+        println!("{} {:>8} ... {} more", side, "", range.len() - HUNK_EVENTS);
+    }
+}
+
+/// Prints the differences between the captures. Returns whether they
+/// differ.
+fn compare(a: &Capture, b: &Capture, opt: &DiffOpt) -> bool {
+    let mut differ = false;
+    println!(
+        "A: {} events ({})\nB: {} events ({})",
+        a.events.len(),
+        opt.a.display(),
+        b.events.len(),
+        opt.b.display()
+    );
+
+    // NOTE(align) events are compared along a longest common
+    // subsequence, so that an extra or missing event does not hide the
+    // equal events after it.
+    let pairs = align(
+        &a.events.iter().map(|e| &e.packet).collect::<Vec<_>>(),
+        &b.events.iter().map(|e| &e.packet).collect::<Vec<_>>(),
+    );
+    let mut hunks = vec![];
+    let mut next = (0, 0);
+    for &(i, j) in pairs
+        .iter()
+        .chain([(a.events.len(), b.events.len())].iter())
+    {
+        if (i, j) != next {
+            hunks.push((next.0..i, next.1..j));
+        }
+        next = (i + 1, j + 1);
+    }
+
+    match hunks.first() {
+        Some((a_range, b_range)) => {
+            differ = true;
+            println!(
+                "\nDivergences: {}, with {} events only in A and {} only in B.",
+                hunks.len(),
+                a.events.len() - pairs.len(),
+                b.events.len() - pairs.len()
+            );
+            println!(
+                "\nFirst divergence at event {} of A and {} of B:",
+                a_range.start, b_range.start
+            );
+            for j in a_range.start.saturating_sub(opt.context)..a_range.start {
+                print_event(" ", j, a.events.get(j));
+            }
+            print_hunk("A", a, a_range.clone());
+            print_hunk("B", b, b_range.clone());
+        }
+        None => println!("\nEvent sequences are equal."),
+    }
+
+    let (a_counts, b_counts) = (a.counts(), b.counts());
+    let mut keys: Vec<&String> = a_counts.keys().chain(b_counts.keys()).collect();
+    keys.sort();
+    keys.dedup();
+    let differences: Vec<_> = keys
+        .into_iter()
+        .map(|key| {
+            (
+                key,
+                a_counts.get(key).copied().unwrap_or(0),
+                b_counts.get(key).copied().unwrap_or(0),
+            )
+        })
+        .filter(|(_, a, b)| a != b)
+        .collect();
+    if !differences.is_empty() {
+        differ = true;
+        println!("\nCount differences:");
+        println!("  {:<40} {:>10} {:>10}", "", "A", "B");
+        for (key, a, b) in differences {
+            println!("  {:<40} {:>10} {:>10}", key, a, b);
+        }
+    }
+
+    if let Some(tolerance) = opt.tolerance.map(Duration::from_nanos) {
+        // NOTE(pairs) only equal events can be compared.
+        let deviations: Vec<((usize, usize), Duration)> = pairs
+            .iter()
+            .filter_map(|&(i, j)| {
+                let (a, b) = (a.events[i].time?, b.events[j].time?);
+                Some(((i, j), a.max(b) - a.min(b)))
+            })
+            .collect();
+        let beyond: Vec<_> = deviations.iter().filter(|(_, d)| *d > tolerance).collect();
+        let max = deviations.iter().map(|(_, d)| *d).max().unwrap_or_default();
+
+        println!(
+            "\nTiming of {} equal events: maximum deviation {:?}, {} beyond the tolerance of {:?}.",
+            deviations.len(),
+            max,
+            beyond.len(),
+            tolerance
+        );
+        if let Some(((i, j), deviation)) = beyond.first() {
+            differ = true;
+            println!(
+                "First deviation beyond the tolerance at event {} of A and {} of B:",
+                i, j
+            );
+            print_event("A", *i, a.events.get(*i));
+            print_event("B", *j, b.events.get(*j));
+            println!("  deviation {:?}", deviation);
+        }
+    }
+
+    differ
+}
+
+/// Compares the captures and exits with status 1 if they differ.
+pub fn run(opt: DiffOpt) -> Result<()> {
+    let a = Capture::decode(&opt.input, &opt.a)
+        .with_context(|| format!("failed to decode {}", opt.a.display()))?;
+    let b = Capture::decode(&opt.input, &opt.b)
+        .with_context(|| format!("failed to decode {}", opt.b.display()))?;
+
+    if compare(&a, &b, &opt) {
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the length of a longest common subsequence of `a` and
+    /// `b`, by dynamic programming.
+    fn lcs_len(a: &[u8], b: &[u8]) -> usize {
+        let mut lengths = vec![vec![0; b.len() + 1]; a.len() + 1];
+        for i in 0..a.len() {
+            for j in 0..b.len() {
+                lengths[i + 1][j + 1] = if a[i] == b[j] {
+                    lengths[i][j] + 1
+                } else {
+                    lengths[i][j + 1].max(lengths[i + 1][j])
+                };
+            }
+        }
+        lengths[a.len()][b.len()]
+    }
+
+    #[test]
+    fn align_lcs() {
+        // NOTE(seed) a linear congruential generator, for reproducible
+        // sequences of a few symbols.
+        let mut seed: u32 = 1;
+        let mut sequence = |len: u32| -> Vec<u8> {
+            (0..len)
+                .map(|_| {
+                    seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                    (seed >> 16) as u8 % 3
+                })
+                .collect()
+        };
+
+        for n in 0..12 {
+            for m in 0..12 {
+                let (a, b) = (sequence(n), sequence(m));
+                let pairs = align(&a, &b);
+                assert_eq!(pairs.len(), lcs_len(&a, &b), "{:?} {:?}", a, b);
+                assert!(pairs.iter().all(|&(i, j)| a[i] == b[j]));
+                assert!(pairs.windows(2).all(|w| w[0].0 < w[1].0 && w[0].1 < w[1].1));
+            }
+        }
+    }
+
+    #[test]
+    fn compare_timing() {
+        let capture = |events: &[(u64, u8)]| Capture {
+            events: events
+                .iter()
+                .map(|&(time, port)| Event {
+                    time: Some(Duration::from_nanos(time)),
+                    packet: TracePacket::Instrumentation {
+                        port,
+                        payload: vec![0],
+                    },
+                })
+                .collect(),
+            malformed: 0,
+        };
+        let opt = |tolerance| {
+            DiffOpt::from_iter([
+                "diff",
+                "--itm-freq",
+                "1000000",
+                "--tolerance",
+                tolerance,
+                "a",
+                "b",
+            ])
+        };
+
+        let a = capture(&[(0, 1), (1000, 2), (2000, 3)]);
+        assert!(!compare(&a, &a, &opt("0")));
+
+        let late = capture(&[(0, 1), (1000, 2), (2500, 3)]);
+        assert!(!compare(&a, &late, &opt("500")));
+        assert!(compare(&a, &late, &opt("100")));
+
+        // NOTE(shifted) the events after the extra one are still
+        // aligned with those of A.
+        let b = capture(&[(0, 1), (500, 9), (1000, 2), (2500, 3)]);
+        assert!(compare(&a, &b, &opt("1000")));
+        let pairs = align(
+            &a.events.iter().map(|e| &e.packet).collect::<Vec<_>>(),
+            &b.events.iter().map(|e| &e.packet).collect::<Vec<_>>(),
+        );
+        assert_eq!(pairs, [(0, 0), (1, 2), (2, 3)]);
+    }
+}
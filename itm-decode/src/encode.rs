@@ -0,0 +1,108 @@
+use crate::source::lts_prescaler;
+use anyhow::{Context, Result};
+use itm::encode::{Encoder, EncoderOptions, TimedPacket};
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+pub struct EncodeOpt {
+    #[structopt(long = "--itm-prescaler")]
+    prescaler: Option<u8>,
+
+    #[structopt(
+        long = "--itm-freq",
+        name = "freq",
+        help = "Frequency of the ITM timestamp clock. Required if any packet is timed."
+    )]
+    freq: Option<u32>,
+
+    #[structopt(
+        long = "--gts-interval",
+        requires = "freq",
+        help = "Also insert a global timestamp every this many nanoseconds, not only when a local timestamp cannot hold the time since the previous one."
+    )]
+    gts_interval: Option<u64>,
+
+    #[structopt(
+        long = "--output",
+        parse(from_os_str),
+        help = "Write the trace stream to this file instead of stdout."
+    )]
+    output: Option<PathBuf>,
+
+    #[structopt(
+        name = "PACKETS",
+        parse(from_os_str),
+        help = "JSON, or TOML if it has the .toml extension, file describing the packets to encode; - reads JSON from stdin."
+    )]
+    packets: PathBuf,
+}
+
+/// A description of a trace stream. A JSON description can also be a
+/// bare list of packets.
+#[derive(serde::Deserialize)]
+struct Description {
+    packets: Vec<TimedPacket>,
+}
+
+/// Reads the packets described in `path`.
+fn read_packets(path: &Path) -> Result<Vec<TimedPacket>> {
+    let mut description = String::new();
+    if path == Path::new("-") {
+        io::stdin().read_to_string(&mut description)
+    } else {
+        File::open(path).and_then(|mut f| f.read_to_string(&mut description))
+    }
+    .with_context(|| format!("failed to read {}", path.display()))?;
+
+    if path.extension().is_some_and(|ext| ext == "toml") {
+        let description: Description = toml::from_str(&description)
+            .with_context(|| format!("failed to parse {}", path.display()))?;
+        return Ok(description.packets);
+    }
+
+    #[derive(serde::Deserialize)]
+    #[serde(untagged)]
+    enum Json {
+        Packets(Vec<TimedPacket>),
+        Description(Description),
+    }
+    Ok(
+        match serde_json::from_str(&description)
+            .with_context(|| format!("failed to parse {}", path.display()))?
+        {
+            Json::Packets(packets) | Json::Description(Description { packets }) => packets,
+        },
+    )
+}
+
+pub fn run(opt: EncodeOpt) -> Result<()> {
+    let packets = read_packets(&opt.packets)?;
+    let options = match opt.freq {
+        Some(freq) => Some(EncoderOptions {
+            clock_frequency: freq,
+            lts_prescaler: lts_prescaler(opt.prescaler)?,
+            gts_interval: opt.gts_interval.map(Duration::from_nanos),
+        }),
+        None => None,
+    };
+
+    let writer: Box<dyn Write> = match &opt.output {
+        Some(path) => Box::new(
+            File::create(path).with_context(|| format!("failed to create {}", path.display()))?,
+        ),
+        None => Box::new(io::stdout()),
+    };
+    let mut encoder = Encoder::new(BufWriter::new(writer), options);
+    for (i, TimedPacket { packet, time }) in packets.iter().enumerate() {
+        encoder
+            .write(packet, *time)
+            .with_context(|| format!("failed to encode packet {}", i))?;
+    }
+    encoder.finish().context("failed to write trace stream")?;
+
+    Ok(())
+}
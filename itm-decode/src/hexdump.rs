@@ -0,0 +1,171 @@
+use crate::source::InputOpt;
+use anyhow::{Context, Result};
+use itm::{classify_header, DecoderError, HeaderClass, TracePacketKind};
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+/// Number of bytes printed per line.
+const BYTES_PER_LINE: usize = 8;
+
+const SYNC_COLOR: &str = "\x1b[36m";
+const CONTINUATION_COLOR: &str = "\x1b[33m";
+const MALFORMED_COLOR: &str = "\x1b[31m";
+const RESET: &str = "\x1b[0m";
+
+#[derive(StructOpt, Debug)]
+pub struct HexdumpOpt {
+    #[structopt(flatten)]
+    input: InputOpt,
+
+    #[structopt(
+        long = "--color",
+        help = "Highlight synchronization packets (cyan), continuation bits (yellow), and malformed packets (red) with ANSI escape codes."
+    )]
+    color: bool,
+
+    #[structopt(
+        name = "FILE",
+        parse(from_os_str),
+        help = "Trace input file, serial device, named pipe, or tcp://host:port; - reads from stdin."
+    )]
+    file: PathBuf,
+}
+
+/// Prints the bytes of each packet next to the classification of its
+/// header and the decoded packet or error.
+struct Hexdump<W>
+where
+    W: Write,
+{
+    writer: W,
+    color: bool,
+}
+
+impl<W> Hexdump<W>
+where
+    W: Write,
+{
+    fn header(&mut self) -> Result<()> {
+        writeln!(
+            self.writer,
+            "{:<8}  {:<w$}  {:<40}  packet",
+            "offset",
+            "bytes",
+            "header",
+            w = BYTES_PER_LINE * 3,
+        )?;
+        Ok(())
+    }
+
+    /// Prints the `raw` bytes of a packet found at byte `offset` of the
+    /// trace. `decoded` is the packet or error they were decoded into,
+    /// or `None` if they do not form a complete packet.
+    fn packet(
+        &mut self,
+        offset: u64,
+        raw: &[u8],
+        decoded: Option<Result<String, String>>,
+    ) -> Result<()> {
+        if raw.is_empty() {
+            return Ok(());
+        }
+
+        let class = classify_header(raw[0]);
+        let (class_text, continuation) = match &class {
+            Ok(HeaderClass::Packet(_)) => ("single-byte packet".to_string(), false),
+            Ok(HeaderClass::Stub {
+                kind: TracePacketKind::Sync,
+                ..
+            }) => (format!("Sync, {} bytes", raw.len()), false),
+            Ok(HeaderClass::Stub {
+                kind,
+                payload_size: Some(size),
+            }) => (format!("{:?}, {} payload bytes", kind, size), false),
+            Ok(HeaderClass::Stub {
+                kind,
+                payload_size: None,
+            }) => (format!("{:?}, {} payload bytes", kind, raw.len() - 1), true),
+            Err(_) => ("malformed header".to_string(), false),
+        };
+        let color = match (&class, &decoded) {
+            (_, Some(Err(_))) | (Err(_), _) => Some(MALFORMED_COLOR),
+            (
+                Ok(HeaderClass::Stub {
+                    kind: TracePacketKind::Sync,
+                    ..
+                }),
+                _,
+            ) => Some(SYNC_COLOR),
+            _ => None,
+        };
+        let decoded = match decoded {
+            Some(Ok(packet)) => packet,
+            Some(Err(e)) => e,
+            None => "incomplete packet".to_string(),
+        };
+
+        for (i, chunk) in raw.chunks(BYTES_PER_LINE).enumerate() {
+            let mut bytes = String::new();
+            for (j, byte) in chunk.iter().enumerate() {
+                // NOTE(continuation) the header is not part of the payload
+                let is_continued = continuation && i + j > 0 && byte & 0x80 != 0;
+                let (color, marker) = if is_continued {
+                    (Some(CONTINUATION_COLOR), '+')
+                } else {
+                    (color, ' ')
+                };
+                bytes += &self.paint(&format!("{:02x}", byte), color);
+                bytes.push(marker);
+            }
+            let pad = " ".repeat((BYTES_PER_LINE - chunk.len()) * 3);
+
+            if i == 0 {
+                let class_text = format!("{:<40}", class_text);
+                let decoded = self.paint(&decoded, color);
+                writeln!(
+                    self.writer,
+                    "{:08x}  {}{}  {}  {}",
+                    offset, bytes, pad, class_text, decoded
+                )?;
+            } else {
+                writeln!(self.writer, "{:8}  {}", "", bytes.trim_end())?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn paint(&self, text: &str, color: Option<&str>) -> String {
+        match color {
+            Some(color) if self.color => format!("{}{}{}", color, text, RESET),
+            _ => text.to_string(),
+        }
+    }
+}
+
+pub fn run(opt: HexdumpOpt) -> Result<()> {
+    let mut singles = opt.input.capture(&opt.file)?.singles();
+    let mut hexdump = Hexdump {
+        writer: BufWriter::new(io::stdout()),
+        color: opt.color,
+    };
+
+    hexdump.header()?;
+    let mut offset = 0;
+    while let Some(packet) = singles.next() {
+        let decoded = match packet {
+            Ok(packet) => Ok(format!("{:?}", packet)),
+            Err(DecoderError::MalformedPacket(m)) => Err(m.to_string()),
+            Err(DecoderError::Io(e)) => return Err(e).context("Decoder error"),
+        };
+        let end = singles.bytes_consumed();
+        hexdump.packet(offset, &singles.get_mut().take(end), Some(decoded))?;
+        offset = end;
+    }
+    hexdump.packet(offset, &singles.get_mut().take(u64::MAX), None)?;
+
+    hexdump.writer.flush()?;
+
+    Ok(())
+}
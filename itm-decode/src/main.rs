@@ -1,85 +1,577 @@
-use anyhow::{bail, Context, Result};
-use itm::{serial, Decoder, DecoderOptions, LocalTimestampOptions, TimestampsConfiguration};
+use anyhow::{Context, Result};
+#[cfg(feature = "defmt")]
+use itm::defmt::{self, DefmtDecoder};
+use itm::{
+    export::{
+        chrome::{ChromeTraceConfiguration, ChromeTraceWriter},
+        ctf::CtfWriter,
+        vcd::{VcdSignals, VcdWriter},
+    },
+    pcapng::{PcapngWriter, RawCapture},
+    tcp, Decoder, DecoderError, PacketFilter, Timestamp, TimestampedTracePackets, TracePacket,
+    TracePacketKind,
+};
 use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
 use std::path::PathBuf;
+use std::time::Duration;
 use structopt::StructOpt;
 
+mod diff;
+use diff::DiffOpt;
+mod encode;
+use encode::EncodeOpt;
+mod hexdump;
+use hexdump::HexdumpOpt;
+mod tui;
+use tui::TuiOpt;
+mod output;
+use output::{OutputFormat, PacketOutput};
+
+mod replay;
+use replay::ReplayOpt;
+mod source;
+use source::InputOpt;
+
+mod stats;
+use stats::StatsOpt;
+
+mod text;
+use text::TextOutput;
+
 #[derive(StructOpt, Debug)]
 #[structopt(
-    about = "An ITM/DWT packet protocol decoder, as specified in the ARMv7-M architecture reference manual, Appendix D4. See <https://developer.arm.com/documentation/ddi0403/ed/>. Report bugs and request features at <https://github.com/rust-embedded/itm>."
+    about = "An ITM/DWT packet protocol decoder, as specified in the ARMv7-M architecture reference manual, Appendix D4. See <https://developer.arm.com/documentation/ddi0403/ed/>. Report bugs and request features at <https://github.com/rust-embedded/itm>.",
+    setting = structopt::clap::AppSettings::SubcommandsNegateReqs,
+    setting = structopt::clap::AppSettings::ArgsNegateSubcommands
 )]
 struct Opt {
-    #[structopt(long = "--ignore-eof")]
-    ignore_eof: bool,
+    #[structopt(subcommand)]
+    command: Option<Command>,
 
-    #[structopt(long = "--timestamps", requires("freq"))]
-    timestamps: bool,
+    #[structopt(flatten)]
+    decode: DecodeOpt,
+}
 
-    #[structopt(long = "--itm-prescaler")]
-    prescaler: Option<u8>,
+// Options of decoding, done unless a subcommand is given. See
+// `InputOpt` on why this is not a doc comment.
+#[derive(StructOpt, Debug)]
+struct DecodeOpt {
+    #[structopt(flatten)]
+    input: InputOpt,
 
-    #[structopt(long = "--itm-freq", name = "freq")]
-    freq: Option<u32>,
+    #[structopt(long = "--timestamps", requires("freq"))]
+    timestamps: bool,
 
     #[structopt(long = "--expect-malformed")]
     expect_malformed: bool,
 
-    #[structopt(name = "FILE", parse(from_os_str), help = "Raw trace input file.")]
-    file: PathBuf,
+    #[structopt(
+        long = "--output-format",
+        default_value = "debug",
+        help = "Format of the decoded packets: debug, json-lines, or csv. json-lines and csv include the byte offset, timestamp, and timestamp quality of each packet."
+    )]
+    output_format: OutputFormat,
+
+    #[structopt(
+        long = "--output",
+        parse(from_os_str),
+        help = "Write the decoded packets, or the text of --text and the logs of --defmt-elf, to this file instead of stdout."
+    )]
+    output: Option<PathBuf>,
+
+    #[structopt(
+        long = "--text",
+        help = "Print the text written to the stimulus ports instead of the decoded packets."
+    )]
+    text: bool,
+
+    #[structopt(
+        long = "--text-port",
+        name = "port",
+        number_of_values = 1,
+        requires("text"),
+        help = "Only print the text written to this stimulus port. Can be given multiple times."
+    )]
+    text_ports: Vec<u8>,
+
+    #[structopt(
+        long = "--text-dir",
+        parse(from_os_str),
+        requires("text"),
+        help = "Write the text of each stimulus port to <DIR>/stim<port>.txt instead of stdout or --output."
+    )]
+    text_dir: Option<PathBuf>,
+
+    #[cfg(feature = "defmt")]
+    #[structopt(
+        long = "--defmt-elf",
+        parse(from_os_str),
+        help = "Decode the defmt frames written to --defmt-port using the defmt table of this ELF."
+    )]
+    defmt_elf: Option<PathBuf>,
+
+    #[cfg(feature = "defmt")]
+    #[structopt(
+        long = "--defmt-port",
+        default_value = "0",
+        help = "The stimulus port the defmt frames are written to."
+    )]
+    defmt_port: u8,
+
+    #[structopt(
+        long = "--chrome-trace",
+        parse(from_os_str),
+        requires("timestamps"),
+        help = "Also write the timestamped packets to this file as Chrome Trace Event JSON, for Perfetto or chrome://tracing."
+    )]
+    chrome_trace: Option<PathBuf>,
+
+    #[structopt(
+        long = "--chrome-counter-port",
+        name = "counter-port",
+        number_of_values = 1,
+        requires("chrome-trace"),
+        help = "Export the payloads written to this stimulus port as counter values. Can be given multiple times."
+    )]
+    chrome_counter_ports: Vec<u8>,
+
+    #[structopt(
+        long = "--vcd",
+        parse(from_os_str),
+        requires("timestamps"),
+        help = "Also write exception, stimulus port, and data trace activity to this file as a Value Change Dump, for GTKWave."
+    )]
+    vcd: Option<PathBuf>,
+
+    #[structopt(
+        long = "--ctf",
+        parse(from_os_str),
+        requires("timestamps"),
+        help = "Also write the timestamped packets to this directory as a Common Trace Format trace, for Trace Compass or babeltrace."
+    )]
+    ctf: Option<PathBuf>,
+
+    #[structopt(
+        long = "--pcapng",
+        parse(from_os_str),
+        help = "Also write the raw bytes of each packet to this file as a pcapng capture. With --timestamps, each packet is timestamped with the timestamp of its set."
+    )]
+    pcapng: Option<PathBuf>,
+
+    #[structopt(
+        long = "--include-kind",
+        name = "include-kind",
+        number_of_values = 1,
+        help = "Only output packets of this kind, e.g. ExceptionTrace. Can be given multiple times. Filters do not apply to --pcapng."
+    )]
+    include_kinds: Vec<TracePacketKind>,
+
+    #[structopt(
+        long = "--exclude-kind",
+        name = "exclude-kind",
+        number_of_values = 1,
+        help = "Do not output packets of this kind. Can be given multiple times."
+    )]
+    exclude_kinds: Vec<TracePacketKind>,
+
+    #[structopt(
+        long = "--filter-port",
+        name = "filter-port",
+        number_of_values = 1,
+        help = "Only output instrumentation packets written to this stimulus port. Can be given multiple times."
+    )]
+    filter_ports: Vec<u8>,
+
+    #[structopt(
+        long = "--filter-comparator",
+        name = "filter-comparator",
+        number_of_values = 1,
+        help = "Only output data trace packets generated by this DWT comparator. Can be given multiple times."
+    )]
+    filter_comparators: Vec<u8>,
+
+    #[structopt(
+        long = "--filter-exception",
+        name = "filter-exception",
+        number_of_values = 1,
+        help = "Only output exception trace packets of this exception number, e.g. 15 for SysTick or 16 for IRQ 0. Can be given multiple times."
+    )]
+    filter_exceptions: Vec<u16>,
+
+    #[structopt(
+        long = "--start",
+        requires("timestamps"),
+        help = "Only output packets timestamped at or after this many nanoseconds."
+    )]
+    start: Option<u64>,
+
+    #[structopt(
+        long = "--end",
+        requires("timestamps"),
+        help = "Only output packets timestamped at or before this many nanoseconds."
+    )]
+    end: Option<u64>,
+
+    // NOTE(Option) only required without a subcommand, which is checked
+    // in `main`.
+    #[structopt(
+        name = "FILE",
+        parse(from_os_str),
+        help = "Trace input file, serial device, named pipe, or tcp://host:port; - reads from stdin. Serial devices are configured with --itm-freq as the baud rate."
+    )]
+    file: Option<PathBuf>,
+}
+
+/// Consumers of the decoded packets, used instead of printing the
+/// packets themselves. Their output is written to `writer`.
+struct Sinks {
+    text: Option<TextOutput>,
+    #[cfg(feature = "defmt")]
+    defmt: Option<DefmtDecoder<'static>>,
+    writer: Box<dyn Write>,
+}
+
+impl Sinks {
+    fn is_empty(&self) -> bool {
+        #[cfg(feature = "defmt")]
+        if self.defmt.is_some() {
+            return false;
+        }
+
+        self.text.is_none()
+    }
+
+    fn handle(&mut self, packet: &TracePacket, timestamp: Option<&Timestamp>) -> Result<()> {
+        #[cfg(feature = "defmt")]
+        if let Some(defmt) = self.defmt.as_mut() {
+            if matches!(packet, TracePacket::Instrumentation { port, .. } if *port == defmt.port())
+            {
+                for log in defmt.push(packet, timestamp) {
+                    match (log, timestamp) {
+                        (Err(e), _) => eprintln!("defmt: {}", e),
+                        (Ok(log), Some(ts)) => writeln!(self.writer, "{:?} {}", ts, log.line)?,
+                        (Ok(log), None) => writeln!(self.writer, "{}", log.line)?,
+                    }
+                }
+                return Ok(());
+            }
+        }
+
+        if let Some(text) = self.text.as_mut() {
+            text.handle(packet, timestamp, &mut self.writer)?;
+        }
+
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<()> {
+        if let Some(text) = self.text {
+            text.finish(&mut self.writer)?;
+        }
+        self.writer.flush()?;
+
+        Ok(())
+    }
+}
+
+/// Where the decoded packets go: printed as packets, or to [`Sinks`].
+enum Output {
+    Packets(PacketOutput),
+    Sinks(Sinks),
+}
+
+impl Output {
+    fn single(&mut self, offset: u64, packet: &TracePacket) -> Result<()> {
+        match self {
+            Self::Packets(output) => output.single(offset, packet),
+            Self::Sinks(sinks) => sinks.handle(packet, None),
+        }
+    }
+
+    fn timestamped(&mut self, offset: u64, packets: &TimestampedTracePackets) -> Result<()> {
+        match self {
+            Self::Packets(output) => output.timestamped(offset, packets),
+            Self::Sinks(sinks) => {
+                for packet in packets.packets.iter() {
+                    sinks.handle(packet, Some(&packets.timestamp))?;
+                }
+
+                Ok(())
+            }
+        }
+    }
+
+    fn finish(self) -> Result<()> {
+        match self {
+            Self::Packets(output) => output.finish(),
+            Self::Sinks(sinks) => sinks.finish(),
+        }
+        .context("failed to write output")
+    }
+}
+
+#[derive(StructOpt, Debug)]
+enum Command {
+    /// Print statistics of a capture: packet counts, bytes, malformed
+    /// packets, timestamp qualities, and duration.
+    Stats(StatsOpt),
+
+    /// Print the raw bytes of each packet next to the classification of
+    /// its header and the decoded packet or error. Timestamp payload
+    /// bytes with a set continuation bit are marked with a +.
+    Hexdump(HexdumpOpt),
+
+    /// Show live packets, stimulus port text, exception activity, and
+    /// overflow and malformed packet rates in an interactive terminal
+    /// UI. Requires --itm-freq.
+    Tui(TuiOpt),
+
+    /// Replay a raw capture or a recording into a pseudo-terminal or a
+    /// named pipe at its original pace, e.g. to test tools that expect
+    /// a serial device.
+    Replay(ReplayOpt),
+
+    /// Compare the exception and instrumentation events of two
+    /// captures, regardless of their byte layout. Events are aligned
+    /// along a longest common subsequence. Prints the first divergence
+    /// and differences in counts and timing, and exits with status 1 if
+    /// the captures differ.
+    Diff(DiffOpt),
+
+    /// Encode a list of packets into a trace stream, inserting local and
+    /// global timestamps for the packets given a time. The list is a
+    /// JSON array, or a `packets` array in JSON or TOML, of objects with
+    /// a `packet` in the JSON representation of the decoded packets and
+    /// an optional `time`, e.g. {"packet": {"Instrumentation": {"port":
+    /// 0, "payload": [104]}}, "time": {"secs": 0, "nanos": 1000}}.
+    Encode(EncodeOpt),
 }
 
 fn main() -> Result<()> {
     let opt = Opt::from_args();
+    match opt.command {
+        None if opt.decode.file.is_none() => structopt::clap::Error::with_description(
+            "The following required arguments were not provided:\n    <FILE>",
+            structopt::clap::ErrorKind::MissingRequiredArgument,
+        )
+        .exit(),
+        None => decode(opt.decode),
+        Some(Command::Stats(opt)) => stats::run(opt),
+        Some(Command::Hexdump(opt)) => hexdump::run(opt),
+        Some(Command::Tui(opt)) => tui::run(opt),
+        Some(Command::Replay(opt)) => replay::run(opt),
+        Some(Command::Diff(opt)) => diff::run(opt),
+        Some(Command::Encode(opt)) => encode::run(opt),
+    }
+}
+
+/// A trace reader from which the raw bytes of each decoded packet can
+/// be taken for `--pcapng`.
+trait TakeRaw: Read {
+    fn take(&mut self, offset: u64) -> Vec<u8>;
+}
+
+impl<R> TakeRaw for RawCapture<R>
+where
+    R: Read,
+{
+    fn take(&mut self, offset: u64) -> Vec<u8> {
+        RawCapture::take(self, offset)
+    }
+}
+
+impl TakeRaw for Box<dyn Read> {
+    // NOTE(empty) only used without --pcapng, which keeps no bytes.
+    fn take(&mut self, _offset: u64) -> Vec<u8> {
+        vec![]
+    }
+}
 
-    let file = File::open(&opt.file).context("failed to open file")?;
-    if let Some(freq) = opt.freq {
-        serial::configure(&file, freq)?;
+/// Decodes the trace of `opt.file` to the outputs selected by `opt`.
+fn decode(opt: DecodeOpt) -> Result<()> {
+    let file = opt.file.as_ref().unwrap();
+    // NOTE(capture) the bytes read are only kept when they are written.
+    match opt.pcapng {
+        Some(_) => decode_from(&opt, opt.input.capture(file)?),
+        None => decode_from(&opt, opt.input.decoder(file)?),
     }
+}
 
-    let decoder = Decoder::<File>::new(
-        file,
-        DecoderOptions {
-            ignore_eof: opt.ignore_eof,
+/// Decodes the trace of `decoder` to the outputs selected by `opt`.
+fn decode_from<R>(opt: &DecodeOpt, decoder: Decoder<R>) -> Result<()>
+where
+    R: TakeRaw,
+{
+    let sinks = Sinks {
+        text: if opt.text {
+            Some(TextOutput::new(
+                opt.text_ports.clone(),
+                opt.text_dir.clone(),
+            )?)
+        } else {
+            None
+        },
+        // NOTE(leak) the table is used until the process exits.
+        #[cfg(feature = "defmt")]
+        defmt: match &opt.defmt_elf {
+            Some(elf) => Some(DefmtDecoder::new(
+                Box::leak(Box::new(defmt::load_table(
+                    &std::fs::read(elf).context("failed to read defmt ELF")?,
+                )?)),
+                opt.defmt_port,
+            )),
+            None => None,
         },
-    );
-
-    match opt {
-        Opt {
-            timestamps: true,
-            prescaler,
-            freq: Some(freq),
-            expect_malformed,
-            ..
-        } => {
-            for packets in decoder.timestamps(TimestampsConfiguration {
-                clock_frequency: freq,
-                lts_prescaler: match prescaler {
-                    None | Some(1) => LocalTimestampOptions::Enabled,
-                    Some(4) => LocalTimestampOptions::EnabledDiv4,
-                    Some(16) => LocalTimestampOptions::EnabledDiv16,
-                    Some(64) => LocalTimestampOptions::EnabledDiv64,
-                    Some(n) => bail!(
-                        "{} is not a valid prescaler; valid prescalers are: 4, 16, 64.",
-                        n
-                    ),
-                },
-                expect_malformed,
-            }) {
+        writer: match &opt.output {
+            Some(path) => Box::new(BufWriter::new(
+                File::create(path).context("failed to create output file")?,
+            )),
+            None => Box::new(io::stdout()),
+        },
+    };
+    let mut output = if sinks.is_empty() {
+        Output::Packets(PacketOutput::new(opt.output_format, sinks.writer))
+    } else {
+        Output::Sinks(sinks)
+    };
+
+    let mut chrome = match &opt.chrome_trace {
+        Some(path) => Some(ChromeTraceWriter::new(
+            BufWriter::new(File::create(path).context("failed to create Chrome trace file")?),
+            ChromeTraceConfiguration {
+                counter_ports: opt.chrome_counter_ports.clone(),
+            },
+        )?),
+        None => None,
+    };
+
+    let mut vcd = match &opt.vcd {
+        Some(path) => Some(
+            VcdWriter::new(
+                BufWriter::new(File::create(path).context("failed to create VCD file")?),
+                VcdSignals::default(),
+            )
+            .context("failed to write VCD file")?,
+        ),
+        None => None,
+    };
+
+    let timestamps = if opt.timestamps {
+        opt.input.timestamps_configuration(opt.expect_malformed)?
+    } else {
+        None
+    };
+
+    let mut pcapng = match &opt.pcapng {
+        Some(path) => Some(PcapngWriter::new(BufWriter::new(
+            File::create(path).context("failed to create pcapng file")?,
+        ))?),
+        None => None,
+    };
+
+    let mut ctf = match (&opt.ctf, &timestamps) {
+        (Some(dir), Some(config)) => {
+            Some(CtfWriter::create(dir, config).context("failed to create CTF trace")?)
+        }
+        _ => None,
+    };
+
+    let filter = PacketFilter {
+        include_kinds: opt.include_kinds.clone(),
+        exclude_kinds: opt.exclude_kinds.clone(),
+        ports: opt.filter_ports.clone(),
+        comparators: opt.filter_comparators.clone(),
+        exceptions: opt.filter_exceptions.clone(),
+        start: opt.start.map(Duration::from_nanos),
+        end: opt.end.map(Duration::from_nanos),
+    };
+
+    match timestamps {
+        Some(config) => {
+            let mut timestamps = decoder.timestamps(config);
+            let mut offset = timestamps.bytes_consumed();
+            while let Some(packets) = timestamps.next() {
+                let end = timestamps.bytes_consumed();
+                if let Some(pcapng) = pcapng.as_mut() {
+                    let raw = timestamps.get_mut().take(end);
+                    match &packets {
+                        Ok(packets) => pcapng.write_set(&raw, &packets.timestamp),
+                        Err(e) => pcapng.write(&raw, None, &e.to_string()),
+                    }
+                    .context("failed to write pcapng file")?;
+                }
+
+                let packets = match packets.map(|packets| filter.apply(packets)) {
+                    Ok(None) => {
+                        offset = end;
+                        continue;
+                    }
+                    Ok(Some(packets)) => Ok(packets),
+                    Err(e) => Err(e),
+                };
+
+                if let (Some(chrome), Ok(packets)) = (chrome.as_mut(), packets.as_ref()) {
+                    chrome
+                        .write(packets)
+                        .context("failed to write Chrome trace")?;
+                }
+
+                if let (Some(vcd), Ok(packets)) = (vcd.as_mut(), packets.as_ref()) {
+                    vcd.write(packets).context("failed to write VCD file")?;
+                }
+
+                if let (Some(ctf), Ok(packets)) = (ctf.as_mut(), packets.as_ref()) {
+                    ctf.write(packets).context("failed to write CTF trace")?;
+                }
+
                 match packets {
+                    Err(DecoderError::Io(e)) if tcp::is_reconnect(&e) => eprintln!("{}", e),
                     Err(e) => return Err(e).context("Decoder error"),
-                    Ok(packets) => println!("{:?}", packets),
+                    Ok(packets) => output.timestamped(offset, &packets)?,
                 }
+                offset = end;
             }
         }
-        _ => {
-            for packet in decoder.singles() {
+        None => {
+            let mut singles = decoder.singles();
+            let mut offset = singles.bytes_consumed();
+            while let Some(packet) = singles.next() {
+                let end = singles.bytes_consumed();
+                if let Some(pcapng) = pcapng.as_mut() {
+                    let comment = match &packet {
+                        Ok(packet) => format!("{:?}", packet),
+                        Err(e) => e.to_string(),
+                    };
+                    pcapng
+                        .write(&singles.get_mut().take(end), None, &comment)
+                        .context("failed to write pcapng file")?;
+                }
+
                 match packet {
+                    Err(DecoderError::Io(e)) if tcp::is_reconnect(&e) => eprintln!("{}", e),
                     Err(e) => return Err(e).context("Decoder error"),
-                    Ok(packet) => println!("{:?}", packet),
+                    Ok(packet) if !filter.matches(&packet) => (),
+                    Ok(packet) => output.single(offset, &packet)?,
                 }
+                offset = end;
             }
         }
     }
 
+    output.finish()?;
+    if let Some(chrome) = chrome {
+        chrome.finish().context("failed to write Chrome trace")?;
+    }
+    if let Some(vcd) = vcd {
+        vcd.finish().context("failed to write VCD file")?;
+    }
+    if let Some(ctf) = ctf {
+        ctf.finish().context("failed to write CTF trace")?;
+    }
+    if let Some(pcapng) = pcapng {
+        pcapng.finish().context("failed to write pcapng file")?;
+    }
+
     Ok(())
 }
@@ -0,0 +1,179 @@
+use anyhow::{bail, Context, Result};
+use itm::{Timestamp, TimestampedTracePackets, TracePacket};
+use serde::Serialize;
+use std::io::Write;
+use std::str::FromStr;
+
+/// Format the decoded packets are printed in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    /// `{:?}` of each packet.
+    Debug,
+
+    /// A JSON object per packet and line.
+    JsonLines,
+
+    /// A CSV row per packet, with the packet fields as a JSON object.
+    Csv,
+}
+
+impl FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "debug" => Self::Debug,
+            "json-lines" | "jsonl" => Self::JsonLines,
+            "csv" => Self::Csv,
+            _ => bail!(
+                "{} is not a valid output format; valid formats are: debug, json-lines, csv.",
+                s
+            ),
+        })
+    }
+}
+
+/// A decoded or malformed packet and where in the trace it was found.
+#[derive(Serialize)]
+struct Record<'a> {
+    offset: u64,
+    timestamp_ns: Option<u64>,
+    timestamp_prev_ns: Option<u64>,
+    quality: Option<&'static str>,
+    packet: Option<&'a TracePacket>,
+    malformed: Option<String>,
+}
+
+/// A [`Record`] flattened into CSV columns.
+#[derive(Serialize)]
+struct CsvRecord {
+    offset: u64,
+    timestamp_ns: Option<u64>,
+    timestamp_prev_ns: Option<u64>,
+    quality: Option<&'static str>,
+    packet: Option<String>,
+    fields: Option<String>,
+    malformed: Option<String>,
+}
+
+enum Writer {
+    Debug(Box<dyn Write>),
+    JsonLines(Box<dyn Write>),
+    Csv(Box<csv::Writer<Box<dyn Write>>>),
+}
+
+/// Prints decoded packets in an [`OutputFormat`].
+pub struct PacketOutput {
+    writer: Writer,
+}
+
+impl PacketOutput {
+    pub fn new(format: OutputFormat, writer: Box<dyn Write>) -> Self {
+        Self {
+            writer: match format {
+                OutputFormat::Debug => Writer::Debug(writer),
+                OutputFormat::JsonLines => Writer::JsonLines(writer),
+                OutputFormat::Csv => Writer::Csv(Box::new(csv::Writer::from_writer(writer))),
+            },
+        }
+    }
+
+    /// Prints a packet found at byte `offset` of the trace.
+    pub fn single(&mut self, offset: u64, packet: &TracePacket) -> Result<()> {
+        if let Writer::Debug(w) = &mut self.writer {
+            writeln!(w, "{:?}", packet)?;
+            return Ok(());
+        }
+
+        self.record(Record {
+            offset,
+            timestamp_ns: None,
+            timestamp_prev_ns: None,
+            quality: None,
+            packet: Some(packet),
+            malformed: None,
+        })
+    }
+
+    /// Prints the packets of a set whose first packet was found at byte
+    /// `offset` of the trace.
+    pub fn timestamped(&mut self, offset: u64, packets: &TimestampedTracePackets) -> Result<()> {
+        if let Writer::Debug(w) = &mut self.writer {
+            writeln!(w, "{:?}", packets)?;
+            return Ok(());
+        }
+
+        let (quality, prev, curr) = match &packets.timestamp {
+            Timestamp::Sync(curr) => ("Sync", None, curr),
+            Timestamp::UnknownDelay { prev, curr } => ("UnknownDelay", Some(prev), curr),
+            Timestamp::AssocEventDelay(curr) => ("AssocEventDelay", None, curr),
+            Timestamp::UnknownAssocEventDelay { prev, curr } => {
+                ("UnknownAssocEventDelay", Some(prev), curr)
+            }
+        };
+        let record = |packet, malformed| Record {
+            offset,
+            timestamp_ns: Some(curr.as_nanos() as u64),
+            timestamp_prev_ns: prev.map(|prev| prev.as_nanos() as u64),
+            quality: Some(quality),
+            packet,
+            malformed,
+        };
+
+        for packet in packets.packets.iter() {
+            self.record(record(Some(packet), None))?;
+        }
+        for malformed in packets.malformed_packets.iter() {
+            self.record(record(None, Some(malformed.to_string())))?;
+        }
+
+        Ok(())
+    }
+
+    pub fn finish(self) -> Result<()> {
+        match self.writer {
+            Writer::Debug(mut w) | Writer::JsonLines(mut w) => w.flush()?,
+            Writer::Csv(mut w) => w.flush()?,
+        }
+
+        Ok(())
+    }
+
+    fn record(&mut self, record: Record) -> Result<()> {
+        match &mut self.writer {
+            Writer::Debug(_) => unreachable!(),
+            Writer::JsonLines(w) => {
+                serde_json::to_writer(&mut *w, &record)?;
+                writeln!(w)?;
+            }
+            Writer::Csv(w) => {
+                // NOTE(to_value) unit variants serialize to their name,
+                // others to an object with the name as the only key.
+                let (packet, fields) = match record.packet.map(serde_json::to_value).transpose()? {
+                    None => (None, None),
+                    Some(serde_json::Value::String(name)) => (Some(name), None),
+                    Some(serde_json::Value::Object(variant)) => {
+                        let (name, fields) = variant
+                            .into_iter()
+                            .next()
+                            .context("packet serialized to an empty object")?;
+                        (Some(name), Some(fields.to_string()))
+                    }
+                    Some(value) => bail!("unexpected packet serialization: {}", value),
+                };
+
+                w.serialize(CsvRecord {
+                    offset: record.offset,
+                    timestamp_ns: record.timestamp_ns,
+                    timestamp_prev_ns: record.timestamp_prev_ns,
+                    quality: record.quality,
+                    packet,
+                    fields,
+                    malformed: record.malformed,
+                })?;
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,156 @@
+use anyhow::{bail, Context, Result};
+use itm::{
+    record::{RecordingReader, RECORDING_MAGIC},
+    serial::PseudoTerminal,
+};
+use std::ffi::CString;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+use structopt::StructOpt;
+
+/// The slowest replay speed.
+const MIN_SPEED: f64 = 0.001;
+
+#[derive(StructOpt, Debug)]
+pub struct ReplayOpt {
+    #[structopt(
+        long = "--fifo",
+        parse(from_os_str),
+        help = "Write to this named pipe, created if missing, instead of to a pseudo-terminal."
+    )]
+    fifo: Option<PathBuf>,
+
+    #[structopt(
+        long = "--speed",
+        default_value = "1",
+        help = "Replay this many times faster than the capture was recorded, at least 0.001; inf replays without delays."
+    )]
+    speed: f64,
+
+    #[structopt(
+        long = "--baud-rate",
+        help = "Pace a raw capture, which has no timing of its own, as if received over a serial link of this baud rate. Raw captures are otherwise replayed without delays."
+    )]
+    baud_rate: Option<u32>,
+
+    #[structopt(
+        name = "CAPTURE",
+        parse(from_os_str),
+        help = "Raw capture or recording written with --record or --record-timestamps."
+    )]
+    capture: PathBuf,
+}
+
+/// A chunk of the capture and when to write it, relative to the start
+/// of the capture.
+struct Chunk {
+    offset: Duration,
+    data: Vec<u8>,
+}
+
+/// Splits the capture into chunks with the timing of a recording, or,
+/// for raw captures, with the timing of a serial link of `baud_rate`.
+fn chunks(capture: &[u8], baud_rate: Option<u32>) -> Result<Vec<Chunk>> {
+    if capture.starts_with(&RECORDING_MAGIC) {
+        let mut reader = RecordingReader::new(capture);
+        let mut chunks = vec![];
+        let mut first = None;
+        while let Some(chunk) = reader.next_chunk().context("failed to read recording")? {
+            let first = *first.get_or_insert(chunk.received);
+            chunks.push(Chunk {
+                offset: chunk.received.saturating_sub(first),
+                data: chunk.data,
+            });
+        }
+        return Ok(chunks);
+    }
+
+    // NOTE(10) a start bit, eight data bits, and a stop bit per byte;
+    // chunks of 10 ms worth of bytes.
+    let bytes_per_sec = baud_rate.map(|baud_rate| (baud_rate / 10).max(1) as usize);
+    let chunk_size = bytes_per_sec.map_or(4096, |rate| (rate / 100).clamp(1, 4096));
+    Ok(capture
+        .chunks(chunk_size)
+        .enumerate()
+        .map(|(i, data)| Chunk {
+            offset: bytes_per_sec.map_or(Duration::ZERO, |rate| {
+                Duration::from_secs_f64((i * chunk_size) as f64 / rate as f64)
+            }),
+            data: data.to_vec(),
+        })
+        .collect())
+}
+
+/// Writes `chunks` to `writer` at `speed` times their original pace.
+fn replay(chunks: &[Chunk], speed: f64, writer: &mut dyn Write) -> Result<()> {
+    let start = Instant::now();
+    for chunk in chunks {
+        let due = start + chunk.offset.div_f64(speed);
+        thread::sleep(due.saturating_duration_since(Instant::now()));
+        writer
+            .write_all(&chunk.data)
+            .and_then(|_| writer.flush())
+            .context("failed to replay capture")?;
+    }
+
+    Ok(())
+}
+
+/// Opens the named pipe at `path` for writing, creating it if missing.
+/// Blocks until a reader opens the pipe.
+fn open_fifo(path: &Path) -> Result<File> {
+    if !path.exists() {
+        let c_path = CString::new(path.as_os_str().as_bytes()).context("invalid FIFO path")?;
+        // SAFETY: c_path is a valid, NUL-terminated string.
+        if unsafe { libc::mkfifo(c_path.as_ptr(), 0o644) } != 0 {
+            return Err(std::io::Error::last_os_error())
+                .with_context(|| format!("failed to create {}", path.display()));
+        }
+    }
+
+    eprintln!("Waiting for a reader to open {}", path.display());
+    OpenOptions::new()
+        .write(true)
+        .open(path)
+        .with_context(|| format!("failed to open {}", path.display()))
+}
+
+pub fn run(opt: ReplayOpt) -> Result<()> {
+    // NOTE(MIN_SPEED) slower speeds overflow the delays of long
+    // captures.
+    if opt.speed.is_nan() || opt.speed < MIN_SPEED {
+        bail!(
+            "{:?} is not a valid speed; it must be at least {}.",
+            opt.speed,
+            MIN_SPEED
+        );
+    }
+
+    let capture = std::fs::read(&opt.capture)
+        .with_context(|| format!("failed to read {}", opt.capture.display()))?;
+    let chunks = chunks(&capture, opt.baud_rate)?;
+
+    match &opt.fifo {
+        Some(path) => replay(&chunks, opt.speed, &mut open_fifo(path)?),
+        None => {
+            let mut pty = PseudoTerminal::open()?;
+            eprintln!("Waiting for {} to be opened", pty.path().display());
+            pty.wait_for_open()?;
+            replay(&chunks, opt.speed, &mut pty)?;
+
+            // NOTE(close) closing the pseudo-terminal hangs up the
+            // device, which discards any bytes not yet read.
+            eprintln!(
+                "Capture replayed; waiting for {} to be closed",
+                pty.path().display()
+            );
+            pty.wait_for_close()?;
+
+            Ok(())
+        }
+    }
+}
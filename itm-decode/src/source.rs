@@ -0,0 +1,293 @@
+use anyhow::{bail, Context, Result};
+use itm::{
+    input::{self, InputFormat},
+    pcapng::RawCapture,
+    record::{RecordFormat, Tee},
+    serial::{self, SerialSession},
+    tcp::TcpSource,
+    Decoder, DecoderOptions, Follow, LocalTimestampOptions, TimestampsConfiguration,
+};
+use signal_hook::consts::TERM_SIGNALS;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read};
+use std::os::unix::fs::{FileTypeExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::time::Duration;
+use structopt::StructOpt;
+
+/// Opens the trace input at `path`: stdin if `path` is `-`, a TCP
+/// connection if `path` is `tcp://host:port`, otherwise the file at
+/// `path`. TCP connections are reestablished when lost. Serial devices
+/// are configured with `baud_rate`, if given, or with the detected baud
+/// rate if `auto_baud` is set, and restored when closed or on SIGINT or
+/// SIGTERM. On EOF of a named pipe, the pipe is reopened to wait for
+/// the next writer if `ignore_eof` is set. If `nonblocking` is set, the
+/// file is opened in non-blocking mode, so that reads from TTYs and
+/// pipes without data return instead of waiting for it.
+pub fn open(
+    path: &Path,
+    baud_rate: Option<u32>,
+    auto_baud: bool,
+    ignore_eof: bool,
+    nonblocking: bool,
+) -> Result<Box<dyn Read>> {
+    if path == Path::new("-") {
+        return Ok(Box::new(io::stdin()));
+    }
+    if let Some(addr) = path.to_str().and_then(|p| p.strip_prefix("tcp://")) {
+        return Ok(Box::new(
+            TcpSource::connect(addr, true)
+                .with_context(|| format!("failed to connect to {}", addr))?,
+        ));
+    }
+
+    let file = OpenOptions::new()
+        .read(true)
+        .custom_flags(if nonblocking { libc::O_NONBLOCK } else { 0 })
+        .open(path)
+        .with_context(|| format!("failed to open {}", path.display()))?;
+    if serial::is_tty(&file) && (auto_baud || baud_rate.is_some()) {
+        // NOTE(nonblocking) when following, the first signal stops the
+        // decoding instead, and the second exits without dropping the
+        // session. The handlers of `stop_on_signal` are registered
+        // later, and so run after the state is restored.
+        let mut session = SerialSession::new(file)?;
+        if nonblocking {
+            session.restore_on_handled_signal(TERM_SIGNALS)?;
+        } else {
+            session.restore_on_signal(TERM_SIGNALS)?;
+        }
+        if auto_baud {
+            detect_baud(path, &session)?;
+        } else if let Some(baud_rate) = baud_rate {
+            session.configure(baud_rate)?;
+        }
+        return Ok(Box::new(session));
+    }
+
+    let is_fifo = file
+        .metadata()
+        .with_context(|| format!("failed to stat {}", path.display()))?
+        .file_type()
+        .is_fifo();
+    if is_fifo && ignore_eof {
+        return Ok(Box::new(Fifo {
+            path: path.to_owned(),
+            file,
+        }));
+    }
+
+    Ok(Box::new(file))
+}
+
+/// Returns whether `path` is a regular file, rather than stdin, a TCP
+/// connection, or a device or named pipe.
+fn is_regular_file(path: &Path) -> bool {
+    path != Path::new("-")
+        && !path.to_string_lossy().starts_with("tcp://")
+        && std::fs::metadata(path).is_ok_and(|m| m.is_file())
+}
+
+/// Detects the baud rate of the serial device of `session`, at `path`,
+/// and reports it.
+fn detect_baud(path: &Path, session: &SerialSession) -> Result<()> {
+    eprintln!("Detecting the baud rate of {}", path.display());
+    let scores = serial::detect_baud(
+        session.device(),
+        &serial::COMMON_BAUD_RATES,
+        Duration::from_millis(250),
+    )?;
+    let best = &scores[0];
+    eprintln!(
+        "Detected a baud rate of {}: {} packets, {} synchronization packets, {:.0}% malformed",
+        best.baud_rate,
+        best.packets,
+        best.syncs,
+        best.malformed_rate() * 100.0
+    );
+    if best.syncs == 0 {
+        eprintln!(
+            "No synchronization packets were decoded at any baud rate, so the detected baud rate \
+             may be wrong. Enable periodic synchronization packets on the target, or set the \
+             baud rate with --itm-freq"
+        );
+    }
+
+    Ok(())
+}
+
+/// A named pipe that is reopened when its writer closes it, so that
+/// e.g. successive `socat` or `nc` sessions can write to it.
+struct Fifo {
+    path: PathBuf,
+    file: File,
+}
+
+impl Read for Fifo {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            match self.file.read(buf)? {
+                // NOTE(open) blocks until the next writer opens the pipe.
+                0 => self.file = File::open(&self.path)?,
+                n => return Ok(n),
+            }
+        }
+    }
+}
+
+/// Returns a flag that is set on SIGINT or SIGTERM. The process is
+/// terminated on the second signal.
+fn stop_on_signal() -> Result<Arc<AtomicBool>> {
+    let stop = Arc::new(AtomicBool::new(false));
+    for signal in TERM_SIGNALS {
+        // NOTE(order) the conditional shutdown must be registered first
+        // so that it only sees the flag set by a previous signal.
+        signal_hook::flag::register_conditional_shutdown(*signal, 1, stop.clone())
+            .context("failed to register signal handler")?;
+        signal_hook::flag::register(*signal, stop.clone())
+            .context("failed to register signal handler")?;
+    }
+
+    Ok(stop)
+}
+
+// Trace input options, shared by all subcommands.
+//
+// NOTE(doc) not a doc comment, which would replace the about text of
+// the commands this is flattened into.
+#[derive(StructOpt, Debug)]
+pub struct InputOpt {
+    #[structopt(long = "--ignore-eof")]
+    pub ignore_eof: bool,
+
+    #[structopt(
+        long = "--follow",
+        short = "f",
+        conflicts_with = "ignore-eof",
+        help = "Keep reading at EOF, like tail -f, until interrupted with Ctrl-C; then finish writing the outputs. Interrupt twice to exit immediately."
+    )]
+    pub follow: bool,
+
+    #[structopt(
+        long = "--auto-baud",
+        help = "Detect the baud rate of a serial device FILE by trying common baud rates and picking the one at which the trace decodes best, instead of using --itm-freq. The target must be tracing, preferably with periodic synchronization packets."
+    )]
+    pub auto_baud: bool,
+
+    #[structopt(long = "--itm-prescaler")]
+    pub prescaler: Option<u8>,
+
+    #[structopt(long = "--itm-freq", name = "freq")]
+    pub freq: Option<u32>,
+
+    #[structopt(
+        long = "--input-format",
+        help = "Format of FILE: raw, tpiu (CoreSight TPIU frames), pcapng, or recording (see --record-timestamps). Detected from the first bytes of FILE if not given and FILE is a regular file; other inputs are read as raw."
+    )]
+    pub input_format: Option<InputFormat>,
+
+    #[structopt(
+        long = "--record",
+        parse(from_os_str),
+        help = "Also write every byte read from FILE to this file, before any TPIU or pcapng unwrapping, e.g. to decode a live trace again later."
+    )]
+    pub record: Option<PathBuf>,
+
+    #[structopt(
+        long = "--record-timestamps",
+        requires = "record",
+        help = "Prefix each chunk of bytes written to the --record file with the time it was received. Such recordings can be decoded with --input-format recording."
+    )]
+    pub record_timestamps: bool,
+}
+
+impl InputOpt {
+    /// Opens `path` and returns a decoder of the trace read from it.
+    pub fn decoder(&self, path: &Path) -> Result<Decoder<Box<dyn Read>>> {
+        Ok(Decoder::new(
+            self.reader(path)?,
+            DecoderOptions {
+                ignore_eof: self.ignore_eof,
+            },
+        ))
+    }
+
+    /// Like [`decoder`](Self::decoder), but keeps the bytes read from
+    /// `path` so that the raw bytes of each packet can be taken.
+    pub fn capture(&self, path: &Path) -> Result<Decoder<RawCapture<Box<dyn Read>>>> {
+        Ok(Decoder::new(
+            RawCapture::new(self.reader(path)?),
+            DecoderOptions {
+                ignore_eof: self.ignore_eof,
+            },
+        ))
+    }
+
+    /// Opens `path` and returns a reader of the trace stream in it.
+    fn reader(&self, path: &Path) -> Result<Box<dyn Read>> {
+        // NOTE(regular) detection waits for a fixed number of bytes,
+        // which live streams may never offer.
+        let format = match self.input_format {
+            None if !is_regular_file(path) => Some(InputFormat::Raw),
+            format => format,
+        };
+
+        let mut file = open(
+            path,
+            self.freq,
+            self.auto_baud,
+            self.ignore_eof,
+            self.follow,
+        )?;
+        if self.follow {
+            file = Box::new(Follow::new(file, stop_on_signal()?));
+        }
+        if let Some(record) = &self.record {
+            let format = if self.record_timestamps {
+                RecordFormat::Timestamped
+            } else {
+                RecordFormat::Raw
+            };
+            let recording = File::create(record)
+                .with_context(|| format!("failed to create {}", record.display()))?;
+            file =
+                Box::new(Tee::new(file, recording, format).context("failed to write recording")?);
+        }
+
+        input::open(file, format).context("failed to read input")
+    }
+
+    /// Returns the timestamps configuration given by `--itm-freq` and
+    /// `--itm-prescaler`, if `--itm-freq` is given.
+    pub fn timestamps_configuration(
+        &self,
+        expect_malformed: bool,
+    ) -> Result<Option<TimestampsConfiguration>> {
+        let freq = match self.freq {
+            Some(freq) => freq,
+            None => return Ok(None),
+        };
+
+        Ok(Some(TimestampsConfiguration {
+            clock_frequency: freq,
+            lts_prescaler: lts_prescaler(self.prescaler)?,
+            expect_malformed,
+        }))
+    }
+}
+
+/// Returns the local timestamp prescaler given by `--itm-prescaler`.
+pub fn lts_prescaler(prescaler: Option<u8>) -> Result<LocalTimestampOptions> {
+    Ok(match prescaler {
+        None | Some(1) => LocalTimestampOptions::Enabled,
+        Some(4) => LocalTimestampOptions::EnabledDiv4,
+        Some(16) => LocalTimestampOptions::EnabledDiv16,
+        Some(64) => LocalTimestampOptions::EnabledDiv64,
+        Some(n) => bail!(
+            "{} is not a valid prescaler; valid prescalers are: 4, 16, 64.",
+            n
+        ),
+    })
+}
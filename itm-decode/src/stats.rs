@@ -0,0 +1,218 @@
+use crate::source::InputOpt;
+use anyhow::{Context, Result};
+use itm::{
+    tcp, Decoder, DecoderError, DecoderOptions, MalformedPacket, Timestamp, TracePacket,
+    TracePacketKind,
+};
+use std::collections::{BTreeMap, VecDeque};
+use std::path::PathBuf;
+use std::time::Duration;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+pub struct StatsOpt {
+    #[structopt(flatten)]
+    input: InputOpt,
+
+    #[structopt(
+        name = "FILE",
+        parse(from_os_str),
+        help = "Trace input file, serial device, named pipe, or tcp://host:port; - reads from stdin. Timestamp qualities and the capture duration are only reported if --itm-freq is given."
+    )]
+    file: PathBuf,
+}
+
+/// Packet count and the number of bytes the packets were decoded from.
+#[derive(Default)]
+struct Count {
+    packets: u64,
+    bytes: u64,
+}
+
+impl Count {
+    fn add(&mut self, bytes: usize) {
+        self.packets += 1;
+        self.bytes += bytes as u64;
+    }
+}
+
+#[derive(Default)]
+struct Stats {
+    bytes: u64,
+    incomplete: u64,
+    reconnects: u64,
+    kinds: BTreeMap<TracePacketKind, Count>,
+    categories: BTreeMap<&'static str, Count>,
+    ports: BTreeMap<u8, Count>,
+    malformed: BTreeMap<String, u64>,
+    qualities: BTreeMap<&'static str, u64>,
+
+    /// Offsets of the first and last timestamp.
+    span: Option<(Duration, Duration)>,
+}
+
+impl Stats {
+    /// Counts a packet, or a malformed packet, decoded from `bytes`
+    /// bytes.
+    fn push(&mut self, packet: Result<TracePacket, MalformedPacket>, bytes: usize) {
+        self.bytes += bytes as u64;
+
+        let packet = match packet {
+            Ok(packet) => packet,
+            Err(m) => {
+                *self.malformed.entry(malformed_name(&m)).or_default() += 1;
+                self.categories.entry("malformed").or_default().add(bytes);
+                return;
+            }
+        };
+
+        let kind = packet.kind();
+        self.kinds.entry(kind).or_default().add(bytes);
+        self.categories
+            .entry(category(kind))
+            .or_default()
+            .add(bytes);
+        if let TracePacket::Instrumentation { port, .. } = packet {
+            self.ports.entry(port).or_default().add(bytes);
+        }
+    }
+
+    /// Counts the trailing bytes of the capture that do not form a
+    /// complete packet.
+    fn push_incomplete(&mut self, raw: &[u8]) {
+        self.bytes += raw.len() as u64;
+        self.incomplete += raw.len() as u64;
+    }
+
+    /// Counts the reconstructed `timestamp` of a set of packets.
+    fn push_timestamp(&mut self, timestamp: &Timestamp) {
+        *self.qualities.entry(quality(timestamp)).or_default() += 1;
+
+        // NOTE(first) the first timestamp counts the time since the
+        // trace was enabled, which precedes the capture.
+        let offset = timestamp.offset();
+        self.span = Some((self.span.map_or(offset, |(first, _)| first), offset));
+    }
+
+    /// Prints the statistics, with timestamp qualities and the capture
+    /// duration if timestamps were reconstructed.
+    fn print(&self) {
+        println!("Bytes: {}", self.bytes);
+        if self.incomplete > 0 {
+            println!("Trailing bytes not decoded: {}", self.incomplete);
+        }
+
+        if self.reconnects > 0 {
+            println!("Reconnects: {}", self.reconnects);
+        }
+
+        println!("\nPackets by kind:");
+        for (kind, count) in self.kinds.iter() {
+            println!(
+                "  {:<20} {:>10} packets {:>10} bytes",
+                format!("{:?}", kind),
+                count.packets,
+                count.bytes
+            );
+        }
+
+        println!("\nPackets by category:");
+        for (category, count) in self.categories.iter() {
+            println!(
+                "  {:<20} {:>10} packets {:>10} bytes",
+                category, count.packets, count.bytes
+            );
+        }
+
+        println!("\nInstrumentation packets by stimulus port:");
+        for (port, count) in self.ports.iter() {
+            println!(
+                "  {:<20} {:>10} packets {:>10} bytes",
+                port, count.packets, count.bytes
+            );
+        }
+
+        println!("\nMalformed packets:");
+        for (name, count) in self.malformed.iter() {
+            println!("  {:<20} {:>10}", name, count);
+        }
+
+        if let Some((first, last)) = self.span {
+            println!("\nTimestamps by quality:");
+            for (quality, count) in self.qualities.iter() {
+                println!("  {:<24} {:>10}", quality, count);
+            }
+            println!("\nDuration: {:?}", last.saturating_sub(first));
+        }
+    }
+}
+
+/// Returns the packet category of `kind`. (Appendix D4, p. 782)
+fn category(kind: TracePacketKind) -> &'static str {
+    use TracePacketKind::*;
+
+    match kind {
+        Sync => "synchronization",
+        Overflow | LocalTimestamp1 | LocalTimestamp2 | GlobalTimestamp1 | GlobalTimestamp2
+        | Extension => "protocol",
+        Instrumentation => "software source",
+        EventCounterWrap | ExceptionTrace | PCSample | DataTracePC | DataTraceAddress
+        | DataTraceValue => "hardware source",
+    }
+}
+
+/// Returns the name of the quality of `timestamp`.
+fn quality(timestamp: &Timestamp) -> &'static str {
+    match timestamp {
+        Timestamp::Sync(_) => "Sync",
+        Timestamp::UnknownDelay { .. } => "UnknownDelay",
+        Timestamp::AssocEventDelay(_) => "AssocEventDelay",
+        Timestamp::UnknownAssocEventDelay { .. } => "UnknownAssocEventDelay",
+    }
+}
+
+/// Returns the name of the `MalformedPacket` variant of `m`.
+fn malformed_name(m: &MalformedPacket) -> String {
+    format!("{:?}", m)
+        .split(|c: char| !c.is_alphanumeric())
+        .next()
+        .unwrap_or_default()
+        .to_string()
+}
+
+pub fn run(opt: StatsOpt) -> Result<()> {
+    let mut singles = opt.input.capture(&opt.file)?.singles();
+    let mut stats = Stats::default();
+
+    // NOTE(timestamps) the packets are counted as single packets, the
+    // raw bytes of which are fed to a second decoder that reconstructs
+    // their timestamps, so that the input is only read once.
+    let mut timestamps = opt.input.timestamps_configuration(true)?.map(|config| {
+        Decoder::new(VecDeque::new(), DecoderOptions { ignore_eof: false }).timestamps(config)
+    });
+
+    while let Some(packet) = singles.next() {
+        let end = singles.bytes_consumed();
+        let raw = singles.get_mut().take(end);
+        match packet {
+            Ok(packet) => stats.push(Ok(packet), raw.len()),
+            Err(DecoderError::MalformedPacket(m)) => stats.push(Err(m), raw.len()),
+            Err(DecoderError::Io(e)) if tcp::is_reconnect(&e) => stats.reconnects += 1,
+            Err(DecoderError::Io(e)) => return Err(e).context("Decoder error"),
+        }
+
+        if let Some(timestamps) = timestamps.as_mut() {
+            timestamps.get_mut().extend(raw);
+            // NOTE(flatten) the feed never fails, and ends when it is
+            // empty.
+            for packets in timestamps.by_ref().flatten() {
+                stats.push_timestamp(&packets.timestamp);
+            }
+        }
+    }
+    stats.push_incomplete(&singles.get_mut().take(u64::MAX));
+
+    stats.print();
+
+    Ok(())
+}
@@ -0,0 +1,116 @@
+use anyhow::{Context, Result};
+use itm::{StimulusLine, StimulusLines, Timestamp, TracePacket};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+/// Writes the text written to the stimulus ports, line by line, to an
+/// output or to a separate file per port.
+pub struct TextOutput {
+    lines: StimulusLines,
+    ports: Vec<u8>,
+    dir: Option<PathBuf>,
+    files: BTreeMap<u8, BufWriter<File>>,
+}
+
+impl TextOutput {
+    /// Only text written to `ports` is output, unless `ports` is empty.
+    /// If `dir` is given, the text of each port is written to
+    /// `<dir>/stim<port>.txt` instead of to the output passed to
+    /// [`handle`](Self::handle).
+    pub fn new(ports: Vec<u8>, dir: Option<PathBuf>) -> Result<Self> {
+        if let Some(dir) = &dir {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("failed to create directory {}", dir.display()))?;
+        }
+
+        Ok(Self {
+            lines: StimulusLines::new(),
+            ports,
+            dir,
+            files: BTreeMap::new(),
+        })
+    }
+
+    pub fn handle(
+        &mut self,
+        packet: &TracePacket,
+        timestamp: Option<&Timestamp>,
+        out: &mut dyn Write,
+    ) -> Result<()> {
+        if let TracePacket::Instrumentation { port, .. } = packet {
+            if !self.ports.is_empty() && !self.ports.contains(port) {
+                return Ok(());
+            }
+        }
+
+        for line in self.lines.push_packet(packet) {
+            self.write(line, timestamp, out)?;
+        }
+
+        Ok(())
+    }
+
+    /// Outputs any incomplete lines and flushes all files.
+    pub fn finish(mut self, out: &mut dyn Write) -> Result<()> {
+        for line in self.lines.flush() {
+            self.write(line, None, out)?;
+        }
+        for file in self.files.values_mut() {
+            file.flush().context("failed to flush stimulus port file")?;
+        }
+
+        Ok(())
+    }
+
+    fn write(
+        &mut self,
+        line: StimulusLine,
+        timestamp: Option<&Timestamp>,
+        out: &mut dyn Write,
+    ) -> Result<()> {
+        let StimulusLine { port, text } = line;
+
+        match &self.dir {
+            Some(dir) => {
+                let file = match self.files.entry(port) {
+                    std::collections::btree_map::Entry::Occupied(e) => e.into_mut(),
+                    std::collections::btree_map::Entry::Vacant(e) => {
+                        let path = dir.join(format!("stim{}.txt", port));
+                        e.insert(BufWriter::new(File::create(&path).with_context(|| {
+                            format!("failed to create {}", path.display())
+                        })?))
+                    }
+                };
+
+                // Write the bytes as-is: the target may well be logging
+                // something other than UTF-8.
+                let bytes = match text {
+                    Ok(text) => text.into_bytes(),
+                    Err(e) => e.into_bytes(),
+                };
+                file.write_all(&bytes)
+                    .and_then(|_| file.write_all(b"\n"))
+                    .context("failed to write to stimulus port file")?;
+            }
+            None => {
+                let text = match text {
+                    Ok(text) => text,
+                    Err(e) => format!(
+                        "{} (invalid UTF-8)",
+                        String::from_utf8_lossy(&e.into_bytes())
+                    ),
+                };
+
+                match timestamp {
+                    Some(ts) => writeln!(out, "{:?} [{}] {}", ts, port, text),
+                    None => writeln!(out, "[{}] {}", port, text),
+                }
+                .context("failed to write output")?;
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,493 @@
+use crate::source::InputOpt;
+use ::tui::{
+    backend::CrosstermBackend,
+    layout::{Constraint, Direction, Layout, Rect},
+    style::{Color, Modifier, Style},
+    text::{Span, Spans},
+    widgets::{Block, Borders, List, ListItem, Paragraph},
+    Frame, Terminal,
+};
+use anyhow::{bail, Context, Result};
+use crossterm::{
+    event::{self, Event, KeyCode, KeyModifiers},
+    execute,
+    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use itm::{
+    exception_name, ExceptionAction, StimulusLine, StimulusLines, TimestampedTracePackets,
+    TracePacket, VectActive,
+};
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{self, Stdout};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+use structopt::StructOpt;
+
+/// Number of packets and lines per port kept for scrollback.
+const SCROLLBACK: usize = 10_000;
+
+/// Interval between redraws.
+const TICK: Duration = Duration::from_millis(100);
+
+#[derive(StructOpt, Debug)]
+pub struct TuiOpt {
+    #[structopt(flatten)]
+    input: InputOpt,
+
+    #[structopt(
+        name = "FILE",
+        parse(from_os_str),
+        help = "Trace input file, serial device, named pipe, or tcp://host:port; - reads from stdin. Serial devices are configured with --itm-freq as the baud rate."
+    )]
+    file: PathBuf,
+}
+
+/// Sent from the decoding thread to the UI.
+enum Message {
+    /// The trace input was opened, or failed to.
+    Opened(Result<()>),
+    Packets(TimestampedTracePackets),
+    Error(String),
+
+    /// The end of the trace was reached.
+    Done,
+}
+
+/// Overflow and malformed packet counts, in total and per second.
+#[derive(Default)]
+struct Rates {
+    overflows: u64,
+    malformed: u64,
+
+    /// Counts at the start of the current one-second window.
+    window_start: Option<(Instant, u64, u64)>,
+    overflows_per_sec: f64,
+    malformed_per_sec: f64,
+}
+
+impl Rates {
+    fn tick(&mut self, now: Instant) {
+        match self.window_start {
+            Some((start, overflows, malformed)) => {
+                let elapsed = now.duration_since(start).as_secs_f64();
+                if elapsed >= 1.0 {
+                    self.overflows_per_sec = (self.overflows - overflows) as f64 / elapsed;
+                    self.malformed_per_sec = (self.malformed - malformed) as f64 / elapsed;
+                    self.window_start = Some((now, self.overflows, self.malformed));
+                }
+            }
+            None => self.window_start = Some((now, self.overflows, self.malformed)),
+        }
+    }
+}
+
+/// Everything shown in the UI.
+#[derive(Default)]
+struct State {
+    packets: VecDeque<String>,
+
+    /// Number of packets scrolled back from the latest.
+    scroll: usize,
+
+    lines: StimulusLines,
+    ports: BTreeMap<u8, VecDeque<String>>,
+    port: Option<u8>,
+
+    /// Number of times each exception was entered.
+    exceptions: BTreeMap<String, u64>,
+
+    /// Active exceptions, innermost last.
+    nesting: Vec<VectActive>,
+
+    rates: Rates,
+    status: String,
+}
+
+impl State {
+    fn push(&mut self, packets: TimestampedTracePackets) {
+        let offset = packets.timestamp.offset();
+        for packet in packets.packets.iter() {
+            push_capped(&mut self.packets, format!("{:>14?} {:?}", offset, packet));
+            self.handle(packet);
+        }
+        for malformed in packets.malformed_packets.iter() {
+            push_capped(&mut self.packets, format!("{:>14?} {}", offset, malformed));
+        }
+        self.rates.malformed += packets.malformed_packets.len() as u64;
+    }
+
+    fn handle(&mut self, packet: &TracePacket) {
+        match packet {
+            TracePacket::Overflow => self.rates.overflows += 1,
+            TracePacket::ExceptionTrace { exception, action } => match action {
+                ExceptionAction::Entered => {
+                    *self
+                        .exceptions
+                        .entry(exception_name(exception))
+                        .or_default() += 1;
+                    self.nesting.push(*exception);
+                }
+                ExceptionAction::Exited => {
+                    if let Some(pos) = self.nesting.iter().rposition(|e| e == exception) {
+                        self.nesting.remove(pos);
+                    }
+                }
+                // NOTE(Returned) the exception returned to is active
+                // again; any exceptions nested in it have ended.
+                ExceptionAction::Returned => {
+                    match self.nesting.iter().rposition(|e| e == exception) {
+                        Some(pos) => self.nesting.truncate(pos + 1),
+                        None => self.nesting.clear(),
+                    }
+                }
+            },
+            TracePacket::Instrumentation { .. } => {
+                for StimulusLine { port, text } in self.lines.push_packet(packet) {
+                    let text = match text {
+                        Ok(text) => text,
+                        Err(e) => String::from_utf8_lossy(&e.into_bytes()).into_owned(),
+                    };
+                    push_capped(self.ports.entry(port).or_default(), text);
+                    self.port.get_or_insert(port);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Selects the next (`forward`) or previous port with text.
+    fn cycle_port(&mut self, forward: bool) {
+        let ports: Vec<u8> = self.ports.keys().copied().collect();
+        let current = self
+            .port
+            .and_then(|port| ports.iter().position(|p| *p == port));
+        self.port = match current {
+            None => ports.first().copied(),
+            Some(i) if forward => ports.get((i + 1) % ports.len()).copied(),
+            Some(i) => ports.get((i + ports.len() - 1) % ports.len()).copied(),
+        };
+    }
+
+    fn draw(&self, f: &mut Frame<CrosstermBackend<Stdout>>) {
+        let columns = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(70), Constraint::Percentage(30)].as_ref())
+            .split(f.size());
+        let left = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints(
+                [
+                    Constraint::Percentage(60),
+                    Constraint::Min(3),
+                    Constraint::Length(1),
+                ]
+                .as_ref(),
+            )
+            .split(columns[0]);
+        let right = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Min(3), Constraint::Length(4)].as_ref())
+            .split(columns[1]);
+
+        let title = if self.scroll > 0 {
+            format!("Packets (scrolled back {})", self.scroll)
+        } else {
+            "Packets".to_string()
+        };
+        f.render_widget(
+            list(tail(&self.packets, self.scroll, left[0]), &title),
+            left[0],
+        );
+
+        let (title, lines) = match self
+            .port
+            .and_then(|port| self.ports.get(&port).map(|l| (port, l)))
+        {
+            Some((port, lines)) => (
+                format!(
+                    "Port {} (of {})",
+                    port,
+                    self.ports
+                        .keys()
+                        .map(|p| p.to_string())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+                tail(lines, 0, left[1]),
+            ),
+            None => ("Stimulus ports".to_string(), vec![]),
+        };
+        f.render_widget(list(lines, &title), left[1]);
+
+        f.render_widget(
+            Paragraph::new(Span::styled(
+                format!(
+                    "q: quit  up/down/pgup/pgdn/end: scroll packets  tab: next port  {}",
+                    self.status
+                ),
+                Style::default().add_modifier(Modifier::REVERSED),
+            )),
+            left[2],
+        );
+
+        let mut exceptions: Vec<Spans> = vec![Spans::from(format!(
+            "Nesting: {}",
+            std::iter::once("ThreadMode".to_string())
+                .chain(
+                    self.nesting
+                        .iter()
+                        .filter(|e| **e != VectActive::ThreadMode)
+                        .map(exception_name)
+                )
+                .collect::<Vec<_>>()
+                .join(" > ")
+        ))];
+        exceptions.extend(
+            self.exceptions
+                .iter()
+                .map(|(name, count)| Spans::from(format!("{:<16} {:>10}", name, count))),
+        );
+        f.render_widget(
+            Paragraph::new(exceptions).block(block("Exceptions (entered)")),
+            right[0],
+        );
+
+        let rate_style = |rate: f64| {
+            if rate > 0.0 {
+                Style::default().fg(Color::Red)
+            } else {
+                Style::default()
+            }
+        };
+        let rates = vec![
+            Spans::from(Span::styled(
+                format!(
+                    "Overflow  {:>8.1}/s {:>10}",
+                    self.rates.overflows_per_sec, self.rates.overflows
+                ),
+                rate_style(self.rates.overflows_per_sec),
+            )),
+            Spans::from(Span::styled(
+                format!(
+                    "Malformed {:>8.1}/s {:>10}",
+                    self.rates.malformed_per_sec, self.rates.malformed
+                ),
+                rate_style(self.rates.malformed_per_sec),
+            )),
+        ];
+        f.render_widget(Paragraph::new(rates).block(block("Rates")), right[1]);
+    }
+}
+
+fn push_capped(lines: &mut VecDeque<String>, line: String) {
+    if lines.len() == SCROLLBACK {
+        lines.pop_front();
+    }
+    lines.push_back(line);
+}
+
+/// Returns the lines that fit in `area`, ending `scroll` lines before
+/// the last one.
+fn tail(lines: &VecDeque<String>, scroll: usize, area: Rect) -> Vec<String> {
+    let height = area.height.saturating_sub(2) as usize;
+    let end = lines.len().saturating_sub(scroll);
+    let start = end.saturating_sub(height);
+    lines.range(start..end).cloned().collect()
+}
+
+fn block(title: &str) -> Block<'_> {
+    Block::default().borders(Borders::ALL).title(title)
+}
+
+fn list(lines: Vec<String>, title: &str) -> List<'_> {
+    List::new(lines.into_iter().map(ListItem::new).collect::<Vec<_>>()).block(block(title))
+}
+
+/// Restores the terminal when dropped, also on error.
+struct TerminalGuard;
+
+impl TerminalGuard {
+    fn new() -> Result<Self> {
+        enable_raw_mode().context("failed to set up terminal")?;
+        execute!(io::stdout(), EnterAlternateScreen).context("failed to set up terminal")?;
+        Ok(Self)
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        let _ = execute!(io::stdout(), LeaveAlternateScreen);
+        let _ = disable_raw_mode();
+    }
+}
+
+/// Decodes the trace on a separate thread, so that the UI stays
+/// responsive while waiting for the input.
+fn spawn_decoder(opt: TuiOpt) -> Result<Receiver<Message>> {
+    let config = match opt.input.timestamps_configuration(true)? {
+        Some(config) => config,
+        None => bail!("tui requires --itm-freq"),
+    };
+
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let decoder = match opt.input.decoder(&opt.file) {
+            Ok(decoder) => decoder,
+            Err(e) => {
+                let _ = tx.send(Message::Opened(Err(e)));
+                return;
+            }
+        };
+        let _ = tx.send(Message::Opened(Ok(())));
+
+        for packets in decoder.timestamps(config) {
+            let message = match packets {
+                Ok(packets) => Message::Packets(packets),
+                Err(e) => Message::Error(e.to_string()),
+            };
+            if tx.send(message).is_err() {
+                return;
+            }
+        }
+        let _ = tx.send(Message::Done);
+    });
+
+    Ok(rx)
+}
+
+pub fn run(opt: TuiOpt) -> Result<()> {
+    let rx = spawn_decoder(opt)?;
+    match rx.recv() {
+        Ok(Message::Opened(result)) => result?,
+        _ => bail!("decoder thread exited unexpectedly"),
+    }
+
+    let _guard = TerminalGuard::new()?;
+    let mut terminal =
+        Terminal::new(CrosstermBackend::new(io::stdout())).context("failed to set up terminal")?;
+    let mut state = State {
+        status: "decoding".to_string(),
+        ..Default::default()
+    };
+
+    loop {
+        let deadline = Instant::now() + TICK;
+        loop {
+            match rx.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                Ok(Message::Packets(packets)) => state.push(packets),
+                Ok(Message::Error(e)) => state.status = format!("error: {}", e),
+                Ok(Message::Done) => state.status = "end of trace".to_string(),
+                Ok(Message::Opened(_)) => (),
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => {
+                    thread::sleep(deadline.saturating_duration_since(Instant::now()));
+                    break;
+                }
+            }
+        }
+        state.rates.tick(Instant::now());
+
+        terminal
+            .draw(|f| state.draw(f))
+            .context("failed to draw terminal")?;
+
+        while event::poll(Duration::ZERO).context("failed to read terminal events")? {
+            if let Event::Key(key) = event::read().context("failed to read terminal events")? {
+                let page = terminal.size().map(|s| s.height as usize / 2).unwrap_or(10);
+                match key.code {
+                    KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                    KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                        return Ok(())
+                    }
+                    KeyCode::Up => state.scroll += 1,
+                    KeyCode::Down => state.scroll = state.scroll.saturating_sub(1),
+                    KeyCode::PageUp => state.scroll += page,
+                    KeyCode::PageDown => state.scroll = state.scroll.saturating_sub(page),
+                    KeyCode::End => state.scroll = 0,
+                    KeyCode::Tab | KeyCode::Right => state.cycle_port(true),
+                    KeyCode::BackTab | KeyCode::Left => state.cycle_port(false),
+                    _ => (),
+                }
+                state.scroll = state.scroll.min(state.packets.len());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nesting() {
+        let mut state = State::default();
+        state.handle(&TracePacket::ExceptionTrace {
+            exception: VectActive::Interrupt { irqn: 0 },
+            action: ExceptionAction::Entered,
+        });
+        state.handle(&TracePacket::ExceptionTrace {
+            exception: VectActive::Interrupt { irqn: 1 },
+            action: ExceptionAction::Entered,
+        });
+        state.handle(&TracePacket::ExceptionTrace {
+            exception: VectActive::Interrupt { irqn: 2 },
+            action: ExceptionAction::Entered,
+        });
+        assert_eq!(state.nesting.len(), 3);
+
+        // NOTE(Exited) the innermost IRQ1 is removed, wherever it is.
+        state.handle(&TracePacket::ExceptionTrace {
+            exception: VectActive::Interrupt { irqn: 1 },
+            action: ExceptionAction::Exited,
+        });
+        assert_eq!(
+            state.nesting,
+            [
+                VectActive::Interrupt { irqn: 0 },
+                VectActive::Interrupt { irqn: 2 }
+            ]
+        );
+
+        state.handle(&TracePacket::ExceptionTrace {
+            exception: VectActive::Interrupt { irqn: 0 },
+            action: ExceptionAction::Returned,
+        });
+        assert_eq!(state.nesting, [VectActive::Interrupt { irqn: 0 }]);
+
+        state.handle(&TracePacket::ExceptionTrace {
+            exception: VectActive::ThreadMode,
+            action: ExceptionAction::Returned,
+        });
+        assert!(state.nesting.is_empty());
+
+        state.handle(&TracePacket::ExceptionTrace {
+            exception: VectActive::Interrupt { irqn: 0 },
+            action: ExceptionAction::Entered,
+        });
+        assert_eq!(state.exceptions["IRQ0"], 2);
+        assert_eq!(state.exceptions["IRQ2"], 1);
+    }
+
+    #[test]
+    fn rates() {
+        let start = Instant::now();
+        let mut rates = Rates::default();
+        rates.tick(start);
+
+        rates.overflows += 3;
+        rates.malformed += 1;
+        rates.tick(start + Duration::from_millis(500));
+        assert_eq!(rates.overflows_per_sec, 0.0);
+
+        rates.tick(start + Duration::from_secs(2));
+        assert_eq!(rates.overflows_per_sec, 1.5);
+        assert_eq!(rates.malformed_per_sec, 0.5);
+
+        // NOTE(window) a new window starts with the current counts.
+        rates.tick(start + Duration::from_secs(3));
+        assert_eq!(rates.overflows_per_sec, 0.0);
+        assert_eq!(rates.overflows, 3);
+    }
+}
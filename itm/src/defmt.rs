@@ -0,0 +1,304 @@
+//! [`defmt`](https://defmt.ferrous-systems.com/) log decoding over ITM
+//! stimulus ports.
+//!
+//! Instead of RTT, a target may write its `defmt` frames to an ITM
+//! stimulus port. This module collects the instrumentation payloads
+//! written to that port and feeds them to a `defmt` stream decoder,
+//! yielding formatted log lines. The `defmt` table is read from the
+//! `.defmt` section of the firmware ELF.
+//!
+//! ```ignore
+//! use itm::defmt::{self, DefmtDecoder};
+//!
+//! let table = defmt::load_table(&std::fs::read("firmware.elf")?)?;
+//! let mut decoder = DefmtDecoder::new(&table, 0);
+//! for packets in itm_decoder.timestamps(config) {
+//!     let packets = packets?;
+//!     for packet in packets.packets.iter() {
+//!         for log in decoder.push(packet, Some(&packets.timestamp)) {
+//!             println!("{}", log?.line);
+//!         }
+//!     }
+//! }
+//! ```
+
+use super::{PortDecoder, Timestamp, TracePacket};
+
+pub use defmt_decoder::Table;
+use defmt_decoder::{DecodeError, StreamDecoder};
+
+/// Possible errors on [`defmt`](self) decode.
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum DefmtError {
+    #[error("Failed to parse the defmt table: {0}")]
+    Table(String),
+    #[error("The ELF does not contain a defmt table")]
+    MissingTable,
+    #[error("A malformed defmt frame was encountered")]
+    MalformedFrame,
+    #[error("A payload was discarded: decoding stopped at a malformed defmt frame")]
+    Stalled,
+}
+
+/// Reads the `defmt` table from the firmware ELF.
+pub fn load_table(elf: &[u8]) -> Result<Table, DefmtError> {
+    Table::parse(elf)
+        .map_err(|e| DefmtError::Table(e.to_string()))?
+        .ok_or(DefmtError::MissingTable)
+}
+
+/// A decoded `defmt` log frame.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DefmtLog {
+    /// The ITM timestamp of the packet that completed the frame, if
+    /// decoding over [`Timestamps`](crate::Timestamps).
+    pub timestamp: Option<Timestamp>,
+
+    /// The formatted log message alone.
+    pub message: String,
+
+    /// The formatted log line, including the `defmt` timestamp and log
+    /// level if present.
+    pub line: String,
+}
+
+/// A `defmt` stream decoder, yielding the message and line of each
+/// frame. Abstracted so that [`DefmtDecoder`] can be tested without a
+/// firmware ELF.
+trait FrameDecoder {
+    fn received(&mut self, data: &[u8]);
+    fn decode(&mut self) -> Result<(String, String), DecodeError>;
+}
+
+impl<'t> FrameDecoder for Box<dyn StreamDecoder + 't> {
+    fn received(&mut self, data: &[u8]) {
+        StreamDecoder::received(self.as_mut(), data)
+    }
+
+    fn decode(&mut self) -> Result<(String, String), DecodeError> {
+        let frame = StreamDecoder::decode(self.as_mut())?;
+        Ok((
+            frame.display_message().to_string(),
+            frame.display(false).to_string(),
+        ))
+    }
+}
+
+/// Decodes the `defmt` frames written to a single stimulus port.
+pub struct DefmtDecoder<'t> {
+    port: u8,
+    can_recover: bool,
+    stalled: bool,
+    decoder: Box<dyn FrameDecoder + 't>,
+}
+
+impl<'t> DefmtDecoder<'t> {
+    /// Creates a decoder of the `defmt` frames written to stimulus
+    /// `port`, as described by `table`.
+    pub fn new(table: &'t Table, port: u8) -> Self {
+        Self {
+            port,
+            can_recover: table.encoding().can_recover(),
+            stalled: false,
+            decoder: Box::new(table.new_stream_decoder()),
+        }
+    }
+
+    /// The stimulus port this decoder collects payloads from.
+    pub fn port(&self) -> u8 {
+        self.port
+    }
+
+    /// Feeds the payload of `packet` to the `defmt` decoder if it was
+    /// written to the configured stimulus port, and returns the frames
+    /// this completed, if any. `timestamp` is attached to each frame.
+    ///
+    /// A [`MalformedFrame`](DefmtError::MalformedFrame) is returned for
+    /// each frame that cannot be decoded. If the encoding of the table
+    /// cannot recover from such an error, no further frames will be
+    /// decoded: a [`Stalled`](DefmtError::Stalled) error is then returned
+    /// for each payload pushed.
+    pub fn push(
+        &mut self,
+        packet: &TracePacket,
+        timestamp: Option<&Timestamp>,
+    ) -> Vec<Result<DefmtLog, DefmtError>> {
+        match packet {
+            TracePacket::Instrumentation { port, payload } if *port == self.port => {
+                self.push_payload(payload, timestamp)
+            }
+            _ => vec![],
+        }
+    }
+
+    fn push_payload(
+        &mut self,
+        payload: &[u8],
+        timestamp: Option<&Timestamp>,
+    ) -> Vec<Result<DefmtLog, DefmtError>> {
+        if self.stalled {
+            return vec![Err(DefmtError::Stalled)];
+        }
+        self.decoder.received(payload);
+
+        let mut logs = vec![];
+        loop {
+            match self.decoder.decode() {
+                Ok((message, line)) => logs.push(Ok(DefmtLog {
+                    timestamp: timestamp.cloned(),
+                    message,
+                    line,
+                })),
+                Err(DecodeError::UnexpectedEof) => break,
+                Err(DecodeError::Malformed) => {
+                    logs.push(Err(DefmtError::MalformedFrame));
+                    if !self.can_recover {
+                        self.stalled = true;
+                        break;
+                    }
+                }
+            }
+        }
+
+        logs
+    }
+}
+
+/// Decodes the payloads written to any port it is
+/// [attached](crate::PortDecoders::attach) to, not only the port given
+/// on construction.
+impl<'t> PortDecoder for DefmtDecoder<'t> {
+    type Event = Result<DefmtLog, DefmtError>;
+
+    fn decode(
+        &mut self,
+        _port: u8,
+        payload: &[u8],
+        timestamp: Option<&Timestamp>,
+    ) -> Vec<Self::Event> {
+        self.push_payload(payload, timestamp)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Frames are terminated by a zero byte; a frame starting with 0xff
+    /// is malformed.
+    struct Frames(Vec<u8>);
+
+    impl FrameDecoder for Frames {
+        fn received(&mut self, data: &[u8]) {
+            self.0.extend(data);
+        }
+
+        fn decode(&mut self) -> Result<(String, String), DecodeError> {
+            let end = self
+                .0
+                .iter()
+                .position(|b| *b == 0)
+                .ok_or(DecodeError::UnexpectedEof)?;
+            let frame: Vec<u8> = self.0.drain(..=end).collect();
+            if frame[0] == 0xff {
+                return Err(DecodeError::Malformed);
+            }
+            let message = String::from_utf8_lossy(&frame[..end]).to_string();
+            Ok((message.clone(), format!("INFO {}", message)))
+        }
+    }
+
+    #[test]
+    fn split_frame() {
+        let mut decoder = DefmtDecoder {
+            port: 1,
+            can_recover: true,
+            stalled: false,
+            decoder: Box::new(Frames(vec![])),
+        };
+        let timestamp = Timestamp::Sync(std::time::Duration::from_micros(3));
+        let he = TracePacket::Instrumentation {
+            port: 1,
+            payload: b"he".to_vec(),
+        };
+        assert!(decoder.push(&he, None).is_empty());
+
+        let ya = TracePacket::Instrumentation {
+            port: 1,
+            payload: b"y\0a".to_vec(),
+        };
+        let logs = decoder.push(&ya, Some(&timestamp));
+        assert_eq!(logs.len(), 1);
+        let log = logs.into_iter().next().unwrap().unwrap();
+        assert_eq!(log.message, "hey");
+        assert_eq!(log.line, "INFO hey");
+        assert_eq!(log.timestamp, Some(timestamp));
+
+        let end = TracePacket::Instrumentation {
+            port: 1,
+            payload: b"\0".to_vec(),
+        };
+        let logs = decoder.push(&end, None);
+        assert_eq!(logs.len(), 1);
+        assert_eq!(logs[0].as_ref().unwrap().message, "a");
+    }
+
+    #[test]
+    fn other_port() {
+        let mut decoder = DefmtDecoder {
+            port: 1,
+            can_recover: true,
+            stalled: false,
+            decoder: Box::new(Frames(vec![])),
+        };
+        let other = TracePacket::Instrumentation {
+            port: 2,
+            payload: b"x\0".to_vec(),
+        };
+        assert!(decoder.push(&other, None).is_empty());
+        assert!(decoder.push(&TracePacket::Overflow, None).is_empty());
+
+        let own = TracePacket::Instrumentation {
+            port: 1,
+            payload: b"y\0".to_vec(),
+        };
+        let logs = decoder.push(&own, None);
+        assert_eq!(logs.len(), 1);
+        assert_eq!(logs[0].as_ref().unwrap().message, "y");
+    }
+
+    #[test]
+    fn malformed_frame() {
+        let malformed = TracePacket::Instrumentation {
+            port: 1,
+            payload: b"\xff\0ok\0".to_vec(),
+        };
+
+        let mut decoder = DefmtDecoder {
+            port: 1,
+            can_recover: true,
+            stalled: false,
+            decoder: Box::new(Frames(vec![])),
+        };
+        let logs = decoder.push(&malformed, None);
+        assert!(matches!(logs[0], Err(DefmtError::MalformedFrame)));
+        assert_eq!(logs[1].as_ref().unwrap().message, "ok");
+
+        let mut decoder = DefmtDecoder {
+            port: 1,
+            can_recover: false,
+            stalled: false,
+            decoder: Box::new(Frames(vec![])),
+        };
+        let logs = decoder.push(&malformed, None);
+        assert_eq!(logs.len(), 1);
+        assert!(matches!(logs[0], Err(DefmtError::MalformedFrame)));
+        let ok = TracePacket::Instrumentation {
+            port: 1,
+            payload: b"ok\0".to_vec(),
+        };
+        let logs = decoder.push(&ok, None);
+        assert!(matches!(logs[..], [Err(DefmtError::Stalled)]));
+    }
+}
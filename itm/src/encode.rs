@@ -0,0 +1,721 @@
+//! Encoding of [`TracePacket`]s into an ITM/DWT trace stream, e.g. to
+//! synthesize test inputs without hardware.
+//!
+//! [`encode`] encodes a single packet. [`Encoder`] writes a sequence of
+//! packets to a [`Write`], each optionally with the time it was
+//! generated, and inserts the local and global timestamp packets from
+//! which [`Timestamps`](crate::Timestamps) reconstructs these times:
+//!
+//! ```
+//! use itm::{
+//!     encode::{Encoder, EncoderOptions},
+//!     Decoder, DecoderOptions, LocalTimestampOptions, TimestampsConfiguration, TracePacket,
+//! };
+//! use std::time::Duration;
+//!
+//! let mut encoder = Encoder::new(
+//!     vec![],
+//!     Some(EncoderOptions {
+//!         clock_frequency: 16_000_000,
+//!         lts_prescaler: LocalTimestampOptions::Enabled,
+//!         gts_interval: None,
+//!     }),
+//! );
+//! let packet = TracePacket::Instrumentation {
+//!     port: 1,
+//!     payload: vec![b'a'],
+//! };
+//! encoder.write(&packet, Some(Duration::from_micros(5))).unwrap();
+//! let stream = encoder.finish().unwrap();
+//!
+//! let mut timestamps = Decoder::new(stream.as_slice(), DecoderOptions { ignore_eof: false })
+//!     .timestamps(TimestampsConfiguration {
+//!         clock_frequency: 16_000_000,
+//!         lts_prescaler: LocalTimestampOptions::Enabled,
+//!         expect_malformed: false,
+//!     });
+//! let packets = timestamps.next().unwrap().unwrap();
+//! assert_eq!(packets.packets, [packet]);
+//! assert_eq!(packets.timestamp.offset(), Duration::from_micros(5));
+//! ```
+
+use super::{
+    exception_number, ExceptionAction, LocalTimestampOptions, MemoryAccessType,
+    TimestampDataRelation, TracePacket,
+};
+
+use std::io::{self, Write};
+use std::time::Duration;
+
+/// The largest value of a [`LocalTimestamp1`](TracePacket::LocalTimestamp1).
+pub(crate) const LTS1_MAX: u64 = (1 << 27) - 1;
+
+/// The number of lower-order bits of a global timestamp held by a
+/// [`GlobalTimestamp1`](TracePacket::GlobalTimestamp1).
+const GTS1_BITS: u32 = 26;
+
+/// Possible errors when encoding packets.
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum EncoderError {
+    #[error("{packet:?} cannot be encoded: {reason}")]
+    InvalidPacket {
+        packet: TracePacket,
+        reason: &'static str,
+    },
+    #[error("Packets cannot be timed without a clock frequency and an enabled local timestamp prescaler")]
+    Untimed,
+    #[error("Packet time {time:?} precedes the time of the previous packet, {prev:?}")]
+    TimeReversed { prev: Duration, time: Duration },
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+}
+
+/// A [`TracePacket`] and, optionally, when it was generated relative to
+/// trace clock start. The unit of input to the `itm-decode encode`
+/// subcommand.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TimedPacket {
+    pub packet: TracePacket,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub time: Option<Duration>,
+}
+
+/// [`Encoder`] configuration for timed packets. Must match the
+/// [`TimestampsConfiguration`](crate::TimestampsConfiguration) the
+/// stream is decoded with.
+#[derive(Debug, Clone)]
+pub struct EncoderOptions {
+    /// Frequency of the ITM timestamp clock.
+    pub clock_frequency: u32,
+
+    /// Prescaler of the ITM timestamp clock. Must not be
+    /// [`Disabled`](LocalTimestampOptions::Disabled).
+    pub lts_prescaler: LocalTimestampOptions,
+
+    /// Interval at which to insert global timestamps. Global timestamps
+    /// are otherwise only inserted when the time since the previous
+    /// timestamp exceeds the range of a local timestamp.
+    pub gts_interval: Option<Duration>,
+}
+
+impl EncoderOptions {
+    fn prescale(&self) -> Option<u64> {
+        match self.lts_prescaler {
+            LocalTimestampOptions::Disabled => None,
+            LocalTimestampOptions::Enabled => Some(1),
+            LocalTimestampOptions::EnabledDiv4 => Some(4),
+            LocalTimestampOptions::EnabledDiv16 => Some(16),
+            LocalTimestampOptions::EnabledDiv64 => Some(64),
+        }
+    }
+
+    /// Returns the number of timestamp clock ticks in `time`, rounded
+    /// up, and divided by `prescale`.
+    fn ticks(&self, time: Duration, prescale: u64) -> u64 {
+        let period = 1e9 * prescale as f64 / self.clock_frequency as f64;
+        // NOTE(1e-6) as to not add a tick to times that are whole
+        // multiples of the period, save for floating point errors.
+        (time.as_nanos() as f64 / period - 1e-6).ceil().max(0.0) as u64
+    }
+}
+
+/// Writes [`TracePacket`]s to a [`Write`] as an ITM/DWT trace stream.
+///
+/// Packets written with a time are grouped by the tick of the
+/// (prescaled) timestamp clock they fall on. Each group is followed by
+/// a synchronous local timestamp holding the ticks since the previous
+/// group. If the time since the previous group exceeds the range of a
+/// local timestamp, or [`gts_interval`](EncoderOptions::gts_interval)
+/// has elapsed, a full global timestamp of the group precedes the local
+/// timestamp instead. A packet written without a time belongs to the
+/// group of the previous packet, or to the first group if it has none.
+/// Timestamp packets written to the encoder are written as-is.
+pub struct Encoder<W>
+where
+    W: Write,
+{
+    writer: W,
+    options: Option<EncoderOptions>,
+
+    /// Tick of the group of the packets written since the last
+    /// timestamp, if any of them was timed.
+    group: Option<(u64, Duration)>,
+
+    /// Whether packets have been written since the last timestamp.
+    pending: bool,
+
+    /// Tick of the previous timestamp.
+    prev_tick: u64,
+
+    /// Time of the previous group.
+    prev_time: Duration,
+
+    /// Time of the previous global timestamp.
+    prev_gts: Option<Duration>,
+
+    /// Upper-order bits of the previous global timestamp.
+    prev_gts2: Option<u64>,
+}
+
+impl<W> Encoder<W>
+where
+    W: Write,
+{
+    /// Writes packets to `writer`. Packets can only be timed if
+    /// `options` are given.
+    pub fn new(writer: W, options: Option<EncoderOptions>) -> Self {
+        Self {
+            writer,
+            options,
+            group: None,
+            pending: false,
+            prev_tick: 0,
+            prev_time: Duration::ZERO,
+            prev_gts: None,
+            prev_gts2: None,
+        }
+    }
+
+    /// Returns a reference to the underlying [`Write`](Write).
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    /// Writes `packet`, generated at `time` relative to trace clock
+    /// start. Times must not decrease.
+    pub fn write(
+        &mut self,
+        packet: &TracePacket,
+        time: Option<Duration>,
+    ) -> Result<(), EncoderError> {
+        let bytes = encode(packet)?;
+
+        if let Some(time) = time {
+            let options = self.options.as_ref().ok_or(EncoderError::Untimed)?;
+            let prescale = options.prescale().ok_or(EncoderError::Untimed)?;
+            if time < self.prev_time {
+                return Err(EncoderError::TimeReversed {
+                    prev: self.prev_time,
+                    time,
+                });
+            }
+
+            let tick = options.ticks(time, prescale);
+            match self.group {
+                Some((group, _)) if group == tick => (),
+                Some(_) => {
+                    self.timestamp()?;
+                    self.group = Some((tick, time));
+                }
+                None => self.group = Some((tick, time)),
+            }
+            self.prev_time = time;
+        }
+
+        self.writer.write_all(&bytes)?;
+        self.pending = true;
+
+        Ok(())
+    }
+
+    /// Writes the timestamp of the last group of packets, if any, and
+    /// returns the underlying [`Write`](Write).
+    pub fn finish(mut self) -> Result<W, EncoderError> {
+        self.timestamp()?;
+        self.writer.flush()?;
+
+        Ok(self.writer)
+    }
+
+    /// Writes the timestamp of the current group of packets.
+    fn timestamp(&mut self) -> Result<(), EncoderError> {
+        let (tick, time) = match self.group.take() {
+            Some(group) if self.pending => group,
+            _ => return Ok(()),
+        };
+        let options = self.options.as_ref().unwrap();
+        let delta = tick - self.prev_tick;
+
+        let gts_due = options
+            .gts_interval
+            .is_some_and(|interval| self.prev_gts.map_or(true, |prev| time - prev >= interval));
+        let lts = if delta > LTS1_MAX || gts_due {
+            // NOTE(prescale) global timestamps count the undivided
+            // clock.
+            let gts = tick * options.prescale().unwrap();
+
+            // NOTE(wrap) as on hardware, the upper-order bits are only
+            // written when they have changed.
+            let upper = gts >> GTS1_BITS;
+            let wrap = self.prev_gts2 != Some(upper);
+            self.writer
+                .write_all(&encode(&TracePacket::GlobalTimestamp1 {
+                    ts: gts & ((1 << GTS1_BITS) - 1),
+                    wrap,
+                    clkch: false,
+                })?)?;
+            if wrap {
+                self.writer
+                    .write_all(&encode(&TracePacket::GlobalTimestamp2 { ts: upper })?)?;
+                self.prev_gts2 = Some(upper);
+            }
+            self.prev_gts = Some(time);
+
+            0
+        } else {
+            delta
+        };
+
+        let lts = match lts {
+            1..=6 => TracePacket::LocalTimestamp2 { ts: lts as u8 },
+            _ => TracePacket::LocalTimestamp1 {
+                ts: lts as u32,
+                data_relation: TimestampDataRelation::Sync,
+            },
+        };
+        self.writer.write_all(&encode(&lts)?)?;
+        self.prev_tick = tick;
+        self.pending = false;
+
+        Ok(())
+    }
+}
+
+/// Encodes `value` into `len` bytes of seven bits each, all but the
+/// last with the continuation bit set. (c.f. e.g. Appendix D4, Fig.
+/// D4-4)
+fn encode_continued(value: u64, len: usize) -> Vec<u8> {
+    (0..len)
+        .map(|i| {
+            let b = ((value >> (7 * i)) & 0x7F) as u8;
+            if i + 1 < len {
+                b | 0x80
+            } else {
+                b
+            }
+        })
+        .collect()
+}
+
+/// Encodes the header of a source packet with a payload of `size`
+/// bytes. See (Appendix D4.2.8, Table D4-4).
+fn source_header(header: u8, size: usize) -> Option<u8> {
+    Some(
+        header
+            | match size {
+                1 => 0b01,
+                2 => 0b10,
+                4 => 0b11,
+                _ => return None,
+            },
+    )
+}
+
+/// Encodes `packet` into the bytes of the trace stream it is decoded
+/// from. Fails if a field of the packet is out of the range the
+/// protocol can represent.
+pub fn encode(packet: &TracePacket) -> Result<Vec<u8>, EncoderError> {
+    let invalid = |reason| {
+        Err(EncoderError::InvalidPacket {
+            packet: packet.clone(),
+            reason,
+        })
+    };
+
+    let hardware = |disc_id: u8, payload: Vec<u8>| {
+        // NOTE(unwrap) payload sizes are checked by the callers
+        let mut bytes = vec![source_header((disc_id << 3) | 0b100, payload.len()).unwrap()];
+        bytes.extend(payload);
+        bytes
+    };
+
+    Ok(match packet {
+        TracePacket::Sync => vec![0, 0, 0, 0, 0, 0x80],
+        TracePacket::Overflow => vec![0b0111_0000],
+        TracePacket::LocalTimestamp1 { ts, data_relation } => {
+            if *ts as u64 > LTS1_MAX {
+                return invalid("timestamp exceeds 27 bits");
+            }
+            let tc = match data_relation {
+                TimestampDataRelation::Sync => 0b00,
+                TimestampDataRelation::UnknownDelay => 0b01,
+                TimestampDataRelation::AssocEventDelay => 0b10,
+                TimestampDataRelation::UnknownAssocEventDelay => 0b11,
+            };
+            let bits = 64 - (*ts as u64).leading_zeros() as usize;
+            let mut bytes = vec![0b1100_0000 | (tc << 4)];
+            bytes.extend(encode_continued(*ts as u64, ((bits + 6) / 7).max(1)));
+            bytes
+        }
+        TracePacket::LocalTimestamp2 { ts } => {
+            if !(1..=6).contains(ts) {
+                return invalid("timestamp must be 1-6");
+            }
+            vec![ts << 4]
+        }
+        TracePacket::GlobalTimestamp1 { ts, wrap, clkch } => {
+            if *ts >= 1 << GTS1_BITS {
+                return invalid("timestamp exceeds 26 bits");
+            }
+            let mut bytes = vec![0b1001_0100];
+            bytes.extend(encode_continued(*ts, 4));
+            bytes[4] |= ((*wrap as u8) << 6) | ((*clkch as u8) << 5);
+            bytes
+        }
+        TracePacket::GlobalTimestamp2 { ts } => {
+            // NOTE(len) bits[47:26] or bits[63:26]
+            let len = match ts {
+                ts if *ts < 1 << 22 => 4,
+                ts if *ts < 1 << 38 => 6,
+                _ => return invalid("timestamp exceeds 38 bits"),
+            };
+            let mut bytes = vec![0b1011_0100];
+            bytes.extend(encode_continued(*ts, len));
+            bytes
+        }
+        TracePacket::Extension { page } => {
+            if *page > 0b111 {
+                return invalid("page exceeds 3 bits");
+            }
+            vec![(page << 4) | 0b1000]
+        }
+        TracePacket::Instrumentation { port, payload } => {
+            if *port > 31 {
+                return invalid("port exceeds 31");
+            }
+            let header = match source_header(port << 3, payload.len()) {
+                Some(header) => header,
+                None => return invalid("payload must be 1, 2, or 4 bytes"),
+            };
+            let mut bytes = vec![header];
+            bytes.extend(payload);
+            bytes
+        }
+        TracePacket::EventCounterWrap {
+            cyc,
+            fold,
+            lsu,
+            sleep,
+            exc,
+            cpi,
+        } => hardware(
+            0,
+            vec![
+                (*cyc as u8) << 5
+                    | (*fold as u8) << 4
+                    | (*lsu as u8) << 3
+                    | (*sleep as u8) << 2
+                    | (*exc as u8) << 1
+                    | *cpi as u8,
+            ],
+        ),
+        TracePacket::ExceptionTrace { exception, action } => {
+            let number = exception_number(exception);
+            if number > 0x1FF {
+                return invalid("exception number exceeds 9 bits");
+            }
+            let function = match action {
+                ExceptionAction::Entered => 0b01,
+                ExceptionAction::Exited => 0b10,
+                ExceptionAction::Returned => 0b11,
+            };
+            hardware(1, vec![number as u8, (function << 4) | (number >> 8) as u8])
+        }
+        TracePacket::PCSample { pc: None } => hardware(2, vec![0]),
+        TracePacket::PCSample { pc: Some(pc) } => hardware(2, pc.to_le_bytes().to_vec()),
+        TracePacket::DataTracePC { comparator, pc } => {
+            if *comparator > 3 {
+                return invalid("comparator exceeds 3");
+            }
+            hardware(0b01_000 | (comparator << 1), pc.to_le_bytes().to_vec())
+        }
+        TracePacket::DataTraceAddress { comparator, data } => {
+            if *comparator > 3 {
+                return invalid("comparator exceeds 3");
+            }
+            if data.len() != 2 {
+                return invalid("address must be 2 bytes");
+            }
+            hardware(0b01_001 | (comparator << 1), data.clone())
+        }
+        TracePacket::DataTraceValue {
+            comparator,
+            access_type,
+            value,
+        } => {
+            if *comparator > 3 {
+                return invalid("comparator exceeds 3");
+            }
+            if source_header(0, value.len()).is_none() {
+                return invalid("value must be 1, 2, or 4 bytes");
+            }
+            let d = match access_type {
+                MemoryAccessType::Read => 0,
+                MemoryAccessType::Write => 1,
+            };
+            hardware(0b10_000 | (comparator << 1) | d, value.clone())
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        Decoder, DecoderOptions, ExceptionAction, TimestampsConfiguration, TracePacketKind,
+        VectActive,
+    };
+
+    const FREQ: u32 = 16_000_000;
+
+    #[test]
+    fn roundtrip_singles() {
+        let packets = [
+            TracePacket::Sync,
+            TracePacket::Overflow,
+            TracePacket::LocalTimestamp1 {
+                ts: 0x7FF_FFFF,
+                data_relation: TimestampDataRelation::UnknownAssocEventDelay,
+            },
+            TracePacket::LocalTimestamp1 {
+                ts: 0,
+                data_relation: TimestampDataRelation::AssocEventDelay,
+            },
+            TracePacket::LocalTimestamp2 { ts: 6 },
+            TracePacket::GlobalTimestamp1 {
+                ts: 0x3FF_FFFF,
+                wrap: true,
+                clkch: false,
+            },
+            TracePacket::GlobalTimestamp1 {
+                ts: 42,
+                wrap: false,
+                clkch: true,
+            },
+            TracePacket::GlobalTimestamp2 { ts: 0x3F_FFFF },
+            TracePacket::GlobalTimestamp2 { ts: 0x40_0000 },
+            TracePacket::Extension { page: 7 },
+            TracePacket::Instrumentation {
+                port: 31,
+                payload: vec![1, 2, 3, 4],
+            },
+            TracePacket::Instrumentation {
+                port: 0,
+                payload: vec![0],
+            },
+            TracePacket::EventCounterWrap {
+                cyc: true,
+                fold: false,
+                lsu: true,
+                sleep: false,
+                exc: true,
+                cpi: false,
+            },
+            TracePacket::ExceptionTrace {
+                exception: VectActive::Interrupt { irqn: 300 },
+                action: ExceptionAction::Returned,
+            },
+            TracePacket::ExceptionTrace {
+                exception: VectActive::ThreadMode,
+                action: ExceptionAction::Entered,
+            },
+            TracePacket::PCSample { pc: None },
+            TracePacket::PCSample {
+                pc: Some(0x0800_1234),
+            },
+            TracePacket::DataTracePC {
+                comparator: 3,
+                pc: 0x2000_0000,
+            },
+            TracePacket::DataTraceAddress {
+                comparator: 1,
+                data: vec![0x34, 0x12],
+            },
+            TracePacket::DataTraceValue {
+                comparator: 2,
+                access_type: MemoryAccessType::Write,
+                value: vec![0xAA, 0xBB],
+            },
+        ];
+
+        let mut encoder = Encoder::new(vec![], None);
+        for packet in packets.iter() {
+            encoder.write(packet, None).unwrap();
+        }
+        let stream = encoder.finish().unwrap();
+
+        let decoded: Vec<TracePacket> =
+            Decoder::new(stream.as_slice(), DecoderOptions { ignore_eof: false })
+                .singles()
+                .map(Result::unwrap)
+                .collect();
+        assert_eq!(decoded, packets);
+    }
+
+    #[test]
+    fn invalid_packets() {
+        for packet in [
+            TracePacket::LocalTimestamp2 { ts: 0 },
+            TracePacket::Instrumentation {
+                port: 32,
+                payload: vec![0],
+            },
+            TracePacket::Instrumentation {
+                port: 1,
+                payload: vec![0; 3],
+            },
+            TracePacket::DataTraceAddress {
+                comparator: 0,
+                data: vec![0; 4],
+            },
+        ] {
+            assert!(matches!(
+                encode(&packet),
+                Err(EncoderError::InvalidPacket { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn roundtrip_timed() {
+        let port = |payload: u8| TracePacket::Instrumentation {
+            port: 0,
+            payload: vec![payload],
+        };
+        let timed = [
+            (port(0), Some(Duration::from_micros(0))),
+            (port(1), Some(Duration::from_micros(1))),
+            (port(2), None),
+            // NOTE(250 ns) a single tick of the prescaled clock: LTS2
+            (port(3), Some(Duration::from_nanos(1250))),
+            // beyond the range of LTS1: GTS
+            (port(4), Some(Duration::from_secs(60))),
+            (
+                port(5),
+                Some(Duration::from_secs(60) + Duration::from_micros(100)),
+            ),
+            // NOTE(100_664_296) lower-order bits of the GTS wrapped
+            (port(6), Some(Duration::from_micros(100_664_296))),
+        ];
+
+        let mut encoder = Encoder::new(
+            vec![],
+            Some(EncoderOptions {
+                clock_frequency: FREQ,
+                lts_prescaler: LocalTimestampOptions::EnabledDiv4,
+                gts_interval: None,
+            }),
+        );
+        for (packet, time) in timed.iter() {
+            encoder.write(packet, *time).unwrap();
+        }
+        let stream = encoder.finish().unwrap();
+        assert!(
+            Decoder::new(stream.as_slice(), DecoderOptions { ignore_eof: false })
+                .singles()
+                .any(|packet| matches!(packet, Ok(TracePacket::GlobalTimestamp2 { .. })))
+        );
+
+        let timestamped: Vec<_> =
+            Decoder::new(stream.as_slice(), DecoderOptions { ignore_eof: false })
+                .timestamps(TimestampsConfiguration {
+                    clock_frequency: FREQ,
+                    lts_prescaler: LocalTimestampOptions::EnabledDiv4,
+                    expect_malformed: false,
+                })
+                .map(|packets| {
+                    let packets = packets.unwrap();
+                    assert!(matches!(packets.timestamp, crate::Timestamp::Sync(_)));
+                    (packets.timestamp.offset(), packets.packets)
+                })
+                .collect();
+        assert_eq!(
+            timestamped,
+            [
+                (Duration::from_micros(0), vec![port(0)]),
+                (Duration::from_micros(1), vec![port(1), port(2)]),
+                (Duration::from_nanos(1250), vec![port(3)]),
+                (Duration::from_secs(60), vec![port(4)]),
+                (
+                    Duration::from_secs(60) + Duration::from_micros(100),
+                    vec![port(5)]
+                ),
+                (Duration::from_micros(100_664_296), vec![port(6)]),
+            ]
+        );
+    }
+
+    #[test]
+    fn gts_interval() {
+        let packet = TracePacket::PCSample { pc: None };
+        let mut encoder = Encoder::new(
+            vec![],
+            Some(EncoderOptions {
+                clock_frequency: FREQ,
+                lts_prescaler: LocalTimestampOptions::EnabledDiv4,
+                gts_interval: Some(Duration::from_millis(1)),
+            }),
+        );
+        for us in (0..5000).step_by(500) {
+            encoder
+                .write(&packet, Some(Duration::from_micros(us)))
+                .unwrap();
+        }
+        let stream = encoder.finish().unwrap();
+
+        let packets: Vec<_> = Decoder::new(stream.as_slice(), DecoderOptions { ignore_eof: false })
+            .singles()
+            .map(Result::unwrap)
+            .collect();
+        let count = |kind| packets.iter().filter(|p| p.kind() == kind).count();
+        assert_eq!(count(TracePacketKind::GlobalTimestamp1), 5);
+
+        // NOTE(GTS2) the upper-order bits never change.
+        assert_eq!(count(TracePacketKind::GlobalTimestamp2), 1);
+        assert!(packets
+            .iter()
+            .any(|p| matches!(p, TracePacket::GlobalTimestamp1 { wrap: false, .. })));
+
+        let times: Vec<_> = Decoder::new(stream.as_slice(), DecoderOptions { ignore_eof: false })
+            .timestamps(TimestampsConfiguration {
+                clock_frequency: FREQ,
+                lts_prescaler: LocalTimestampOptions::EnabledDiv4,
+                expect_malformed: false,
+            })
+            .map(|packets| packets.unwrap().timestamp.offset())
+            .collect();
+        assert_eq!(
+            times,
+            (0..5000)
+                .step_by(500)
+                .map(Duration::from_micros)
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn time_reversed() {
+        let packet = TracePacket::Overflow;
+        let mut encoder = Encoder::new(
+            vec![],
+            Some(EncoderOptions {
+                clock_frequency: FREQ,
+                lts_prescaler: LocalTimestampOptions::EnabledDiv4,
+                gts_interval: None,
+            }),
+        );
+        encoder
+            .write(&packet, Some(Duration::from_micros(2)))
+            .unwrap();
+        assert!(matches!(
+            encoder.write(&packet, Some(Duration::from_micros(1))),
+            Err(EncoderError::TimeReversed { .. })
+        ));
+        assert!(matches!(
+            Encoder::new(vec![], None).write(&packet, Some(Duration::ZERO)),
+            Err(EncoderError::Untimed)
+        ));
+    }
+}
@@ -0,0 +1,23 @@
+//! Identification of exceptions in exception trace packets.
+
+use super::VectActive;
+
+/// Returns a human-readable name of `exception`, e.g. `SysTick` or
+/// `IRQ3`.
+pub fn exception_name(exception: &VectActive) -> String {
+    match exception {
+        VectActive::ThreadMode => "ThreadMode".to_string(),
+        VectActive::Exception(ex) => format!("{:?}", ex),
+        VectActive::Interrupt { irqn } => format!("IRQ{}", irqn),
+    }
+}
+
+/// Returns the exception number of `exception`, as stored in the
+/// `VECTACTIVE` field of the `ICSR`.
+pub(crate) fn exception_number(exception: &VectActive) -> u16 {
+    match exception {
+        VectActive::ThreadMode => 0,
+        VectActive::Exception(ex) => (ex.irqn() as i16 + 16) as u16,
+        VectActive::Interrupt { irqn } => irqn + 16,
+    }
+}
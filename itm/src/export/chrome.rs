@@ -0,0 +1,269 @@
+//! Export to the [Chrome Trace Event
+//! format](https://docs.google.com/document/d/1CvAClvFfyA5R-PhYUmn5OOQtYMH4h6I0nSsKchNAySU),
+//! which can be opened in [Perfetto](https://ui.perfetto.dev) and
+//! `chrome://tracing`.
+//!
+//! - [`ExceptionTrace`](TracePacket::ExceptionTrace) packets are
+//! exported as duration events, beginning on exception entry and ending
+//! on exception exit;
+//! - [`Instrumentation`](TracePacket::Instrumentation) packets are
+//! exported as instant events, or as counter events for the ports in
+//! [`counter_ports`](ChromeTraceConfiguration::counter_ports);
+//! - [`PCSample`](TracePacket::PCSample) packets are exported as sample
+//! events;
+//! - DWT data trace packets, event counter wraps, overflows, and
+//! malformed packets are exported as instant events.
+
+use super::le_value;
+use crate::{exception_name, ExceptionAction, Timestamp, TimestampedTracePackets, TracePacket};
+
+use std::io::{self, Write};
+
+const TID_EXCEPTIONS: u8 = 0;
+const TID_STIMULUS: u8 = 1;
+const TID_DWT: u8 = 2;
+const TID_PC: u8 = 3;
+
+/// [`ChromeTraceWriter`] configuration.
+#[derive(Debug, Clone, Default)]
+pub struct ChromeTraceConfiguration {
+    /// Stimulus ports whose payloads are exported as counter values
+    /// instead of instant events. The payload is interpreted as a
+    /// little-endian unsigned integer.
+    pub counter_ports: Vec<u8>,
+}
+
+/// Writes [`TimestampedTracePackets`] as a Chrome Trace Event JSON
+/// document.
+pub struct ChromeTraceWriter<W>
+where
+    W: Write,
+{
+    writer: W,
+    config: ChromeTraceConfiguration,
+    first: bool,
+}
+
+impl<W> ChromeTraceWriter<W>
+where
+    W: Write,
+{
+    /// Creates a new writer and writes the document header to `writer`.
+    pub fn new(writer: W, config: ChromeTraceConfiguration) -> io::Result<Self> {
+        let mut this = Self {
+            writer,
+            config,
+            first: true,
+        };
+
+        this.writer
+            .write_all(b"{\"displayTimeUnit\":\"ns\",\"traceEvents\":[\n")?;
+        for (tid, name) in [
+            (TID_EXCEPTIONS, "Exceptions"),
+            (TID_STIMULUS, "Stimulus ports"),
+            (TID_DWT, "DWT"),
+            (TID_PC, "PC samples"),
+        ] {
+            this.event(&format!(
+                "\"name\":\"thread_name\",\"ph\":\"M\",\"pid\":0,\"tid\":{},\"args\":{{\"name\":\"{}\"}}",
+                tid, name
+            ))?;
+        }
+
+        Ok(this)
+    }
+
+    /// Writes the events of all `packets`.
+    pub fn write(&mut self, packets: &TimestampedTracePackets) -> io::Result<()> {
+        let ts = timestamp_us(&packets.timestamp);
+
+        for packet in packets.packets.iter() {
+            self.write_packet(packet, &ts)?;
+        }
+        for malformed in packets.malformed_packets.iter() {
+            self.event(&format!(
+                "\"name\":\"Malformed packet\",\"ph\":\"i\",\"s\":\"g\",\"ts\":{},\"pid\":0,\"tid\":0,\"args\":{{\"error\":\"{}\"}}",
+                ts,
+                escape(&malformed.to_string())
+            ))?;
+        }
+
+        Ok(())
+    }
+
+    /// Terminates the JSON document and returns the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.writer.write_all(b"\n]}\n")?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    fn write_packet(&mut self, packet: &TracePacket, ts: &str) -> io::Result<()> {
+        let instant = |tid: u8, name: &str, args: String| {
+            format!(
+                "\"name\":\"{}\",\"ph\":\"i\",\"s\":\"t\",\"ts\":{},\"pid\":0,\"tid\":{},\"args\":{{{}}}",
+                name, ts, tid, args
+            )
+        };
+
+        let event = match packet {
+            TracePacket::ExceptionTrace { exception, action } => {
+                let name = exception_name(exception);
+                match action {
+                    ExceptionAction::Entered | ExceptionAction::Exited => format!(
+                        "\"name\":\"{}\",\"cat\":\"exception\",\"ph\":\"{}\",\"ts\":{},\"pid\":0,\"tid\":{}",
+                        name,
+                        if *action == ExceptionAction::Entered {
+                            "B"
+                        } else {
+                            "E"
+                        },
+                        ts,
+                        TID_EXCEPTIONS
+                    ),
+                    ExceptionAction::Returned => instant(
+                        TID_EXCEPTIONS,
+                        &format!("{} returned", name),
+                        String::new(),
+                    ),
+                }
+            }
+            TracePacket::Instrumentation { port, payload }
+                if self.config.counter_ports.contains(port) =>
+            {
+                format!(
+                    "\"name\":\"Port {}\",\"ph\":\"C\",\"ts\":{},\"pid\":0,\"args\":{{\"value\":{}}}",
+                    port,
+                    ts,
+                    le_value(payload)
+                )
+            }
+            TracePacket::Instrumentation { port, payload } => instant(
+                TID_STIMULUS,
+                &format!("Port {}", port),
+                format!("\"payload\":{:?}", payload),
+            ),
+            TracePacket::PCSample { pc } => format!(
+                "\"name\":\"PC sample\",\"ph\":\"P\",\"ts\":{},\"pid\":0,\"tid\":{},\"args\":{{\"pc\":{}}}",
+                ts,
+                TID_PC,
+                match pc {
+                    Some(pc) => format!("\"{:#010x}\"", pc),
+                    None => "\"sleeping\"".to_string(),
+                }
+            ),
+            TracePacket::DataTracePC { comparator, pc } => instant(
+                TID_DWT,
+                &format!("Comparator {}", comparator),
+                format!("\"pc\":\"{:#010x}\"", pc),
+            ),
+            TracePacket::DataTraceAddress { comparator, data } => instant(
+                TID_DWT,
+                &format!("Comparator {}", comparator),
+                format!("\"address\":{:?}", data),
+            ),
+            TracePacket::DataTraceValue {
+                comparator,
+                access_type,
+                value,
+            } => instant(
+                TID_DWT,
+                &format!("Comparator {}", comparator),
+                format!("\"access\":\"{:?}\",\"value\":{:?}", access_type, value),
+            ),
+            TracePacket::EventCounterWrap { .. } => instant(
+                TID_DWT,
+                "Event counter wrap",
+                format!("\"counters\":\"{}\"", escape(&format!("{:?}", packet))),
+            ),
+            TracePacket::Overflow => format!(
+                "\"name\":\"Overflow\",\"ph\":\"i\",\"s\":\"g\",\"ts\":{},\"pid\":0,\"tid\":0",
+                ts
+            ),
+            // Synchronization, timestamp, and extension packets do not
+            // describe target events.
+            _ => return Ok(()),
+        };
+
+        self.event(&event)
+    }
+
+    fn event(&mut self, fields: &str) -> io::Result<()> {
+        if !self.first {
+            self.writer.write_all(b",\n")?;
+        }
+        self.first = false;
+
+        write!(self.writer, "{{{}}}", fields)
+    }
+}
+
+/// Formats the offset of `timestamp` in microseconds, the time unit of
+/// the trace event format.
+fn timestamp_us(timestamp: &Timestamp) -> String {
+    let nanos = timestamp.offset().as_nanos();
+    format!("{}.{:03}", nanos / 1000, nanos % 1000)
+}
+
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::VectActive;
+    use std::time::Duration;
+
+    #[test]
+    fn events() {
+        let mut writer = ChromeTraceWriter::new(
+            vec![],
+            ChromeTraceConfiguration {
+                counter_ports: vec![1],
+            },
+        )
+        .unwrap();
+        writer
+            .write(&TimestampedTracePackets {
+                timestamp: Timestamp::Sync(Duration::from_nanos(1500)),
+                packets: vec![
+                    TracePacket::ExceptionTrace {
+                        exception: VectActive::Interrupt { irqn: 3 },
+                        action: ExceptionAction::Entered,
+                    },
+                    TracePacket::Instrumentation {
+                        port: 1,
+                        payload: vec![0x01, 0x01],
+                    },
+                ],
+                malformed_packets: vec![],
+                consumed_packets: 3,
+            })
+            .unwrap();
+        let json = String::from_utf8(writer.finish().unwrap()).unwrap();
+
+        assert!(json.starts_with("{\"displayTimeUnit\":\"ns\",\"traceEvents\":[\n"));
+        assert!(json.ends_with("\n]}\n"));
+        assert!(json.contains(
+            "{\"name\":\"IRQ3\",\"cat\":\"exception\",\"ph\":\"B\",\"ts\":1.500,\"pid\":0,\"tid\":0}"
+        ));
+        assert!(json.contains(
+            "{\"name\":\"Port 1\",\"ph\":\"C\",\"ts\":1.500,\"pid\":0,\"args\":{\"value\":257}}"
+        ));
+    }
+
+    #[test]
+    fn escaping() {
+        assert_eq!(escape("a\"b\\c\n"), "a\\\"b\\\\c\\u000a");
+    }
+}
@@ -0,0 +1,502 @@
+//! Export to the [Common Trace Format](https://diamon.org/ctf/v1.8.3/)
+//! (CTF) 1.8, which can be opened in Trace Compass and babeltrace.
+//!
+//! A CTF trace is a directory containing a `metadata` file, which
+//! describes the trace in the Trace Stream Description Language (TSDL),
+//! and one or more binary stream files. [`CtfWriter::create`] writes
+//! both: a single stream, `stream_0`, in which each [`TracePacket`]
+//! variant and each malformed packet is an event class of its own.
+//!
+//! The trace clock is the ITM timestamp clock as described by the
+//! [`TimestampsConfiguration`]; event timestamps are the reconstructed
+//! [offsets](crate::Timestamp::offset) in ticks of this clock.
+
+use crate::{
+    exception_number, ExceptionAction, LocalTimestampOptions, MemoryAccessType,
+    TimestampedTracePackets, TimestampsConfiguration, TracePacket,
+};
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+const CTF_MAGIC: u32 = 0xC1FC_1FC1;
+
+/// Upper bound of the size of a CTF packet, after which a new packet
+/// is started.
+const MAX_PACKET_SIZE: usize = 64 * 1024;
+
+/// Size of the packet header and context, in bytes.
+const PACKET_PREAMBLE_SIZE: usize = 4 + 4 + 8 + 8;
+
+mod event_id {
+    pub const SYNC: u32 = 0;
+    pub const OVERFLOW: u32 = 1;
+    pub const EXTENSION: u32 = 2;
+    pub const INSTRUMENTATION: u32 = 3;
+    pub const EVENT_COUNTER_WRAP: u32 = 4;
+    pub const EXCEPTION_TRACE: u32 = 5;
+    pub const PC_SAMPLE: u32 = 6;
+    pub const DATA_TRACE_PC: u32 = 7;
+    pub const DATA_TRACE_ADDRESS: u32 = 8;
+    pub const DATA_TRACE_VALUE: u32 = 9;
+    pub const MALFORMED: u32 = 10;
+}
+
+/// Returns the frequency of the trace clock after prescaling.
+fn clock_frequency(config: &TimestampsConfiguration) -> u64 {
+    config.clock_frequency as u64
+        / match config.lts_prescaler {
+            LocalTimestampOptions::Disabled | LocalTimestampOptions::Enabled => 1,
+            LocalTimestampOptions::EnabledDiv4 => 4,
+            LocalTimestampOptions::EnabledDiv16 => 16,
+            LocalTimestampOptions::EnabledDiv64 => 64,
+        }
+}
+
+/// Returns the TSDL metadata of a trace written by [`CtfWriter`].
+pub fn metadata(config: &TimestampsConfiguration) -> String {
+    format!(
+        r#"/* CTF 1.8 */
+
+typealias integer {{ size = 8; align = 8; signed = false; }} := uint8_t;
+typealias integer {{ size = 16; align = 8; signed = false; }} := uint16_t;
+typealias integer {{ size = 32; align = 8; signed = false; }} := uint32_t;
+typealias integer {{ size = 64; align = 8; signed = false; }} := uint64_t;
+
+trace {{
+    major = 1;
+    minor = 8;
+    byte_order = le;
+    packet.header := struct {{
+        uint32_t magic;
+        uint32_t stream_id;
+    }};
+}};
+
+env {{
+    domain = "itm";
+    tracer_name = "itm";
+    tracer_version = "{version}";
+}};
+
+clock {{
+    name = itm_clock;
+    description = "ITM timestamp clock ({prescaler:?})";
+    freq = {freq};
+    offset = 0;
+}};
+
+typealias integer {{
+    size = 64; align = 8; signed = false;
+    map = clock.itm_clock.value;
+}} := itm_clock_t;
+
+stream {{
+    id = 0;
+    packet.context := struct {{
+        uint64_t content_size;
+        uint64_t packet_size;
+    }};
+    event.header := struct {{
+        uint32_t id;
+        itm_clock_t timestamp;
+    }};
+}};
+
+event {{
+    name = "sync";
+    id = {SYNC};
+    stream_id = 0;
+    fields := struct {{ }};
+}};
+
+event {{
+    name = "overflow";
+    id = {OVERFLOW};
+    stream_id = 0;
+    fields := struct {{ }};
+}};
+
+event {{
+    name = "extension";
+    id = {EXTENSION};
+    stream_id = 0;
+    fields := struct {{
+        uint8_t page;
+    }};
+}};
+
+event {{
+    name = "instrumentation";
+    id = {INSTRUMENTATION};
+    stream_id = 0;
+    fields := struct {{
+        uint8_t port;
+        uint8_t size;
+        uint8_t payload[size];
+    }};
+}};
+
+event {{
+    name = "event_counter_wrap";
+    id = {EVENT_COUNTER_WRAP};
+    stream_id = 0;
+    fields := struct {{
+        uint8_t cyc;
+        uint8_t fold;
+        uint8_t lsu;
+        uint8_t sleep;
+        uint8_t exc;
+        uint8_t cpi;
+    }};
+}};
+
+event {{
+    name = "exception_trace";
+    id = {EXCEPTION_TRACE};
+    stream_id = 0;
+    fields := struct {{
+        uint16_t exception;
+        enum : uint8_t {{ entered = 1, exited = 2, returned = 3 }} action;
+    }};
+}};
+
+event {{
+    name = "pc_sample";
+    id = {PC_SAMPLE};
+    stream_id = 0;
+    fields := struct {{
+        uint8_t sleeping;
+        uint32_t pc;
+    }};
+}};
+
+event {{
+    name = "data_trace_pc";
+    id = {DATA_TRACE_PC};
+    stream_id = 0;
+    fields := struct {{
+        uint8_t comparator;
+        uint32_t pc;
+    }};
+}};
+
+event {{
+    name = "data_trace_address";
+    id = {DATA_TRACE_ADDRESS};
+    stream_id = 0;
+    fields := struct {{
+        uint8_t comparator;
+        uint8_t size;
+        uint8_t data[size];
+    }};
+}};
+
+event {{
+    name = "data_trace_value";
+    id = {DATA_TRACE_VALUE};
+    stream_id = 0;
+    fields := struct {{
+        uint8_t comparator;
+        enum : uint8_t {{ read = 0, write = 1 }} access;
+        uint8_t size;
+        uint8_t value[size];
+    }};
+}};
+
+event {{
+    name = "malformed";
+    id = {MALFORMED};
+    stream_id = 0;
+    fields := struct {{
+        string error;
+    }};
+}};
+"#,
+        version = env!("CARGO_PKG_VERSION"),
+        prescaler = config.lts_prescaler,
+        freq = clock_frequency(config),
+        SYNC = event_id::SYNC,
+        OVERFLOW = event_id::OVERFLOW,
+        EXTENSION = event_id::EXTENSION,
+        INSTRUMENTATION = event_id::INSTRUMENTATION,
+        EVENT_COUNTER_WRAP = event_id::EVENT_COUNTER_WRAP,
+        EXCEPTION_TRACE = event_id::EXCEPTION_TRACE,
+        PC_SAMPLE = event_id::PC_SAMPLE,
+        DATA_TRACE_PC = event_id::DATA_TRACE_PC,
+        DATA_TRACE_ADDRESS = event_id::DATA_TRACE_ADDRESS,
+        DATA_TRACE_VALUE = event_id::DATA_TRACE_VALUE,
+        MALFORMED = event_id::MALFORMED,
+    )
+}
+
+/// Writes [`TimestampedTracePackets`] as the binary stream of a CTF
+/// trace described by [`metadata`].
+pub struct CtfWriter<W>
+where
+    W: Write,
+{
+    writer: W,
+    freq: u64,
+
+    /// The ticks of the last event written.
+    ticks: u64,
+
+    /// Events of the current CTF packet.
+    events: Vec<u8>,
+}
+
+impl CtfWriter<BufWriter<File>> {
+    /// Creates the CTF trace directory `dir`, writes the `metadata`
+    /// file, and returns a writer of the `stream_0` file.
+    pub fn create<P>(dir: P, config: &TimestampsConfiguration) -> io::Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)?;
+        fs::write(dir.join("metadata"), metadata(config))?;
+
+        Ok(Self::new(
+            BufWriter::new(File::create(dir.join("stream_0"))?),
+            config,
+        ))
+    }
+}
+
+impl<W> CtfWriter<W>
+where
+    W: Write,
+{
+    /// Creates a writer of the binary stream to `writer`. The metadata
+    /// must be written separately.
+    pub fn new(writer: W, config: &TimestampsConfiguration) -> Self {
+        Self {
+            writer,
+            freq: clock_frequency(config),
+            ticks: 0,
+            events: vec![],
+        }
+    }
+
+    /// Writes an event for each of the `packets`.
+    pub fn write(&mut self, packets: &TimestampedTracePackets) -> io::Result<()> {
+        // NOTE(max) CTF timestamps must not decrease, but a global
+        // timestamp may set the trace clock back.
+        let ticks =
+            (packets.timestamp.offset().as_nanos() * self.freq as u128 / 1_000_000_000) as u64;
+        let ticks = ticks.max(self.ticks);
+        self.ticks = ticks;
+
+        for packet in packets.packets.iter() {
+            self.event(packet, ticks);
+        }
+        for malformed in packets.malformed_packets.iter() {
+            self.header(event_id::MALFORMED, ticks);
+            self.events.extend(malformed.to_string().bytes());
+            self.events.push(0);
+        }
+
+        if self.events.len() >= MAX_PACKET_SIZE {
+            self.flush_packet()?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes the last CTF packet and returns the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.flush_packet()?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    fn header(&mut self, id: u32, ticks: u64) {
+        self.events.extend(id.to_le_bytes());
+        self.events.extend(ticks.to_le_bytes());
+    }
+
+    fn event(&mut self, packet: &TracePacket, ticks: u64) {
+        match packet {
+            TracePacket::Sync => self.header(event_id::SYNC, ticks),
+            TracePacket::Overflow => self.header(event_id::OVERFLOW, ticks),
+            TracePacket::Extension { page } => {
+                self.header(event_id::EXTENSION, ticks);
+                self.events.push(*page);
+            }
+            TracePacket::Instrumentation { port, payload } => {
+                self.header(event_id::INSTRUMENTATION, ticks);
+                self.events.push(*port);
+                self.sequence(payload);
+            }
+            TracePacket::EventCounterWrap {
+                cyc,
+                fold,
+                lsu,
+                sleep,
+                exc,
+                cpi,
+            } => {
+                self.header(event_id::EVENT_COUNTER_WRAP, ticks);
+                self.events.extend(
+                    [cyc, fold, lsu, sleep, exc, cpi]
+                        .iter()
+                        .map(|wrapped| **wrapped as u8),
+                );
+            }
+            TracePacket::ExceptionTrace { exception, action } => {
+                self.header(event_id::EXCEPTION_TRACE, ticks);
+                self.events
+                    .extend(exception_number(exception).to_le_bytes());
+                self.events.push(match action {
+                    ExceptionAction::Entered => 1,
+                    ExceptionAction::Exited => 2,
+                    ExceptionAction::Returned => 3,
+                });
+            }
+            TracePacket::PCSample { pc } => {
+                self.header(event_id::PC_SAMPLE, ticks);
+                self.events.push(pc.is_none() as u8);
+                self.events.extend(pc.unwrap_or(0).to_le_bytes());
+            }
+            TracePacket::DataTracePC { comparator, pc } => {
+                self.header(event_id::DATA_TRACE_PC, ticks);
+                self.events.push(*comparator);
+                self.events.extend(pc.to_le_bytes());
+            }
+            TracePacket::DataTraceAddress { comparator, data } => {
+                self.header(event_id::DATA_TRACE_ADDRESS, ticks);
+                self.events.push(*comparator);
+                self.sequence(data);
+            }
+            TracePacket::DataTraceValue {
+                comparator,
+                access_type,
+                value,
+            } => {
+                self.header(event_id::DATA_TRACE_VALUE, ticks);
+                self.events.push(*comparator);
+                self.events.push(match access_type {
+                    MemoryAccessType::Read => 0,
+                    MemoryAccessType::Write => 1,
+                });
+                self.sequence(value);
+            }
+            // Timestamps are described by the event headers instead.
+            TracePacket::LocalTimestamp1 { .. }
+            | TracePacket::LocalTimestamp2 { .. }
+            | TracePacket::GlobalTimestamp1 { .. }
+            | TracePacket::GlobalTimestamp2 { .. } => (),
+        }
+    }
+
+    /// Appends a sequence of at most 255 bytes, prefixed by its length.
+    fn sequence(&mut self, bytes: &[u8]) {
+        let bytes = &bytes[..bytes.len().min(u8::MAX as usize)];
+        self.events.push(bytes.len() as u8);
+        self.events.extend(bytes);
+    }
+
+    fn flush_packet(&mut self) -> io::Result<()> {
+        if self.events.is_empty() {
+            return Ok(());
+        }
+
+        let size = ((PACKET_PREAMBLE_SIZE + self.events.len()) * 8) as u64;
+        self.writer.write_all(&CTF_MAGIC.to_le_bytes())?;
+        self.writer.write_all(&0u32.to_le_bytes())?; // stream_id
+        self.writer.write_all(&size.to_le_bytes())?; // content_size
+        self.writer.write_all(&size.to_le_bytes())?; // packet_size
+        self.writer.write_all(&self.events)?;
+        self.events.clear();
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Timestamp, VectActive};
+    use std::time::Duration;
+
+    #[test]
+    fn clock() {
+        let metadata = metadata(&TimestampsConfiguration {
+            clock_frequency: 16_000_000,
+            lts_prescaler: LocalTimestampOptions::EnabledDiv4,
+            expect_malformed: false,
+        });
+        assert!(metadata.contains("    freq = 4000000;\n"));
+    }
+
+    #[test]
+    fn stream() {
+        let mut ctf = CtfWriter::new(
+            vec![],
+            &TimestampsConfiguration {
+                clock_frequency: 16_000_000,
+                lts_prescaler: LocalTimestampOptions::EnabledDiv4,
+                expect_malformed: false,
+            },
+        );
+        ctf.write(&TimestampedTracePackets {
+            timestamp: Timestamp::Sync(Duration::from_micros(1)),
+            packets: vec![TracePacket::ExceptionTrace {
+                exception: VectActive::Interrupt { irqn: 3 },
+                action: ExceptionAction::Exited,
+            }],
+            malformed_packets: vec![],
+            consumed_packets: 3,
+        })
+        .unwrap();
+        let stream = ctf.finish().unwrap();
+
+        #[rustfmt::skip]
+        assert_eq!(
+            stream,
+            [
+                // packet header
+                0xC1, 0x1F, 0xFC, 0xC1,
+                0, 0, 0, 0,
+                // packet context: (24 + 15) * 8 bits
+                0x38, 0x01, 0, 0, 0, 0, 0, 0,
+                0x38, 0x01, 0, 0, 0, 0, 0, 0,
+                // event header: exception_trace at 4 ticks
+                5, 0, 0, 0,
+                4, 0, 0, 0, 0, 0, 0, 0,
+                // IRQ3, exited
+                19, 0,
+                2,
+            ]
+        );
+    }
+
+    #[test]
+    fn monotonic() {
+        let mut ctf = CtfWriter::new(
+            vec![],
+            &TimestampsConfiguration {
+                clock_frequency: 16_000_000,
+                lts_prescaler: LocalTimestampOptions::EnabledDiv4,
+                expect_malformed: false,
+            },
+        );
+        for nanos in [1000, 500] {
+            ctf.write(&TimestampedTracePackets {
+                timestamp: Timestamp::Sync(Duration::from_nanos(nanos)),
+                packets: vec![TracePacket::Overflow],
+                malformed_packets: vec![],
+                consumed_packets: 1,
+            })
+            .unwrap();
+        }
+        let stream = ctf.finish().unwrap();
+
+        // NOTE(max) the second event is not placed before the first.
+        let events = &stream[PACKET_PREAMBLE_SIZE..];
+        assert_eq!(events[4..12], 4u64.to_le_bytes());
+        assert_eq!(events[16..24], 4u64.to_le_bytes());
+    }
+}
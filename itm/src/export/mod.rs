@@ -0,0 +1,21 @@
+//! Exporters of decoded trace streams to formats understood by other
+//! tools.
+//!
+//! - [`chrome`]: Chrome Trace Event JSON, for Perfetto and
+//! `chrome://tracing`;
+//! - [`ctf`]: Common Trace Format, for Trace Compass and babeltrace;
+//! - [`vcd`]: Value Change Dump, for GTKWave and other waveform
+//! viewers.
+
+pub mod chrome;
+pub mod ctf;
+pub mod vcd;
+
+/// Interprets `bytes` as a little-endian unsigned integer, as written
+/// to a stimulus port or read by a DWT comparator.
+fn le_value(bytes: &[u8]) -> u64 {
+    bytes
+        .iter()
+        .rev()
+        .fold(0u64, |value, b| (value << 8) | *b as u64)
+}
@@ -0,0 +1,312 @@
+//! Export to the Value Change Dump (VCD) format of IEEE 1364, which
+//! can be opened in e.g. [GTKWave](https://gtkwave.sourceforge.net/).
+//!
+//! The following signals are exported:
+//!
+//! - a wire per exception, set while the exception is active: from
+//! exception entry until exception exit;
+//! - a 32-bit bus per stimulus port, carrying the last value written to
+//! the port;
+//! - a 32-bit bus per DWT comparator, carrying the last
+//! [`DataTraceValue`](TracePacket::DataTraceValue) of the comparator.
+//!
+//! The time base is the [offset](crate::Timestamp::offset) of the
+//! reconstructed timestamps in nanoseconds. Because the VCD header must
+//! declare all signals up front, the exported signals are given on
+//! construction by [`VcdSignals`]; value changes are then written as
+//! they are recorded.
+
+use super::le_value;
+use crate::{exception_name, ExceptionAction, TimestampedTracePackets, TracePacket, VectActive};
+
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+#[derive(Clone, Copy, PartialEq)]
+enum Scope {
+    Exceptions,
+    Stimulus,
+    Dwt,
+}
+
+impl Scope {
+    fn name(&self) -> &'static str {
+        match self {
+            Scope::Exceptions => "exceptions",
+            Scope::Stimulus => "stimulus",
+            Scope::Dwt => "dwt",
+        }
+    }
+}
+
+struct Signal {
+    scope: Scope,
+    name: String,
+    width: u8,
+}
+
+/// The signals declared by a [`VcdWriter`]. Activity of other
+/// exceptions, stimulus ports, and DWT comparators is not exported.
+#[derive(Debug, Clone)]
+pub struct VcdSignals {
+    pub exceptions: Vec<VectActive>,
+    pub ports: Vec<u8>,
+    pub comparators: Vec<u8>,
+}
+
+impl Default for VcdSignals {
+    /// Thread mode, the system exceptions, the 240 interrupts of
+    /// ARMv7-M, the 32 stimulus ports, and 4 DWT comparators.
+    fn default() -> Self {
+        Self {
+            exceptions: (0..16)
+                .filter_map(VectActive::from)
+                .chain((0..240).map(|irqn| VectActive::Interrupt { irqn }))
+                .collect(),
+            ports: (0..32).collect(),
+            comparators: (0..4).collect(),
+        }
+    }
+}
+
+/// Writes [`TimestampedTracePackets`] as a VCD file.
+pub struct VcdWriter<W>
+where
+    W: Write,
+{
+    writer: W,
+    signals: Vec<Signal>,
+    signal_ids: BTreeMap<String, usize>,
+
+    /// The time of the last value change written.
+    time: u64,
+}
+
+impl<W> VcdWriter<W>
+where
+    W: Write,
+{
+    /// Writes the header declaring `signals`, all initially zero.
+    pub fn new(writer: W, signals: VcdSignals) -> io::Result<Self> {
+        let mut vcd = Self {
+            writer,
+            signals: vec![],
+            signal_ids: BTreeMap::new(),
+            time: 0,
+        };
+        for exception in signals.exceptions.iter() {
+            vcd.declare(Scope::Exceptions, exception_name(exception), 1);
+        }
+        for port in signals.ports {
+            vcd.declare(Scope::Stimulus, format!("port{}", port), 32);
+        }
+        for comparator in signals.comparators {
+            vcd.declare(Scope::Dwt, format!("comparator{}", comparator), 32);
+        }
+        vcd.header()?;
+
+        Ok(vcd)
+    }
+
+    /// Writes the value changes of all `packets`.
+    pub fn write(&mut self, packets: &TimestampedTracePackets) -> io::Result<()> {
+        // NOTE(max) VCD time must not decrease, but a global timestamp
+        // may set the trace clock back.
+        let time = (packets.timestamp.offset().as_nanos() as u64).max(self.time);
+
+        for packet in packets.packets.iter() {
+            let (scope, name, value) = match packet {
+                TracePacket::ExceptionTrace { exception, action } => (
+                    Scope::Exceptions,
+                    exception_name(exception),
+                    match action {
+                        ExceptionAction::Entered | ExceptionAction::Returned => 1,
+                        ExceptionAction::Exited => 0,
+                    },
+                ),
+                TracePacket::Instrumentation { port, payload } => {
+                    (Scope::Stimulus, format!("port{}", port), le_value(payload))
+                }
+                TracePacket::DataTraceValue {
+                    comparator, value, ..
+                } => (
+                    Scope::Dwt,
+                    format!("comparator{}", comparator),
+                    le_value(value),
+                ),
+                _ => continue,
+            };
+
+            let signal = match self.signal_ids.get(&name) {
+                Some(signal) if self.signals[*signal].scope == scope => *signal,
+                _ => continue,
+            };
+            if time != self.time {
+                writeln!(self.writer, "#{}", time)?;
+                self.time = time;
+            }
+            self.value(signal, value)?;
+        }
+
+        Ok(())
+    }
+
+    /// Flushes the VCD file and returns the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    fn header(&mut self) -> io::Result<()> {
+        writeln!(
+            self.writer,
+            "$version itm {} $end",
+            env!("CARGO_PKG_VERSION")
+        )?;
+        writeln!(self.writer, "$timescale 1ns $end")?;
+        writeln!(self.writer, "$scope module itm $end")?;
+        for scope in [Scope::Exceptions, Scope::Stimulus, Scope::Dwt] {
+            writeln!(self.writer, "$scope module {} $end", scope.name())?;
+            for (i, signal) in self.signals.iter().enumerate() {
+                if signal.scope == scope {
+                    writeln!(
+                        self.writer,
+                        "$var wire {} {} {} $end",
+                        signal.width,
+                        identifier(i),
+                        signal.name
+                    )?;
+                }
+            }
+            writeln!(self.writer, "$upscope $end")?;
+        }
+        writeln!(self.writer, "$upscope $end")?;
+        writeln!(self.writer, "$enddefinitions $end")?;
+
+        writeln!(self.writer, "#0")?;
+        writeln!(self.writer, "$dumpvars")?;
+        for i in 0..self.signals.len() {
+            self.value(i, 0)?;
+        }
+        writeln!(self.writer, "$end")
+    }
+
+    fn declare(&mut self, scope: Scope, name: String, width: u8) {
+        if self.signal_ids.contains_key(&name) {
+            return;
+        }
+
+        self.signals.push(Signal {
+            scope,
+            name: name.clone(),
+            width,
+        });
+        self.signal_ids.insert(name, self.signals.len() - 1);
+    }
+
+    fn value(&mut self, signal: usize, value: u64) -> io::Result<()> {
+        if self.signals[signal].width == 1 {
+            writeln!(self.writer, "{}{}", value, identifier(signal))
+        } else {
+            writeln!(self.writer, "b{:b} {}", value, identifier(signal))
+        }
+    }
+}
+
+/// Returns the VCD identifier code of the `n`th signal, built from the
+/// printable ASCII characters `!` to `~`.
+fn identifier(mut n: usize) -> String {
+    let mut id = String::new();
+    loop {
+        id.push((b'!' + (n % 94) as u8) as char);
+        n /= 94;
+        if n == 0 {
+            break;
+        }
+        n -= 1;
+    }
+    id
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Timestamp, VectActive};
+    use std::time::Duration;
+
+    #[test]
+    fn identifiers() {
+        assert_eq!(identifier(0), "!");
+        assert_eq!(identifier(93), "~");
+        assert_eq!(identifier(94), "!!");
+        assert_eq!(identifier(95), "\"!");
+    }
+
+    #[test]
+    fn value_changes() {
+        let mut vcd = VcdWriter::new(
+            vec![],
+            VcdSignals {
+                exceptions: vec![VectActive::Interrupt { irqn: 3 }],
+                ports: vec![2],
+                comparators: vec![],
+            },
+        )
+        .unwrap();
+        vcd.write(&TimestampedTracePackets {
+            timestamp: Timestamp::Sync(Duration::from_nanos(100)),
+            packets: vec![
+                TracePacket::ExceptionTrace {
+                    exception: VectActive::Interrupt { irqn: 3 },
+                    action: ExceptionAction::Entered,
+                },
+                TracePacket::Instrumentation {
+                    port: 2,
+                    payload: vec![0x05, 0x01],
+                },
+            ],
+            malformed_packets: vec![],
+            consumed_packets: 0,
+        })
+        .unwrap();
+        // NOTE(header) written up front; the value changes as recorded
+        assert!(vcd.writer.ends_with(b"#100\n1!\nb100000101 \"\n"));
+
+        vcd.write(&TimestampedTracePackets {
+            timestamp: Timestamp::Sync(Duration::from_nanos(250)),
+            packets: vec![
+                TracePacket::ExceptionTrace {
+                    exception: VectActive::Interrupt { irqn: 3 },
+                    action: ExceptionAction::Exited,
+                },
+                // NOTE(port5) not declared
+                TracePacket::Instrumentation {
+                    port: 5,
+                    payload: vec![1],
+                },
+            ],
+            malformed_packets: vec![],
+            consumed_packets: 0,
+        })
+        .unwrap();
+        // NOTE(200) time is not set back
+        vcd.write(&TimestampedTracePackets {
+            timestamp: Timestamp::Sync(Duration::from_nanos(200)),
+            packets: vec![TracePacket::Instrumentation {
+                port: 2,
+                payload: vec![1],
+            }],
+            malformed_packets: vec![],
+            consumed_packets: 0,
+        })
+        .unwrap();
+        let vcd = String::from_utf8(vcd.finish().unwrap()).unwrap();
+
+        assert!(vcd.contains("$var wire 1 ! IRQ3 $end\n"));
+        assert!(vcd.contains("$var wire 32 \" port2 $end\n"));
+        assert!(!vcd.contains("port5"));
+        assert!(vcd.ends_with(
+            "#0\n$dumpvars\n0!\nb0 \"\n$end\n#100\n1!\nb100000101 \"\n#250\n0!\nb1 \"\n"
+        ));
+    }
+}
@@ -0,0 +1,228 @@
+//! Packet filtering over [`Singles`] and [`Timestamps`].
+
+use super::{
+    exception_number, DecoderError, Singles, Timestamp, TimestampedTracePackets, Timestamps,
+    TracePacket, TracePacketKind,
+};
+
+use std::io::Read;
+use std::time::Duration;
+
+/// Selects the packets yielded by a [`Filter`]. Empty sets and unset
+/// bounds select all packets.
+#[derive(Debug, Clone, Default)]
+pub struct PacketFilter {
+    /// Only packets of these kinds are selected.
+    pub include_kinds: Vec<TracePacketKind>,
+
+    /// Packets of these kinds are not selected.
+    pub exclude_kinds: Vec<TracePacketKind>,
+
+    /// Only [`Instrumentation`](TracePacket::Instrumentation) packets
+    /// written to these stimulus ports are selected.
+    pub ports: Vec<u8>,
+
+    /// Only DWT data trace packets generated by these comparators are
+    /// selected.
+    pub comparators: Vec<u8>,
+
+    /// Only [`ExceptionTrace`](TracePacket::ExceptionTrace) packets of
+    /// these exception numbers are selected, e.g. 15 for `SysTick` and
+    /// 16 for IRQ 0.
+    pub exceptions: Vec<u16>,
+
+    /// Only packets with a timestamp [offset](Timestamp::offset) of at
+    /// least this are selected. Ignored by [`Singles`].
+    pub start: Option<Duration>,
+
+    /// Only packets with a timestamp [offset](Timestamp::offset) of at
+    /// most this are selected. Ignored by [`Singles`].
+    pub end: Option<Duration>,
+}
+
+impl PacketFilter {
+    /// Returns whether `packet` is selected, disregarding timestamps.
+    pub fn matches(&self, packet: &TracePacket) -> bool {
+        let kind = packet.kind();
+        if !self.include_kinds.is_empty() && !self.include_kinds.contains(&kind) {
+            return false;
+        }
+        if self.exclude_kinds.contains(&kind) {
+            return false;
+        }
+
+        match packet {
+            TracePacket::Instrumentation { port, .. } => {
+                self.ports.is_empty() || self.ports.contains(port)
+            }
+            TracePacket::DataTracePC { comparator, .. }
+            | TracePacket::DataTraceAddress { comparator, .. }
+            | TracePacket::DataTraceValue { comparator, .. } => {
+                self.comparators.is_empty() || self.comparators.contains(comparator)
+            }
+            TracePacket::ExceptionTrace { exception, .. } => {
+                self.exceptions.is_empty() || self.exceptions.contains(&exception_number(exception))
+            }
+            _ => true,
+        }
+    }
+
+    /// Returns whether `timestamp` is within the
+    /// [`start`](Self::start) and [`end`](Self::end) bounds.
+    pub fn matches_timestamp(&self, timestamp: &Timestamp) -> bool {
+        let offset = timestamp.offset();
+        self.start.map_or(true, |start| offset >= start)
+            && self.end.map_or(true, |end| offset <= end)
+    }
+
+    /// Removes the packets of `packets` that are not selected. Returns
+    /// `None` if no packets remain or if the timestamp is out of bounds.
+    /// Malformed packets are kept unless the timestamp is out of bounds.
+    pub fn apply(&self, mut packets: TimestampedTracePackets) -> Option<TimestampedTracePackets> {
+        if !self.matches_timestamp(&packets.timestamp) {
+            return None;
+        }
+
+        packets.packets.retain(|packet| self.matches(packet));
+        if packets.packets.is_empty() && packets.malformed_packets.is_empty() {
+            return None;
+        }
+
+        Some(packets)
+    }
+}
+
+/// Iterator adapter that only yields the packets selected by a
+/// [`PacketFilter`]. Errors are always yielded. Created by
+/// [`Singles::filtered`] and [`Timestamps::filtered`].
+pub struct Filter<I> {
+    iter: I,
+    filter: PacketFilter,
+}
+
+impl<I> Filter<I> {
+    /// Returns a reference to the wrapped iterator.
+    pub fn get_ref(&self) -> &I {
+        &self.iter
+    }
+
+    /// Returns a mutable reference to the wrapped iterator.
+    pub fn get_mut(&mut self) -> &mut I {
+        &mut self.iter
+    }
+}
+
+impl<R> Singles<R>
+where
+    R: Read,
+{
+    /// Returns an iterator that only yields the packets selected by
+    /// `filter`.
+    pub fn filtered(self, filter: PacketFilter) -> Filter<Self> {
+        Filter { iter: self, filter }
+    }
+}
+
+impl<R> Timestamps<R>
+where
+    R: Read,
+{
+    /// Returns an iterator that only yields the packets selected by
+    /// `filter`. Sets in which no packets are selected are skipped.
+    pub fn filtered(self, filter: PacketFilter) -> Filter<Self> {
+        Filter { iter: self, filter }
+    }
+}
+
+impl<R> Iterator for Filter<Singles<R>>
+where
+    R: Read,
+{
+    type Item = Result<TracePacket, DecoderError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.iter.next()? {
+                Ok(packet) if !self.filter.matches(&packet) => continue,
+                res => return Some(res),
+            }
+        }
+    }
+}
+
+impl<R> Iterator for Filter<Timestamps<R>>
+where
+    R: Read,
+{
+    type Item = Result<TimestampedTracePackets, DecoderError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.iter.next()? {
+                Ok(packets) => match self.filter.apply(packets) {
+                    Some(packets) => return Some(Ok(packets)),
+                    None => continue,
+                },
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ExceptionAction, VectActive};
+
+    #[test]
+    fn matches() {
+        let filter = PacketFilter {
+            exclude_kinds: vec![TracePacketKind::Overflow],
+            ports: vec![1],
+            exceptions: vec![19],
+            ..Default::default()
+        };
+
+        assert!(!filter.matches(&TracePacket::Overflow));
+        assert!(filter.matches(&TracePacket::Sync));
+        assert!(filter.matches(&TracePacket::Instrumentation {
+            port: 1,
+            payload: vec![]
+        }));
+        assert!(!filter.matches(&TracePacket::Instrumentation {
+            port: 2,
+            payload: vec![]
+        }));
+        assert!(filter.matches(&TracePacket::ExceptionTrace {
+            exception: VectActive::Interrupt { irqn: 3 },
+            action: ExceptionAction::Entered,
+        }));
+        assert!(!filter.matches(&TracePacket::ExceptionTrace {
+            exception: VectActive::Interrupt { irqn: 4 },
+            action: ExceptionAction::Entered,
+        }));
+    }
+
+    #[test]
+    fn time_window() {
+        let filter = PacketFilter {
+            include_kinds: vec![TracePacketKind::Overflow],
+            start: Some(Duration::from_micros(1)),
+            end: Some(Duration::from_micros(2)),
+            ..Default::default()
+        };
+        let packets = |micros| TimestampedTracePackets {
+            timestamp: Timestamp::Sync(Duration::from_micros(micros)),
+            packets: vec![TracePacket::Sync, TracePacket::Overflow],
+            malformed_packets: vec![],
+            consumed_packets: 3,
+        };
+
+        assert_eq!(filter.apply(packets(0)), None);
+        assert_eq!(
+            filter.apply(packets(1)).unwrap().packets,
+            [TracePacket::Overflow]
+        );
+        assert_eq!(filter.apply(packets(3)), None);
+    }
+}
@@ -0,0 +1,150 @@
+//! Waiting for more data at EOF without spinning.
+
+use std::io::{self, Read};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+const MIN_DELAY: Duration = Duration::from_millis(1);
+const MAX_DELAY: Duration = Duration::from_millis(100);
+
+/// Exponentially increasing delay between polls of a source without
+/// new data.
+pub(crate) struct Backoff {
+    delay: Duration,
+}
+
+impl Backoff {
+    pub fn new() -> Self {
+        Self { delay: MIN_DELAY }
+    }
+
+    /// Sleeps for the current delay, then doubles it, up to 100 ms.
+    pub fn wait(&mut self) {
+        thread::sleep(self.delay);
+        self.delay = (self.delay * 2).min(MAX_DELAY);
+    }
+
+    /// Resets the delay after new data was read.
+    pub fn reset(&mut self) {
+        self.delay = MIN_DELAY;
+    }
+}
+
+/// Reads from a source that is still being written to, like `tail -f`.
+///
+/// On EOF, or if a non-blocking source has no data yet, the source is
+/// polled again after a delay that grows from 1 ms to 100 ms for as
+/// long as no new data arrives. This works for growing regular files as
+/// well as for non-blocking TTYs and pipes.
+///
+/// Once the `stop` flag is set, e.g. from a signal handler, the
+/// remaining data is read and EOF is returned, so that a
+/// [`Decoder`](crate::Decoder) reading from a [`Follow`] ends its
+/// iteration cleanly. The decoder must then not be configured to
+/// [`ignore_eof`](crate::DecoderOptions::ignore_eof).
+pub struct Follow<R>
+where
+    R: Read,
+{
+    reader: R,
+    stop: Arc<AtomicBool>,
+    backoff: Backoff,
+}
+
+impl<R> Follow<R>
+where
+    R: Read,
+{
+    pub fn new(reader: R, stop: Arc<AtomicBool>) -> Self {
+        Self {
+            reader,
+            stop,
+            backoff: Backoff::new(),
+        }
+    }
+
+    /// Returns a reference to the underlying [`Read`](Read).
+    pub fn get_ref(&self) -> &R {
+        &self.reader
+    }
+
+    /// Returns a mutable reference to the underlying [`Read`](Read).
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+}
+
+impl<R> Read for Follow<R>
+where
+    R: Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            // NOTE(stop) loaded before reading so that data written
+            // before the flag was set is still returned.
+            let stop = self.stop.load(Ordering::Relaxed);
+            match self.reader.read(buf) {
+                Ok(0) if stop => return Ok(0),
+                Err(e) if stop && e.kind() == io::ErrorKind::WouldBlock => return Ok(0),
+                Ok(0) => self.backoff.wait(),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => self.backoff.wait(),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Ok(n) => {
+                    self.backoff.reset();
+                    return Ok(n);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Yields its chunks one read at a time, with EOF in between.
+    struct Growing {
+        chunks: Vec<&'static [u8]>,
+        eof: bool,
+    }
+
+    impl Read for Growing {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.eof = !self.eof;
+            if self.eof || self.chunks.is_empty() {
+                return Ok(0);
+            }
+
+            let chunk = self.chunks.remove(0);
+            buf[..chunk.len()].copy_from_slice(chunk);
+            Ok(chunk.len())
+        }
+    }
+
+    #[test]
+    fn reads_past_eof_until_stopped() {
+        let stop = Arc::new(AtomicBool::new(false));
+        let mut follow = Follow::new(
+            Growing {
+                chunks: vec![&[1, 2], &[3]],
+                eof: false,
+            },
+            stop.clone(),
+        );
+
+        let mut buf = [0; 4];
+        assert_eq!(follow.read(&mut buf).unwrap(), 2);
+        assert_eq!(follow.read(&mut buf).unwrap(), 1);
+        assert_eq!(buf[0], 3);
+
+        let stopper = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            stop.store(true, Ordering::Relaxed);
+        });
+        assert_eq!(follow.read(&mut buf).unwrap(), 0);
+        stopper.join().unwrap();
+    }
+}
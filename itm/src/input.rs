@@ -0,0 +1,443 @@
+//! Readers that unwrap probe capture files into the raw ITM/DWT trace
+//! stream expected by [`Decoder`](crate::Decoder).
+//!
+//! Captures are laid out in one of the following [`InputFormat`]s:
+//!
+//! - [`Raw`](InputFormat::Raw): the trace stream as-is, e.g. an SWO
+//! capture taken with the TPIU formatter disabled;
+//! - [`Tpiu`](InputFormat::Tpiu): the trace stream wrapped in the
+//! 16-byte frames of the CoreSight TPIU formatter, e.g. a capture taken
+//! with the formatter enabled. Unwrapped by [`TpiuReader`];
+//! - [`Pcapng`](InputFormat::Pcapng): a pcapng capture written by
+//! [`PcapngWriter`](crate::pcapng::PcapngWriter). Unwrapped by
+//! [`PcapngReader`];
+//! - [`Recording`](InputFormat::Recording): a timestamped recording
+//! written by [`Tee`](crate::record::Tee). Unwrapped by
+//! [`RecordingReader`], after which the format of the recorded bytes
+//! is detected in turn.
+//!
+//! [`open`] detects the format of a capture unless one is given.
+//!
+//! # Probe captures
+//!
+//! OpenOCD's `tpiu config ... file` output holds the bytes received from
+//! the probe as-is, and so is [`Raw`](InputFormat::Raw), or
+//! [`Tpiu`](InputFormat::Tpiu) if the formatter is enabled. The same
+//! holds for the SWO stream read from a J-Link or probe-rs at runtime,
+//! e.g. over a [`TcpSource`](crate::tcp::TcpSource).
+//!
+//! Files that wrap the stream in a probe-specific layout, i.e. those
+//! with a file header or with a timestamp per chunk of the stream, as
+//! J-Link and probe-rs tools may write, are not supported: there is no
+//! reader or detection for them, and they are read as raw.
+
+use crate::pcapng::PcapngReader;
+use crate::record::{RecordingReader, RECORDING_MAGIC};
+use crate::tcp;
+
+use std::io::{self, Cursor, Read};
+use std::str::FromStr;
+
+/// The TPIU stream ID of the ITM, unless configured otherwise.
+pub const TPIU_ITM_ID: u8 = 1;
+
+const TPIU_FRAME_SIZE: usize = 16;
+const TPIU_FULL_SYNC: [u8; 4] = [0xFF, 0xFF, 0xFF, 0x7F];
+const HALFWORD_SYNC: [u8; 2] = [0xFF, 0x7F];
+const PCAPNG_SHB: [u8; 4] = [0x0A, 0x0D, 0x0D, 0x0A];
+
+/// The number of bytes [`open`] inspects to detect the input format.
+pub const DETECT_SIZE: usize = 64;
+
+/// Layout of a trace capture. See the [module documentation](self).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InputFormat {
+    Raw,
+    Tpiu,
+    Pcapng,
+    Recording,
+}
+
+impl FromStr for InputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "raw" => Ok(Self::Raw),
+            "tpiu" => Ok(Self::Tpiu),
+            "pcapng" => Ok(Self::Pcapng),
+            "recording" => Ok(Self::Recording),
+            _ => Err(format!(
+                "{} is not a valid input format; valid formats are: raw, tpiu, pcapng, recording.",
+                s
+            )),
+        }
+    }
+}
+
+impl InputFormat {
+    /// Detects the format of a capture from its first bytes: pcapng
+    /// captures start with a section header block, recordings with
+    /// [`RECORDING_MAGIC`], and TPIU captures with full synchronization
+    /// packets followed by a frame that starts with a valid stream ID.
+    /// Anything else is assumed to be raw.
+    pub fn detect(start: &[u8]) -> Self {
+        if start.starts_with(&PCAPNG_SHB) {
+            Self::Pcapng
+        } else if start.starts_with(&RECORDING_MAGIC) {
+            Self::Recording
+        } else if is_tpiu(start) {
+            Self::Tpiu
+        } else {
+            Self::Raw
+        }
+    }
+}
+
+/// Returns whether `start` holds full synchronization packets followed
+/// by a TPIU frame.
+fn is_tpiu(mut start: &[u8]) -> bool {
+    if !start.starts_with(&TPIU_FULL_SYNC) {
+        return false;
+    }
+    while let Some(rest) = start.strip_prefix(&TPIU_FULL_SYNC) {
+        start = rest;
+    }
+
+    // NOTE(id) after synchronization the stream of the data is unknown,
+    // so the first frame starts with an ID change. IDs 0x00 and 0x70 to
+    // 0x7F are reserved.
+    match start.get(..TPIU_FRAME_SIZE) {
+        Some(frame) => frame[0] & 1 == 1 && (0x01..0x70).contains(&(frame[0] >> 1)),
+        None => false,
+    }
+}
+
+/// Wraps `reader` in the reader of the given `format`, or of the format
+/// [detected](InputFormat::detect) from the first bytes read from
+/// `reader` if none is given. TPIU captures are unwrapped for the
+/// [`TPIU_ITM_ID`] stream.
+///
+/// Detection waits for the first [`DETECT_SIZE`] bytes of `reader`, or
+/// for EOF, so that it does not depend on how a stream is split into
+/// reads. Live streams that may not offer as many bytes should be given
+/// a format instead. If a [`TcpSource`](crate::tcp::TcpSource)
+/// reconnects during detection, the bytes of the lost connection are
+/// discarded and detection starts over.
+pub fn open<R>(mut reader: R, format: Option<InputFormat>) -> io::Result<Box<dyn Read>>
+where
+    R: Read + 'static,
+{
+    let (reader, format): (Box<dyn Read>, _) = match format {
+        Some(format) => (Box::new(reader), format),
+        None => {
+            let mut start = vec![0; DETECT_SIZE];
+            let mut n = 0;
+            while n < DETECT_SIZE {
+                match reader.read(&mut start[n..]) {
+                    Ok(0) => break,
+                    Ok(m) => n += m,
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(e) if tcp::is_reconnect(&e) => n = 0,
+                    Err(e) => return Err(e),
+                }
+            }
+            start.truncate(n);
+            let format = InputFormat::detect(&start);
+            (Box::new(Cursor::new(start).chain(reader)), format)
+        }
+    };
+
+    Ok(match format {
+        InputFormat::Raw => reader,
+        InputFormat::Tpiu => Box::new(TpiuReader::new(reader, TPIU_ITM_ID)),
+        InputFormat::Pcapng => Box::new(PcapngReader::new(reader)),
+        // NOTE(open) a recording holds the bytes of a source before
+        // they were unwrapped.
+        InputFormat::Recording => open(RecordingReader::new(reader), None)?,
+    })
+}
+
+/// Unwraps the data of a single stream from TPIU formatted frames.
+///
+/// Frames are aligned to the first full synchronization packet; data
+/// before it is discarded. Full synchronization packets between frames
+/// and halfword synchronization packets within frames are skipped. See
+/// the CoreSight Architecture Specification, section D4.2.
+///
+/// A halfword synchronization packet, `0x7FFF`, occupies an ID and data
+/// byte pair of a frame.
+pub struct TpiuReader<R>
+where
+    R: Read,
+{
+    reader: R,
+    id: u8,
+
+    /// Stream ID of the data currently being unwrapped.
+    current_id: u8,
+    synced: bool,
+
+    /// Raw bytes read but not yet unwrapped.
+    raw: Vec<u8>,
+
+    /// Unwrapped data not yet read.
+    data: Vec<u8>,
+    pos: usize,
+}
+
+impl<R> TpiuReader<R>
+where
+    R: Read,
+{
+    /// Unwraps the data of stream `id` from the frames read from
+    /// `reader`.
+    pub fn new(reader: R, id: u8) -> Self {
+        Self {
+            reader,
+            id,
+            current_id: 0,
+            synced: false,
+            raw: vec![],
+            data: vec![],
+            pos: 0,
+        }
+    }
+
+    /// Reads more raw bytes. Returns `false` on EOF.
+    fn fill(&mut self) -> io::Result<bool> {
+        let mut buf = [0; 256];
+        loop {
+            match self.reader.read(&mut buf) {
+                Ok(0) => return Ok(false),
+                Ok(n) => {
+                    self.raw.extend_from_slice(&buf[..n]);
+                    return Ok(true);
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Unwraps all complete frames in `raw`.
+    fn unwrap_frames(&mut self) {
+        let mut start = 0;
+        loop {
+            let raw = &self.raw[start..];
+            if !self.synced {
+                match raw.windows(4).position(|w| w == TPIU_FULL_SYNC) {
+                    Some(pos) => {
+                        self.synced = true;
+                        start += pos + TPIU_FULL_SYNC.len();
+                    }
+                    None => {
+                        // keep a potential partial sync packet
+                        start += raw.len().saturating_sub(TPIU_FULL_SYNC.len() - 1);
+                        break;
+                    }
+                }
+            } else if raw.starts_with(&TPIU_FULL_SYNC) {
+                start += TPIU_FULL_SYNC.len();
+            } else if raw.len() >= TPIU_FRAME_SIZE {
+                let frame: [u8; TPIU_FRAME_SIZE] = raw[..TPIU_FRAME_SIZE].try_into().unwrap();
+                self.unwrap_frame(&frame);
+                start += TPIU_FRAME_SIZE;
+            } else {
+                break;
+            }
+        }
+
+        self.raw.drain(..start);
+    }
+
+    fn unwrap_frame(&mut self, frame: &[u8; TPIU_FRAME_SIZE]) {
+        let aux = frame[15];
+        for i in 0..8 {
+            let byte = frame[2 * i];
+            let aux_bit = (aux >> i) & 1;
+            if i < 7 && [byte, frame[2 * i + 1]] == HALFWORD_SYNC {
+                continue;
+            }
+
+            // NOTE(delayed) an ID change with the auxiliary bit set
+            // takes effect after the following data byte.
+            let mut delayed_id = None;
+            if byte & 1 == 1 {
+                if aux_bit == 1 {
+                    delayed_id = Some(byte >> 1);
+                } else {
+                    self.current_id = byte >> 1;
+                }
+            } else {
+                self.push(byte | aux_bit);
+            }
+
+            if i < 7 {
+                self.push(frame[2 * i + 1]);
+            }
+            if let Some(id) = delayed_id {
+                self.current_id = id;
+            }
+        }
+    }
+
+    fn push(&mut self, byte: u8) {
+        if self.current_id == self.id {
+            self.data.push(byte);
+        }
+    }
+}
+
+impl<R> Read for TpiuReader<R>
+where
+    R: Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.data.len() {
+            self.data.clear();
+            self.pos = 0;
+            if !self.fill()? {
+                return Ok(0);
+            }
+            self.unwrap_frames();
+        }
+
+        let n = buf.len().min(self.data.len() - self.pos);
+        buf[..n].copy_from_slice(&self.data[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detect() {
+        assert_eq!(InputFormat::detect(&[0x70, 0x0b, 0x01]), InputFormat::Raw);
+        let mut tpiu = vec![0xFF, 0xFF, 0xFF, 0x7F, 0xFF, 0xFF, 0xFF, 0x7F, 0x03];
+        tpiu.resize(4 + 4 + 16, 0);
+        assert_eq!(InputFormat::detect(&tpiu), InputFormat::Tpiu);
+
+        // NOTE(sync) a raw stream may contain a full synchronization
+        // packet, e.g. in an instrumentation payload.
+        assert_eq!(
+            InputFormat::detect(&[0x00, 0xFF, 0xFF, 0xFF, 0x7F, 0x03]),
+            InputFormat::Raw
+        );
+        assert_eq!(InputFormat::detect(&tpiu[..20]), InputFormat::Raw);
+        tpiu[8] = 0xFD;
+        assert_eq!(InputFormat::detect(&tpiu), InputFormat::Raw);
+        assert_eq!(
+            InputFormat::detect(&[0x0A, 0x0D, 0x0D, 0x0A, 0x1C]),
+            InputFormat::Pcapng
+        );
+        assert_eq!(
+            InputFormat::detect(b"ITMREC\x00\x01\x00"),
+            InputFormat::Recording
+        );
+    }
+
+    #[test]
+    fn tpiu_frames() {
+        #[rustfmt::skip]
+        let capture: &[u8] = &[
+            0xAA, // garbage before sync
+            0xFF, 0xFF, 0xFF, 0x7F,
+            // ID 1, 0x01, 0x02, 0x03, ID 2 (delayed), 0x04 (still ID 1),
+            // 0x05 (LSB from aux bit 3), 0x05, ID 1, 0x06, 0x08, 0x09, 0x00...
+            0x03, 0x01, 0x02, 0x03, 0x05, 0x04, 0x04, 0x05,
+            0x03, 0x06, 0x08, 0x09, 0x00, 0x00, 0x00, 0b0000_1100,
+            0xFF, 0xFF, 0xFF, 0x7F,
+        ];
+
+        let mut data = vec![];
+        TpiuReader::new(capture, 1).read_to_end(&mut data).unwrap();
+        assert_eq!(
+            data,
+            [0x01, 0x02, 0x03, 0x04, 0x06, 0x08, 0x09, 0x00, 0x00, 0x00]
+        );
+    }
+
+    #[test]
+    fn halfword_sync() {
+        #[rustfmt::skip]
+        let capture: &[u8] = &[
+            0xFF, 0xFF, 0xFF, 0x7F,
+            // ID 1, 0x01, halfword sync, 0x02, 0x03, halfword sync...
+            0x03, 0x01, 0xFF, 0x7F, 0x02, 0x03, 0xFF, 0x7F,
+            0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0A, 0x00,
+        ];
+
+        let mut data = vec![];
+        TpiuReader::new(capture, 1).read_to_end(&mut data).unwrap();
+        assert_eq!(
+            data,
+            [0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0A]
+        );
+    }
+
+    #[test]
+    fn detect_bytewise() {
+        /// Returns a byte per read.
+        struct Bytewise(Vec<u8>);
+
+        impl Read for Bytewise {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                if self.0.is_empty() {
+                    return Ok(0);
+                }
+                buf[0] = self.0.remove(0);
+                Ok(1)
+            }
+        }
+
+        let mut capture = vec![0xFF, 0xFF, 0xFF, 0x7F, 0x03, 0x01, 0x02];
+        capture.resize(4 + 16, 0);
+        let mut data = vec![];
+        open(Bytewise(capture), None)
+            .unwrap()
+            .read_to_end(&mut data)
+            .unwrap();
+        assert_eq!(data[..2], [0x01, 0x02]);
+    }
+
+    #[test]
+    fn detect_reconnect() {
+        /// Returns a result per read.
+        struct Script(Vec<io::Result<Vec<u8>>>);
+
+        impl Read for Script {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                if self.0.is_empty() {
+                    return Ok(0);
+                }
+                let bytes = self.0.remove(0)?;
+                buf[..bytes.len()].copy_from_slice(&bytes);
+                Ok(bytes.len())
+            }
+        }
+
+        let reconnected = io::Error::new(
+            io::ErrorKind::ConnectionReset,
+            tcp::Reconnected {
+                addr: "localhost:3443".to_string(),
+            },
+        );
+        let mut data = vec![];
+        open(
+            Script(vec![
+                Ok(vec![0x0A, 0x0D]),
+                Err(reconnected),
+                Ok(vec![0x70, 0x0b, 0x01]),
+            ]),
+            None,
+        )
+        .unwrap()
+        .read_to_end(&mut data)
+        .unwrap();
+        assert_eq!(data, [0x70, 0x0b, 0x01]);
+    }
+}
@@ -22,6 +22,17 @@ where
     pub(super) fn new(decoder: Decoder<R>) -> Self {
         Self { decoder }
     }
+
+    /// Returns the byte offset of the next packet in the trace stream.
+    /// See [`Decoder::bytes_consumed`].
+    pub fn bytes_consumed(&self) -> u64 {
+        self.decoder.bytes_consumed()
+    }
+
+    /// Returns a mutable reference to the underlying [`Read`](Read).
+    pub fn get_mut(&mut self) -> &mut R {
+        self.decoder.get_mut()
+    }
 }
 
 impl<R> Iterator for Singles<R>
@@ -127,6 +138,22 @@ pub enum Timestamp {
     },
 }
 
+impl Timestamp {
+    /// Returns the offset from trace clock start, disregarding the
+    /// timestamp quality. For
+    /// [`UnknownDelay`](Timestamp::UnknownDelay) and
+    /// [`UnknownAssocEventDelay`](Timestamp::UnknownAssocEventDelay),
+    /// this is the current timestamp: the latest point in time the
+    /// associated event could have occured.
+    pub fn offset(&self) -> Duration {
+        match self {
+            Timestamp::Sync(offset) | Timestamp::AssocEventDelay(offset) => *offset,
+            Timestamp::UnknownDelay { curr, .. }
+            | Timestamp::UnknownAssocEventDelay { curr, .. } => *curr,
+        }
+    }
+}
+
 /// Iterator that yield [`TimestampedTracePackets`](TimestampedTracePackets).
 pub struct Timestamps<R>
 where
@@ -147,14 +174,16 @@ struct Gts {
 impl Gts {
     const GTS2_SHIFT: u32 = 26; // see (Appendix D4.2.5).
 
-    pub fn replace_lower(&mut self, new: u64) {
-        self.lower = match self.lower {
-            None => Some(new),
-            Some(old) => {
-                let shift = 64 - new.leading_zeros();
-                Some(((old >> shift) << shift) | new)
-            }
-        }
+    /// Replaces the lower-order bits carried by a GTS1 packet with a
+    /// payload of `len` bytes: seven bits per byte, or all bits if the
+    /// packet is of full size. The bits of omitted bytes are those of
+    /// the previous GTS1. (Appendix D4.2.5)
+    pub fn replace_lower(&mut self, new: u64, len: u64) {
+        let mask = match len {
+            4.. => (1 << Self::GTS2_SHIFT) - 1,
+            len => (1 << (7 * len)) - 1,
+        };
+        self.lower = Some(self.lower.map_or(new, |old| (old & !mask) | new));
     }
 
     pub fn reset(&mut self) {
@@ -217,6 +246,18 @@ where
         }
     }
 
+    /// Returns the byte offset of the first packet of the next
+    /// [`TimestampedTracePackets`] in the trace stream. See
+    /// [`Decoder::bytes_consumed`].
+    pub fn bytes_consumed(&self) -> u64 {
+        self.decoder.bytes_consumed()
+    }
+
+    /// Returns a mutable reference to the underlying [`Read`](Read).
+    pub fn get_mut(&mut self) -> &mut R {
+        self.decoder.get_mut()
+    }
+
     fn next_timestamped(
         &mut self,
         options: TimestampsConfiguration,
@@ -266,6 +307,7 @@ where
 
         loop {
             consumed_packets += 1;
+            let start = self.decoder.bytes_consumed();
             match self.decoder.next_single() {
                 Err(DecoderErrorInt::MalformedPacket(m)) if options.expect_malformed => {
                     malformed_packets.push(m);
@@ -306,7 +348,9 @@ where
                     // A global timestamp: store until we have both the
                     // upper (GTS2) and lower (GTS1) bits.
                     TracePacket::GlobalTimestamp1 { ts, wrap, clkch } => {
-                        self.gts.replace_lower(ts);
+                        // NOTE(len) less the header
+                        let len = self.decoder.bytes_consumed() - start - 1;
+                        self.gts.replace_lower(ts, len);
 
                         if wrap {
                             // upper bits have changed; GTS2 incoming
@@ -384,7 +428,7 @@ mod timestamp_utils {
         };
         assert_eq!(gts.merge(), Some(67108865));
 
-        gts.replace_lower(127);
+        gts.replace_lower(127, 1);
         assert_eq!(gts.merge(), Some(67108991));
 
         let gts = Gts {
@@ -403,23 +447,36 @@ mod timestamp_utils {
             "(42, 42) merge"
         );
 
-        gts.replace_lower(0b1101011);
+        gts.replace_lower(0b1101011, 1);
         assert_eq!(
             gts.merge(),
             Some((42 << Gts::GTS2_SHIFT) | 0b1101011),
             "replace whole merge"
         );
 
+        // A single-byte GTS1 replaces all seven bits of the first
+        // byte, not only those up to its most significant set bit.
         let mut gts = Gts {
-            lower: Some(42),
+            lower: Some((3 << 7) | 42),
             upper: Some(42),
         };
-        gts.replace_lower(1);
+        gts.replace_lower(1, 1);
         assert_eq!(
             gts.merge(),
-            Some((42 << Gts::GTS2_SHIFT) | 43),
+            Some((42 << Gts::GTS2_SHIFT) | (3 << 7) | 1),
             "replace partial merge"
         );
+
+        let mut gts = Gts {
+            lower: Some((1 << 25) | 42),
+            upper: Some(42),
+        };
+        gts.replace_lower(1, 4);
+        assert_eq!(
+            gts.merge(),
+            Some((42 << Gts::GTS2_SHIFT) | 1),
+            "replace full-size merge"
+        );
     }
 
     #[test]
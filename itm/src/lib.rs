@@ -18,6 +18,28 @@
 //! [a timestamp relative to target reset of when the packets where
 //! generated target-side](TimestampedTracePackets::timestamp).
 //!
+//! Instrumentation packets written to the stimulus ports can be
+//! reassembled into per-port lines of text with
+//! [`StimulusLines`](StimulusLines). Other protocols written to the
+//! stimulus ports can be decoded into typed events by attaching a
+//! [`PortDecoder`](PortDecoder) to the ports in a
+//! [`PortDecoders`](PortDecoders) registry. The packets yielded by
+//! either iterator can be [filtered](PacketFilter) by packet kind,
+//! stimulus port, DWT comparator, exception, and time. The
+//! [`rtic`](rtic) module reconstructs an RTIC task timeline from the
+//! trace stream. The
+//! [`export`](export) module writes timestamped trace streams to
+//! formats understood by other tools, and the [`pcapng`](pcapng)
+//! module archives raw trace streams in pcapng capture files. The
+//! [`input`](input) module unwraps the capture files of common debug
+//! probes into the raw trace stream, and the [`tcp`](tcp) module reads
+//! it from the TCP servers of debug probes. The [`record`](record)
+//! module records the raw bytes of a source while they are decoded,
+//! and the [`encode`](encode) module does the reverse of decoding:
+//! it synthesizes trace streams from packets. The [`sim`](sim) module
+//! simulates the trace hardware of a target to generate trace streams
+//! with a known ground truth.
+//!
 //! Usage is simple:
 //! ```
 //! use itm::{Decoder, DecoderOptions};
@@ -38,9 +60,38 @@ pub use iter::{
     TimestampsConfiguration,
 };
 
+mod stimulus;
+pub use stimulus::{StimulusLine, StimulusLines};
+
+mod filter;
+pub use filter::{Filter, PacketFilter};
+
+mod follow;
+use follow::Backoff;
+pub use follow::Follow;
+
+mod ports;
+pub use ports::{IntoPortRange, MapEvents, PortDecoder, PortDecoderError, PortDecoders};
+
+mod exception;
+pub use exception::exception_name;
+use exception::exception_number;
+
+pub mod encode;
+pub mod export;
+pub mod input;
+pub mod pcapng;
+pub mod record;
+pub mod rtic;
+pub mod sim;
+pub mod tcp;
+
 #[cfg(feature = "serial")]
 pub mod serial;
 
+#[cfg(feature = "defmt")]
+pub mod defmt;
+
 use std::convert::TryInto;
 use std::io::Read;
 
@@ -192,6 +243,92 @@ pub enum TracePacket {
     },
 }
 
+/// The kind of a [`TracePacket`](TracePacket), without its fields.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TracePacketKind {
+    Sync,
+    Overflow,
+    LocalTimestamp1,
+    LocalTimestamp2,
+    GlobalTimestamp1,
+    GlobalTimestamp2,
+    Extension,
+    Instrumentation,
+    EventCounterWrap,
+    ExceptionTrace,
+    PCSample,
+    DataTracePC,
+    DataTraceAddress,
+    DataTraceValue,
+}
+
+impl TracePacketKind {
+    /// All packet kinds, in order of declaration.
+    pub const ALL: [TracePacketKind; 14] = [
+        Self::Sync,
+        Self::Overflow,
+        Self::LocalTimestamp1,
+        Self::LocalTimestamp2,
+        Self::GlobalTimestamp1,
+        Self::GlobalTimestamp2,
+        Self::Extension,
+        Self::Instrumentation,
+        Self::EventCounterWrap,
+        Self::ExceptionTrace,
+        Self::PCSample,
+        Self::DataTracePC,
+        Self::DataTraceAddress,
+        Self::DataTraceValue,
+    ];
+}
+
+impl std::str::FromStr for TracePacketKind {
+    type Err = String;
+
+    /// Parses the name of a [`TracePacket`](TracePacket) variant, e.g.
+    /// `ExceptionTrace`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .iter()
+            .find(|kind| format!("{:?}", kind) == s)
+            .copied()
+            .ok_or_else(|| {
+                format!(
+                    "{} is not a valid packet kind; valid kinds are: {}.",
+                    s,
+                    Self::ALL
+                        .iter()
+                        .map(|kind| format!("{:?}", kind))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )
+            })
+    }
+}
+
+impl TracePacket {
+    /// Returns the kind of this packet.
+    pub fn kind(&self) -> TracePacketKind {
+        match self {
+            Self::Sync => TracePacketKind::Sync,
+            Self::Overflow => TracePacketKind::Overflow,
+            Self::LocalTimestamp1 { .. } => TracePacketKind::LocalTimestamp1,
+            Self::LocalTimestamp2 { .. } => TracePacketKind::LocalTimestamp2,
+            Self::GlobalTimestamp1 { .. } => TracePacketKind::GlobalTimestamp1,
+            Self::GlobalTimestamp2 { .. } => TracePacketKind::GlobalTimestamp2,
+            Self::Extension { .. } => TracePacketKind::Extension,
+            Self::Instrumentation { .. } => TracePacketKind::Instrumentation,
+            Self::EventCounterWrap { .. } => TracePacketKind::EventCounterWrap,
+            Self::ExceptionTrace { .. } => TracePacketKind::ExceptionTrace,
+            Self::PCSample { .. } => TracePacketKind::PCSample,
+            Self::DataTracePC { .. } => TracePacketKind::DataTracePC,
+            Self::DataTraceAddress { .. } => TracePacketKind::DataTraceAddress,
+            Self::DataTraceValue { .. } => TracePacketKind::DataTraceValue,
+        }
+    }
+}
+
 /// Denotes the action taken by the processor by a given exception. (Table D4-6)
 #[derive(Debug, Clone, PartialEq)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
@@ -372,12 +509,35 @@ enum HeaderVariant {
     Stub(PacketStub),
 }
 
+/// Classification of the first byte of a packet, the header. See
+/// [`classify_header`](classify_header).
+#[derive(Debug, Clone, PartialEq)]
+pub enum HeaderClass {
+    /// The header is a complete single-byte packet.
+    Packet(TracePacket),
+
+    /// The header starts a packet of the given kind that continues in
+    /// the following bytes.
+    Stub {
+        kind: TracePacketKind,
+
+        /// The number of payload bytes following the header. `None` if
+        /// the payload ends at the first byte with a cleared
+        /// continuation bit (timestamp packets) or, for synchronization
+        /// packets, at the first set bit.
+        payload_size: Option<usize>,
+    },
+}
+
 /// [`Decoder`](Decoder) configuration.
 pub struct DecoderOptions {
     /// Whether to keep reading after a (temporary) EOF condition. If
     /// set iteration is done over [`Singles`](Singles) or
     /// [`Timestamps`](Timestamps), [`next`](Iterator::next) will never
-    /// return unless the EOF condition is eventually resolved.
+    /// return unless the EOF condition is eventually resolved. The
+    /// source is polled with an increasing delay of up to 100 ms while
+    /// at EOF. To be able to stop at EOF, wrap the source in a
+    /// [`Follow`](Follow) instead.
     pub ignore_eof: bool,
 }
 
@@ -407,6 +567,9 @@ where
     reader: R,
     buffer: BitVec,
     ignore_eof: bool,
+
+    /// The number of bits popped from the buffer.
+    bits_consumed: u64,
 }
 
 impl<R> Buffer<R>
@@ -418,18 +581,21 @@ where
             reader,
             ignore_eof,
             buffer: BitVec::new(),
+            bits_consumed: 0,
         }
     }
 
-    /// Tries to read up to 32 bytes from [Self::reader]. Continuously retries if [ignore_eof] is set.
+    /// Tries to read up to 32 bytes from [Self::reader]. Retries with backoff if [ignore_eof] is set.
     fn buffer_some(&mut self) -> Result<(), DecoderErrorInt> {
         // `Read::read` reportedly reads in 32-byte chunks. Source:
         // <https://github.com/rust-embedded/itm/blob/3e4251b42aa2e4b05ae372c47c7b835b8acae6dc/src/lib.rs#L42>.
         let mut buffer: [u8; 32] = [0; 32];
+        let mut backoff = Backoff::new();
         loop {
             match self.reader.read(&mut buffer) {
                 Ok(0) => {
                     if self.ignore_eof {
+                        backoff.wait();
                         continue;
                     }
                     return Err(DecoderErrorInt::Eof);
@@ -462,7 +628,10 @@ where
                     self.buffer_some()?;
                     continue;
                 }
-                Some(bit) => return Ok(bit),
+                Some(bit) => {
+                    self.bits_consumed += 1;
+                    return Ok(bit);
+                }
             }
         }
     }
@@ -544,6 +713,13 @@ where
         &mut self.buffer.reader
     }
 
+    /// Returns the number of bytes of the trace stream that have been
+    /// decoded so far. Before a packet is decoded, this is the byte
+    /// offset of the packet in the stream.
+    pub fn bytes_consumed(&self) -> u64 {
+        self.buffer.bits_consumed / 8
+    }
+
     /// Returns an iterator over [`TracePacket`](TracePacket)s. Consumes
     /// the [`Decoder`](Decoder).
     pub fn singles(self) -> Singles<R> {
@@ -777,6 +953,43 @@ fn decode_header(header: u8) -> Result<HeaderVariant, MalformedPacket> {
     }
 }
 
+/// Classifies the first byte of a packet, the header, as a complete
+/// packet or the start of a packet of some kind. This is the first step
+/// of decoding every packet and is exposed to inspect captures
+/// byte-by-byte.
+pub fn classify_header(header: u8) -> Result<HeaderClass, MalformedPacket> {
+    use TracePacketKind::*;
+
+    let stub = match decode_header(header)? {
+        HeaderVariant::Packet(p) => return Ok(HeaderClass::Packet(p)),
+        HeaderVariant::Stub(stub) => stub,
+    };
+    let (kind, payload_size) = match stub {
+        PacketStub::Sync(_) => (Sync, None),
+        PacketStub::Instrumentation { expected_size, .. } => (Instrumentation, Some(expected_size)),
+        PacketStub::HardwareSource {
+            disc_id,
+            expected_size,
+        } => (
+            match disc_id {
+                0 => EventCounterWrap,
+                1 => ExceptionTrace,
+                2 => PCSample,
+                // NOTE(disc_id) c.f. handle_hardware_source
+                8..=15 if disc_id & 1 == 0 => DataTracePC,
+                8..=15 => DataTraceAddress,
+                _ => DataTraceValue,
+            },
+            Some(expected_size),
+        ),
+        PacketStub::LocalTimestamp { .. } => (LocalTimestamp1, None),
+        PacketStub::GlobalTimestamp1 => (GlobalTimestamp1, None),
+        PacketStub::GlobalTimestamp2 => (GlobalTimestamp2, None),
+    };
+
+    Ok(HeaderClass::Stub { kind, payload_size })
+}
+
 /// Decodes the payload of a hardware source packet.
 #[bitmatch]
 fn handle_hardware_source(disc_id: u8, payload: Vec<u8>) -> Result<TracePacket, MalformedPacket> {
@@ -886,6 +1099,39 @@ fn handle_hardware_source(disc_id: u8, payload: Vec<u8>) -> Result<TracePacket,
 mod decoder_buffer_utils {
     use super::*;
 
+    #[test]
+    fn classify_header() {
+        assert_eq!(
+            super::classify_header(0b0111_0000).unwrap(),
+            HeaderClass::Packet(TracePacket::Overflow)
+        );
+        assert_eq!(
+            super::classify_header(0b0000_1011).unwrap(),
+            HeaderClass::Stub {
+                kind: TracePacketKind::Instrumentation,
+                payload_size: Some(4),
+            }
+        );
+        assert_eq!(
+            super::classify_header(0b0101_1101).unwrap(),
+            HeaderClass::Stub {
+                kind: TracePacketKind::DataTraceAddress,
+                payload_size: Some(1),
+            }
+        );
+        assert_eq!(
+            super::classify_header(0b1001_0100).unwrap(),
+            HeaderClass::Stub {
+                kind: TracePacketKind::GlobalTimestamp1,
+                payload_size: None,
+            }
+        );
+        assert!(matches!(
+            super::classify_header(0b1111_0100),
+            Err(MalformedPacket::InvalidHardwareDisc { disc_id: 30, .. })
+        ));
+    }
+
     #[test]
     fn buffer_pop_bytes() {
         let bytes: &[u8] = &[0b1000_0000, 0b1010_0000, 0b1000_0100, 0b0110_0000];
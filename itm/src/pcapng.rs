@@ -0,0 +1,448 @@
+//! Archival of trace streams in the [pcapng capture file
+//! format](https://datatracker.ietf.org/doc/draft-ietf-opsawg-pcapng/).
+//!
+//! [`PcapngWriter`] writes a single interface of link type
+//! [`LINKTYPE_ITM`] with nanosecond timestamp resolution. Each decoded
+//! ITM/DWT packet is written as an Enhanced Packet Block holding the raw
+//! bytes the decoder consumed for it, timestamped with the
+//! reconstructed [`Timestamp`] of its set, and commented with the
+//! decoded packet.
+//!
+//! [`PcapngReader`] does the reverse: it concatenates the packet data
+//! of all [`LINKTYPE_ITM`] interfaces of a pcapng file back into the raw
+//! trace stream, and implements [`Read`] so that it can be given to a
+//! [`Decoder`].
+//!
+//! Raw bytes are recovered from a decoded stream by wrapping its reader
+//! in a [`RawCapture`] and taking them up to the
+//! [`bytes_consumed`](crate::Singles::bytes_consumed) of the decoder:
+//!
+//! ```
+//! use itm::{pcapng::{PcapngWriter, RawCapture}, Decoder, DecoderOptions};
+//!
+//! let stream: &[u8] = &[0x70, 0x0b, 0x01, 0x02, 0x03, 0x04];
+//! let mut singles = Decoder::new(RawCapture::new(stream), DecoderOptions { ignore_eof: false })
+//!     .singles();
+//! let mut pcapng = PcapngWriter::new(vec![]).unwrap();
+//! while let Some(packet) = singles.next() {
+//!     let offset = singles.bytes_consumed();
+//!     let raw = singles.get_mut().take(offset);
+//!     pcapng.write(&raw, None, &format!("{:?}", packet)).unwrap();
+//! }
+//! let pcapng = pcapng.finish().unwrap();
+//! ```
+
+use super::{Decoder, DecoderOptions, Timestamp};
+
+use std::io::{self, Read, Write};
+
+/// The link type of ITM/DWT trace streams:
+/// [`LINKTYPE_USER0`](https://www.tcpdump.org/linktypes.html), reserved
+/// for private use until a link type is assigned.
+pub const LINKTYPE_ITM: u16 = 147;
+
+const BLOCK_SHB: u32 = 0x0A0D_0D0A;
+const BLOCK_IDB: u32 = 0x0000_0001;
+const BLOCK_SPB: u32 = 0x0000_0003;
+const BLOCK_EPB: u32 = 0x0000_0006;
+
+const BYTE_ORDER_MAGIC: u32 = 0x1A2B_3C4D;
+
+const OPT_ENDOFOPT: u16 = 0;
+const OPT_COMMENT: u16 = 1;
+const IF_NAME: u16 = 2;
+const IF_TSRESOL: u16 = 9;
+
+/// Wraps a [`Read`] and keeps the bytes read from it until they are
+/// [taken](Self::take).
+pub struct RawCapture<R>
+where
+    R: Read,
+{
+    reader: R,
+    bytes: Vec<u8>,
+
+    /// Stream offset of the first byte in `bytes`.
+    start: u64,
+}
+
+impl<R> RawCapture<R>
+where
+    R: Read,
+{
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            bytes: vec![],
+            start: 0,
+        }
+    }
+
+    /// Returns the bytes from the end of the previous take up to the
+    /// stream `offset`, e.g. a
+    /// [`bytes_consumed`](crate::Singles::bytes_consumed) of the
+    /// decoder.
+    pub fn take(&mut self, offset: u64) -> Vec<u8> {
+        let n = (offset.saturating_sub(self.start) as usize).min(self.bytes.len());
+        self.start += n as u64;
+        self.bytes.drain(..n).collect()
+    }
+}
+
+impl<R> Read for RawCapture<R>
+where
+    R: Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.reader.read(buf)?;
+        self.bytes.extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+}
+
+/// Writes raw trace bytes to a pcapng file, an Enhanced Packet Block
+/// per decoded ITM/DWT packet.
+pub struct PcapngWriter<W>
+where
+    W: Write,
+{
+    writer: W,
+}
+
+impl<W> PcapngWriter<W>
+where
+    W: Write,
+{
+    /// Creates a new writer and writes the section header and the
+    /// interface description to `writer`.
+    pub fn new(writer: W) -> io::Result<Self> {
+        let mut this = Self { writer };
+
+        let mut shb = vec![];
+        shb.extend(BYTE_ORDER_MAGIC.to_le_bytes());
+        shb.extend(1u16.to_le_bytes()); // major version
+        shb.extend(0u16.to_le_bytes()); // minor version
+        shb.extend((-1i64).to_le_bytes()); // section length: unspecified
+        this.block(BLOCK_SHB, &shb)?;
+
+        let mut idb = vec![];
+        idb.extend(LINKTYPE_ITM.to_le_bytes());
+        idb.extend(0u16.to_le_bytes()); // reserved
+        idb.extend(0u32.to_le_bytes()); // snap length: unlimited
+        option(&mut idb, IF_NAME, b"itm");
+        option(&mut idb, IF_TSRESOL, &[9]); // nanoseconds
+        option(&mut idb, OPT_ENDOFOPT, &[]);
+        this.block(BLOCK_IDB, &idb)?;
+
+        Ok(this)
+    }
+
+    /// Writes the `raw` bytes of a decoded packet in an Enhanced Packet
+    /// Block with the given `timestamp`, or a zero timestamp if none is
+    /// given, and the decoded packet as `comment`.
+    pub fn write(
+        &mut self,
+        raw: &[u8],
+        timestamp: Option<&Timestamp>,
+        comment: &str,
+    ) -> io::Result<()> {
+        let nanos = timestamp
+            .map(|ts| ts.offset().as_nanos() as u64)
+            .unwrap_or(0);
+
+        self.packet(raw, nanos, comment)
+    }
+
+    /// Writes the `raw` bytes of a set of timestamped packets, e.g. those
+    /// consumed for a [`TimestampedTracePackets`](crate::TimestampedTracePackets),
+    /// an Enhanced Packet Block per packet, each with the `timestamp` of
+    /// the set. The packets are decoded again from `raw` to find their
+    /// bytes.
+    pub fn write_set(&mut self, raw: &[u8], timestamp: &Timestamp) -> io::Result<()> {
+        let mut singles =
+            Decoder::new(RawCapture::new(raw), DecoderOptions { ignore_eof: false }).singles();
+        while let Some(packet) = singles.next() {
+            let comment = match packet {
+                Ok(packet) => format!("{:?}", packet),
+                Err(e) => e.to_string(),
+            };
+            let offset = singles.bytes_consumed();
+            self.write(&singles.get_mut().take(offset), Some(timestamp), &comment)?;
+        }
+
+        // NOTE(rest) a set ends with a complete packet, but keep any
+        // trailing bytes.
+        let rest = singles.get_mut().take(u64::MAX);
+        if !rest.is_empty() {
+            self.write(&rest, Some(timestamp), "")?;
+        }
+
+        Ok(())
+    }
+
+    /// Flushes the underlying writer and returns it.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    fn packet(&mut self, data: &[u8], nanos: u64, comment: &str) -> io::Result<()> {
+        let mut epb = vec![];
+        epb.extend(0u32.to_le_bytes()); // interface ID
+        epb.extend(((nanos >> 32) as u32).to_le_bytes());
+        epb.extend((nanos as u32).to_le_bytes());
+        epb.extend((data.len() as u32).to_le_bytes()); // captured length
+        epb.extend((data.len() as u32).to_le_bytes()); // original length
+        epb.extend(data);
+        pad(&mut epb);
+        option(&mut epb, OPT_COMMENT, comment.as_bytes());
+        option(&mut epb, OPT_ENDOFOPT, &[]);
+
+        self.block(BLOCK_EPB, &epb)
+    }
+
+    fn block(&mut self, block_type: u32, body: &[u8]) -> io::Result<()> {
+        let len = (body.len() as u32 + 12).to_le_bytes();
+        self.writer.write_all(&block_type.to_le_bytes())?;
+        self.writer.write_all(&len)?;
+        self.writer.write_all(body)?;
+        self.writer.write_all(&len)
+    }
+}
+
+fn pad(buf: &mut Vec<u8>) {
+    buf.resize((buf.len() + 3) / 4 * 4, 0);
+}
+
+fn option(buf: &mut Vec<u8>, code: u16, value: &[u8]) {
+    buf.extend(code.to_le_bytes());
+    buf.extend((value.len() as u16).to_le_bytes());
+    buf.extend(value);
+    pad(buf);
+}
+
+/// Reads the raw trace stream from a pcapng file. See the [module
+/// documentation](self).
+pub struct PcapngReader<R>
+where
+    R: Read,
+{
+    reader: R,
+    big_endian: bool,
+
+    /// Link types of the interfaces of the current section.
+    interfaces: Vec<u16>,
+
+    /// Packet data not yet read.
+    data: Vec<u8>,
+    pos: usize,
+}
+
+impl<R> PcapngReader<R>
+where
+    R: Read,
+{
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            big_endian: false,
+            interfaces: vec![],
+            data: vec![],
+            pos: 0,
+        }
+    }
+
+    fn u16(&self, bytes: &[u8]) -> u16 {
+        let bytes = bytes[..2].try_into().unwrap();
+        if self.big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        }
+    }
+
+    fn u32(&self, bytes: &[u8]) -> u32 {
+        let bytes = bytes[..4].try_into().unwrap();
+        if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        }
+    }
+
+    /// Reads the next block, buffering its packet data if any. Returns
+    /// `false` on EOF.
+    fn next_block(&mut self) -> io::Result<bool> {
+        let mut header = [0; 8];
+        match self.reader.read_exact(&mut header[..4]) {
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(false),
+            res => res?,
+        }
+        self.reader.read_exact(&mut header[4..])?;
+
+        let block_type = self.u32(&header[..4]);
+        if block_type == BLOCK_SHB {
+            let mut magic = [0; 4];
+            self.reader.read_exact(&mut magic)?;
+            self.big_endian = match (
+                u32::from_le_bytes(magic) == BYTE_ORDER_MAGIC,
+                u32::from_be_bytes(magic) == BYTE_ORDER_MAGIC,
+            ) {
+                (true, _) => false,
+                (_, true) => true,
+                _ => return Err(invalid_data("invalid section header byte-order magic")),
+            };
+            self.interfaces.clear();
+
+            let len = self.u32(&header[4..]) as usize;
+            if len < 16 {
+                return Err(invalid_data("invalid block length"));
+            }
+            io::copy(
+                &mut self.reader.by_ref().take(len as u64 - 12),
+                &mut io::sink(),
+            )?;
+            return Ok(true);
+        }
+
+        let len = self.u32(&header[4..]) as usize;
+        if len < 12 || len % 4 != 0 {
+            return Err(invalid_data("invalid block length"));
+        }
+        let mut body = vec![0; len - 8];
+        self.reader.read_exact(&mut body)?;
+        let body = &body[..len - 12]; // strip trailing block length
+
+        let data = match block_type {
+            BLOCK_IDB if body.len() >= 2 => {
+                self.interfaces.push(self.u16(body));
+                return Ok(true);
+            }
+            BLOCK_EPB if body.len() >= 20 => {
+                let interface = self.u32(body) as usize;
+                let captured = self.u32(&body[12..]) as usize;
+                if self.interfaces.get(interface) != Some(&LINKTYPE_ITM) {
+                    return Ok(true);
+                }
+                body.get(20..20 + captured)
+            }
+            BLOCK_SPB if body.len() >= 4 => {
+                let original = self.u32(body) as usize;
+                if self.interfaces.first() != Some(&LINKTYPE_ITM) {
+                    return Ok(true);
+                }
+                body.get(4..(4 + original).min(body.len()))
+            }
+            BLOCK_IDB | BLOCK_EPB | BLOCK_SPB => {
+                return Err(invalid_data("truncated block"));
+            }
+            _ => return Ok(true),
+        };
+
+        self.data = data
+            .ok_or_else(|| invalid_data("packet data exceeds block"))?
+            .to_vec();
+        self.pos = 0;
+        Ok(true)
+    }
+}
+
+impl<R> Read for PcapngReader<R>
+where
+    R: Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.data.len() {
+            if !self.next_block()? {
+                return Ok(0);
+            }
+        }
+
+        let n = buf.len().min(self.data.len() - self.pos);
+        buf[..n].copy_from_slice(&self.data[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("pcapng: {}", msg))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    /// Returns the Enhanced Packet Blocks of `pcapng`.
+    fn epbs(pcapng: &[u8]) -> Vec<&[u8]> {
+        let mut blocks = vec![];
+        let mut rest = pcapng;
+        while !rest.is_empty() {
+            let len = u32::from_le_bytes(rest[4..8].try_into().unwrap()) as usize;
+            blocks.push(&rest[..len]);
+            rest = &rest[len..];
+        }
+
+        blocks
+            .into_iter()
+            .filter(|b| b[..4] == BLOCK_EPB.to_le_bytes())
+            .collect()
+    }
+
+    #[test]
+    fn packet_blocks() {
+        let mut pcapng = PcapngWriter::new(vec![]).unwrap();
+        let timestamp = Timestamp::Sync(Duration::from_nanos(0x1_0000_0002));
+        for raw in [&[0x70][..], &[0x0b, 0x01, 0x02, 0x03, 0x04], &[0x0b]] {
+            pcapng.write(raw, Some(&timestamp), "packet").unwrap();
+        }
+        let pcapng = pcapng.finish().unwrap();
+
+        let epbs = epbs(&pcapng);
+        assert_eq!(epbs.len(), 3);
+        assert_eq!(&epbs[1][12..20], [1, 0, 0, 0, 2, 0, 0, 0]);
+        assert_eq!(&epbs[1][20..28], [5, 0, 0, 0, 5, 0, 0, 0]);
+        assert_eq!(&epbs[1][28..33], [0x0b, 0x01, 0x02, 0x03, 0x04]);
+    }
+
+    #[test]
+    fn set_blocks() {
+        let mut pcapng = PcapngWriter::new(vec![]).unwrap();
+        let timestamp = Timestamp::Sync(Duration::from_nanos(3));
+        // NOTE(set) Overflow, Instrumentation, and LocalTimestamp2
+        pcapng
+            .write_set(&[0x70, 0x0b, 0x01, 0x02, 0x03, 0x04, 0x30], &timestamp)
+            .unwrap();
+        let pcapng = pcapng.finish().unwrap();
+
+        let mut read = vec![];
+        PcapngReader::new(pcapng.as_slice())
+            .read_to_end(&mut read)
+            .unwrap();
+        assert_eq!(read, [0x70, 0x0b, 0x01, 0x02, 0x03, 0x04, 0x30]);
+
+        let epbs = epbs(&pcapng);
+        assert_eq!(epbs.len(), 3);
+        assert!(epbs
+            .iter()
+            .all(|epb| epb[12..20] == [0, 0, 0, 0, 3, 0, 0, 0]));
+        assert_eq!(&epbs[1][20..28], [5, 0, 0, 0, 5, 0, 0, 0]);
+    }
+
+    #[test]
+    fn roundtrip() {
+        let raw: Vec<u8> = (0..100).collect();
+        let mut pcapng = PcapngWriter::new(vec![]).unwrap();
+        for chunk in raw.chunks(7) {
+            pcapng.write(chunk, None, "").unwrap();
+        }
+        let pcapng = pcapng.finish().unwrap();
+
+        let mut read = vec![];
+        PcapngReader::new(pcapng.as_slice())
+            .read_to_end(&mut read)
+            .unwrap();
+        assert_eq!(read, raw);
+    }
+}
@@ -0,0 +1,360 @@
+//! Pluggable decoders of the payloads written to the stimulus ports.
+//!
+//! Different stimulus ports often carry different protocols: text on
+//! one port, binary counters on another, task markers on a third. A
+//! [`PortDecoder`] turns the payloads written to a port into typed
+//! events, and [`PortDecoders`] dispatches the
+//! [`Instrumentation`](TracePacket::Instrumentation) packets of a
+//! stream to the decoder attached to the packet's port.
+//!
+//! ```
+//! use itm::{PortDecoder, PortDecoders, StimulusLines, Timestamp, TracePacket};
+//!
+//! #[derive(Debug, PartialEq)]
+//! enum Event {
+//!     Log(String),
+//!     Counter(u32),
+//! }
+//!
+//! let mut decoders = PortDecoders::new();
+//! decoders
+//!     .attach(0, StimulusLines::new().map_events(|l| Event::Log(l.text.unwrap())))
+//!     .unwrap();
+//! decoders
+//!     .attach(1..=2, |_port: u8, payload: &[u8], _ts: Option<&Timestamp>| {
+//!         let mut bytes = [0; 4];
+//!         bytes[..payload.len()].copy_from_slice(payload);
+//!         vec![Event::Counter(u32::from_le_bytes(bytes))]
+//!     })
+//!     .unwrap();
+//!
+//! let packet = TracePacket::Instrumentation { port: 2, payload: vec![42, 0, 0, 0] };
+//! assert_eq!(decoders.decode(&packet, None), [Event::Counter(42)]);
+//! ```
+
+use super::{StimulusLine, StimulusLines, Timestamp, TimestampedTracePackets, TracePacket};
+
+use std::ops::{Bound, RangeBounds, RangeInclusive};
+
+/// A decoder of the payloads written to one or more stimulus ports.
+pub trait PortDecoder {
+    /// The type of event yielded by this decoder.
+    type Event;
+
+    /// Decodes a `payload` written to stimulus `port`, yielding the
+    /// events this completed, if any. `timestamp` is the timestamp of
+    /// the packet if decoding over [`Timestamps`](crate::Timestamps).
+    fn decode(
+        &mut self,
+        port: u8,
+        payload: &[u8],
+        timestamp: Option<&Timestamp>,
+    ) -> Vec<Self::Event>;
+
+    /// Yields any events still pending once the trace stream has
+    /// ended.
+    fn flush(&mut self) -> Vec<Self::Event> {
+        vec![]
+    }
+
+    /// Maps the events of this decoder with `f`. Useful to attach
+    /// decoders with different event types to the same
+    /// [`PortDecoders`].
+    fn map_events<F, E>(self, f: F) -> MapEvents<Self, F>
+    where
+        Self: Sized,
+        F: FnMut(Self::Event) -> E,
+    {
+        MapEvents { decoder: self, f }
+    }
+}
+
+impl<E, F> PortDecoder for F
+where
+    F: FnMut(u8, &[u8], Option<&Timestamp>) -> Vec<E>,
+{
+    type Event = E;
+
+    fn decode(&mut self, port: u8, payload: &[u8], timestamp: Option<&Timestamp>) -> Vec<E> {
+        self(port, payload, timestamp)
+    }
+}
+
+impl PortDecoder for StimulusLines {
+    type Event = StimulusLine;
+
+    fn decode(&mut self, port: u8, payload: &[u8], _: Option<&Timestamp>) -> Vec<StimulusLine> {
+        self.push(port, payload)
+    }
+
+    fn flush(&mut self) -> Vec<StimulusLine> {
+        StimulusLines::flush(self)
+    }
+}
+
+/// A [`PortDecoder`] with mapped events. See
+/// [`PortDecoder::map_events`].
+pub struct MapEvents<D, F> {
+    decoder: D,
+    f: F,
+}
+
+impl<D, F, E> PortDecoder for MapEvents<D, F>
+where
+    D: PortDecoder,
+    F: FnMut(D::Event) -> E,
+{
+    type Event = E;
+
+    fn decode(&mut self, port: u8, payload: &[u8], timestamp: Option<&Timestamp>) -> Vec<E> {
+        self.decoder
+            .decode(port, payload, timestamp)
+            .into_iter()
+            .map(&mut self.f)
+            .collect()
+    }
+
+    fn flush(&mut self) -> Vec<E> {
+        self.decoder.flush().into_iter().map(&mut self.f).collect()
+    }
+}
+
+/// Possible errors on [`PortDecoders::attach`].
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+#[non_exhaustive]
+pub enum PortDecoderError {
+    #[error("The port range is empty")]
+    EmptyRange,
+    #[error("Port {0} already has a decoder attached")]
+    Occupied(u8),
+}
+
+/// A registry of [`PortDecoder`]s, each attached to a range of
+/// stimulus ports, all yielding events of type `E`.
+pub struct PortDecoders<'a, E> {
+    decoders: Vec<(RangeInclusive<u8>, Box<dyn PortDecoder<Event = E> + 'a>)>,
+}
+
+impl<'a, E> Default for PortDecoders<'a, E> {
+    fn default() -> Self {
+        Self { decoders: vec![] }
+    }
+}
+
+impl<'a, E> PortDecoders<'a, E> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Attaches `decoder` to the given stimulus `ports`: a single port
+    /// (`3`) or a range of ports (`8..16`). A port can only have a
+    /// single decoder attached.
+    pub fn attach<P, D>(&mut self, ports: P, decoder: D) -> Result<(), PortDecoderError>
+    where
+        P: IntoPortRange,
+        D: PortDecoder<Event = E> + 'a,
+    {
+        let ports = ports
+            .into_port_range()
+            .ok_or(PortDecoderError::EmptyRange)?;
+        if let Some(port) = ports
+            .clone()
+            .find(|port| self.decoders.iter().any(|(r, _)| r.contains(port)))
+        {
+            return Err(PortDecoderError::Occupied(port));
+        }
+
+        self.decoders.push((ports, Box::new(decoder)));
+        Ok(())
+    }
+
+    /// Dispatches `packet` to the decoder attached to its port and
+    /// returns the yielded events. Packets other than
+    /// [`Instrumentation`](TracePacket::Instrumentation) packets, and
+    /// packets written to ports without a decoder, are ignored.
+    pub fn decode(&mut self, packet: &TracePacket, timestamp: Option<&Timestamp>) -> Vec<E> {
+        match packet {
+            TracePacket::Instrumentation { port, payload } => {
+                match self.decoders.iter_mut().find(|(r, _)| r.contains(port)) {
+                    Some((_, decoder)) => decoder.decode(*port, payload, timestamp),
+                    None => vec![],
+                }
+            }
+            _ => vec![],
+        }
+    }
+
+    /// Like [`decode`](Self::decode), but for all packets of a
+    /// [`TimestampedTracePackets`].
+    pub fn decode_timestamped(&mut self, packets: &TimestampedTracePackets) -> Vec<E> {
+        packets
+            .packets
+            .iter()
+            .flat_map(|packet| self.decode(packet, Some(&packets.timestamp)))
+            .collect()
+    }
+
+    /// Yields the pending events of all decoders. Should be called
+    /// once the trace stream has ended.
+    pub fn flush(&mut self) -> Vec<E> {
+        self.decoders
+            .iter_mut()
+            .flat_map(|(_, decoder)| decoder.flush())
+            .collect()
+    }
+}
+
+/// Stimulus port selections accepted by [`PortDecoders::attach`].
+pub trait IntoPortRange {
+    /// Returns the selected ports, or `None` if the selection is
+    /// empty.
+    fn into_port_range(self) -> Option<RangeInclusive<u8>>;
+}
+
+impl IntoPortRange for u8 {
+    fn into_port_range(self) -> Option<RangeInclusive<u8>> {
+        Some(self..=self)
+    }
+}
+
+macro_rules! into_port_range {
+    ($($range:ty),*) => {
+        $(
+            impl IntoPortRange for $range {
+                fn into_port_range(self) -> Option<RangeInclusive<u8>> {
+                    let start = match RangeBounds::<u8>::start_bound(&self) {
+                        Bound::Included(s) => *s,
+                        Bound::Excluded(s) => s.checked_add(1)?,
+                        Bound::Unbounded => 0,
+                    };
+                    let end = match RangeBounds::<u8>::end_bound(&self) {
+                        Bound::Included(e) => *e,
+                        Bound::Excluded(e) => e.checked_sub(1)?,
+                        Bound::Unbounded => u8::MAX,
+                    };
+
+                    if start > end {
+                        None
+                    } else {
+                        Some(start..=end)
+                    }
+                }
+            }
+        )*
+    };
+}
+
+into_port_range!(
+    std::ops::Range<u8>,
+    std::ops::RangeInclusive<u8>,
+    std::ops::RangeFrom<u8>,
+    std::ops::RangeTo<u8>,
+    std::ops::RangeToInclusive<u8>,
+    std::ops::RangeFull
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    enum Event {
+        Line(u8, String),
+        Marker(u8, u8),
+    }
+
+    #[test]
+    fn dispatch() {
+        let mut decoders = PortDecoders::new();
+        decoders
+            .attach(
+                0,
+                StimulusLines::new().map_events(|l| Event::Line(l.port, l.text.unwrap())),
+            )
+            .unwrap();
+        decoders
+            .attach(4..8, |port: u8, payload: &[u8], _: Option<&Timestamp>| {
+                vec![Event::Marker(port, payload[0])]
+            })
+            .unwrap();
+
+        assert!(decoders
+            .decode(
+                &TracePacket::Instrumentation {
+                    port: 0,
+                    payload: b"hi".to_vec(),
+                },
+                None
+            )
+            .is_empty());
+        assert_eq!(
+            decoders.decode(
+                &TracePacket::Instrumentation {
+                    port: 7,
+                    payload: vec![1],
+                },
+                None
+            ),
+            [Event::Marker(7, 1)]
+        );
+        assert!(decoders
+            .decode(
+                &TracePacket::Instrumentation {
+                    port: 8,
+                    payload: vec![1],
+                },
+                None
+            )
+            .is_empty());
+        assert!(decoders.decode(&TracePacket::Overflow, None).is_empty());
+        assert_eq!(
+            decoders.decode_timestamped(&TimestampedTracePackets {
+                timestamp: Timestamp::Sync(Default::default()),
+                packets: vec![
+                    TracePacket::Instrumentation {
+                        port: 0,
+                        payload: b"!\n".to_vec(),
+                    },
+                    TracePacket::Instrumentation {
+                        port: 5,
+                        payload: vec![2],
+                    },
+                ],
+                malformed_packets: vec![],
+                consumed_packets: 3,
+            }),
+            [Event::Line(0, "hi!".to_string()), Event::Marker(5, 2)]
+        );
+
+        decoders.decode(
+            &TracePacket::Instrumentation {
+                port: 0,
+                payload: b"bye".to_vec(),
+            },
+            None,
+        );
+        assert_eq!(decoders.flush(), [Event::Line(0, "bye".to_string())]);
+    }
+
+    #[test]
+    fn attach() {
+        let noop = |_: u8, _: &[u8], _: Option<&Timestamp>| -> Vec<()> { vec![] };
+        let mut decoders = PortDecoders::new();
+
+        assert_eq!(
+            decoders.attach(3..3, noop),
+            Err(PortDecoderError::EmptyRange)
+        );
+        assert_eq!(decoders.attach(2..=4, noop), Ok(()));
+        assert_eq!(
+            decoders.attach(4.., noop),
+            Err(PortDecoderError::Occupied(4))
+        );
+        assert_eq!(decoders.attach(..2, noop), Ok(()));
+        assert_eq!(decoders.attach(5.., noop), Ok(()));
+        assert_eq!(
+            decoders.attach(.., noop),
+            Err(PortDecoderError::Occupied(0))
+        );
+    }
+}
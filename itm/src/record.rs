@@ -0,0 +1,277 @@
+//! Recording of the raw bytes read from a trace source while it is
+//! being decoded.
+//!
+//! [`Tee`] wraps the [`Read`] given to a [`Decoder`](crate::Decoder)
+//! and writes every byte read from it to a recording, in one of two
+//! [`RecordFormat`]s:
+//!
+//! - [`Raw`](RecordFormat::Raw): the bytes as-is, e.g. to be decoded
+//! again later;
+//! - [`Timestamped`](RecordFormat::Timestamped): the bytes in chunks as
+//! they were received, each prefixed with the time of reception on the
+//! host. Read back by [`RecordingReader`].
+//!
+//! ```
+//! use itm::{record::{RecordFormat, Tee}, Decoder, DecoderOptions};
+//!
+//! let stream: &[u8] = &[0x70, 0x0b, 0x01, 0x02, 0x03, 0x04];
+//! let tee = Tee::new(stream, vec![], RecordFormat::Raw).unwrap();
+//! let mut singles = Decoder::new(tee, DecoderOptions { ignore_eof: false }).singles();
+//! while let Some(_packet) = singles.next() {}
+//! assert_eq!(singles.get_mut().get_ref().1, stream);
+//! ```
+//!
+//! A timestamped recording starts with the 8-byte magic
+//! [`RECORDING_MAGIC`], followed by chunks of a 12-byte header and the
+//! chunk data. The header holds the reception time in nanoseconds since
+//! the UNIX epoch as a little-endian `u64`, and the length of the data as
+//! a little-endian `u32`.
+
+use std::io::{self, Read, Write};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The first bytes of a [timestamped](RecordFormat::Timestamped)
+/// recording: `ITMREC`, a zero byte, and the format version.
+pub const RECORDING_MAGIC: [u8; 8] = *b"ITMREC\x00\x01";
+
+const CHUNK_HEADER_SIZE: usize = 12;
+
+/// Layout of a recording. See the [module documentation](self).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RecordFormat {
+    Raw,
+    Timestamped,
+}
+
+/// Writes all bytes read from a [`Read`] to a [`Write`].
+///
+/// Bytes are written as soon as they are read, without buffering, so
+/// that a recording is complete up to the last read even if the process
+/// is killed. A timestamped chunk is written at once, header and data;
+/// should it still be cut short, [`RecordingReader`] returns what was
+/// written of it. Wrap `writer` in a [`BufWriter`](std::io::BufWriter)
+/// to trade this for fewer writes.
+pub struct Tee<R, W>
+where
+    R: Read,
+    W: Write,
+{
+    reader: R,
+    writer: W,
+    format: RecordFormat,
+}
+
+impl<R, W> Tee<R, W>
+where
+    R: Read,
+    W: Write,
+{
+    /// Records the bytes read from `reader` to `writer` in `format`.
+    /// Writes the magic of a timestamped recording.
+    pub fn new(reader: R, mut writer: W, format: RecordFormat) -> io::Result<Self> {
+        if format == RecordFormat::Timestamped {
+            writer.write_all(&RECORDING_MAGIC)?;
+        }
+
+        Ok(Self {
+            reader,
+            writer,
+            format,
+        })
+    }
+
+    /// Returns references to the underlying [`Read`](Read) and
+    /// [`Write`](Write).
+    pub fn get_ref(&self) -> (&R, &W) {
+        (&self.reader, &self.writer)
+    }
+
+    /// Flushes the recording and returns the underlying [`Read`](Read)
+    /// and [`Write`](Write).
+    pub fn into_inner(mut self) -> io::Result<(R, W)> {
+        self.writer.flush()?;
+        Ok((self.reader, self.writer))
+    }
+}
+
+impl<R, W> Read for Tee<R, W>
+where
+    R: Read,
+    W: Write,
+{
+    /// Reads from the underlying reader and records the bytes read. A
+    /// failure to record is returned as an error, although the bytes
+    /// were read.
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.reader.read(buf)?;
+        if n == 0 {
+            return Ok(0);
+        }
+
+        match self.format {
+            RecordFormat::Raw => self.writer.write_all(&buf[..n])?,
+            RecordFormat::Timestamped => {
+                let received = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default();
+                let mut chunk = Vec::with_capacity(CHUNK_HEADER_SIZE + n);
+                chunk.extend((received.as_nanos() as u64).to_le_bytes());
+                chunk.extend((n as u32).to_le_bytes());
+                chunk.extend_from_slice(&buf[..n]);
+                self.writer.write_all(&chunk)?;
+            }
+        }
+
+        Ok(n)
+    }
+}
+
+/// A chunk of a [timestamped](RecordFormat::Timestamped) recording.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chunk {
+    /// When the chunk was received, since the UNIX epoch.
+    pub received: Duration,
+    pub data: Vec<u8>,
+}
+
+/// Reads a [timestamped](RecordFormat::Timestamped) recording, either
+/// [chunk by chunk](Self::next_chunk) or, through [`Read`], as the
+/// recorded trace stream.
+pub struct RecordingReader<R>
+where
+    R: Read,
+{
+    reader: R,
+    magic_read: bool,
+
+    /// Data of the current chunk not yet read.
+    data: Vec<u8>,
+    pos: usize,
+}
+
+impl<R> RecordingReader<R>
+where
+    R: Read,
+{
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            magic_read: false,
+            data: vec![],
+            pos: 0,
+        }
+    }
+
+    /// Returns the next chunk of the recording, or `None` at the end of
+    /// it. Fails if the recording does not start with
+    /// [`RECORDING_MAGIC`].
+    ///
+    /// A recording that ends within a chunk, e.g. because the recording
+    /// process was killed, ends with the data read of that chunk; a
+    /// partial chunk header is ignored.
+    pub fn next_chunk(&mut self) -> io::Result<Option<Chunk>> {
+        if !self.magic_read {
+            let mut magic = [0; RECORDING_MAGIC.len()];
+            self.reader.read_exact(&mut magic)?;
+            if magic != RECORDING_MAGIC {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "not a timestamped ITM recording",
+                ));
+            }
+            self.magic_read = true;
+        }
+
+        let mut header = [0; CHUNK_HEADER_SIZE];
+        match self.reader.read_exact(&mut header) {
+            Ok(()) => (),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+        let received = u64::from_le_bytes(header[..8].try_into().unwrap());
+        let len = u32::from_le_bytes(header[8..].try_into().unwrap());
+
+        let mut data = Vec::with_capacity(len as usize);
+        self.reader
+            .by_ref()
+            .take(len as u64)
+            .read_to_end(&mut data)?;
+
+        Ok(Some(Chunk {
+            received: Duration::from_nanos(received),
+            data,
+        }))
+    }
+}
+
+impl<R> Read for RecordingReader<R>
+where
+    R: Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.data.len() {
+            match self.next_chunk()? {
+                Some(chunk) => {
+                    self.data = chunk.data;
+                    self.pos = 0;
+                }
+                None => return Ok(0),
+            }
+        }
+
+        let n = buf.len().min(self.data.len() - self.pos);
+        buf[..n].copy_from_slice(&self.data[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timestamped_roundtrip() {
+        let stream: &[u8] = &[0x70, 0x0b, 0x01, 0x02, 0x03, 0x04];
+        let mut tee = Tee::new(stream, vec![], RecordFormat::Timestamped).unwrap();
+        let mut buf = [0; 4];
+        tee.read_exact(&mut buf).unwrap();
+        let mut rest = vec![];
+        tee.read_to_end(&mut rest).unwrap();
+        let (_, recording) = tee.into_inner().unwrap();
+
+        let mut reader = RecordingReader::new(recording.as_slice());
+        let first = reader.next_chunk().unwrap().unwrap();
+        let second = reader.next_chunk().unwrap().unwrap();
+        assert_eq!(first.data, [0x70, 0x0b, 0x01, 0x02]);
+        assert_eq!(second.data, [0x03, 0x04]);
+        assert!(first.received <= second.received);
+        assert!(reader.next_chunk().unwrap().is_none());
+
+        let mut data = vec![];
+        RecordingReader::new(recording.as_slice())
+            .read_to_end(&mut data)
+            .unwrap();
+        assert_eq!(data, stream);
+    }
+
+    #[test]
+    fn truncated_chunk() {
+        let stream: &[u8] = &[0x70, 0x0b, 0x01, 0x02];
+        let mut tee = Tee::new(stream, vec![], RecordFormat::Timestamped).unwrap();
+        io::copy(&mut tee, &mut io::sink()).unwrap();
+        let (_, mut recording) = tee.into_inner().unwrap();
+        recording.truncate(recording.len() - 1);
+
+        let mut data = vec![];
+        RecordingReader::new(recording.as_slice())
+            .read_to_end(&mut data)
+            .unwrap();
+        assert_eq!(data, [0x70, 0x0b, 0x01]);
+
+        // NOTE(header) not even the chunk length was written
+        recording.truncate(RECORDING_MAGIC.len() + CHUNK_HEADER_SIZE - 1);
+        let mut reader = RecordingReader::new(recording.as_slice());
+        assert!(reader.next_chunk().unwrap().is_none());
+    }
+}
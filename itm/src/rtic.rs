@@ -0,0 +1,426 @@
+//! [RTIC](https://rtic.rs) task dispatch tracing.
+//!
+//! Reconstructs an RTIC task timeline from the trace stream, as done by
+//! [RTIC Scope](https://github.com/rtic-scope):
+//!
+//! - hardware tasks are bound to interrupts and are traced via the
+//! [`ExceptionTrace`](TracePacket::ExceptionTrace) packets of their
+//! interrupt;
+//! - software tasks are run by dispatchers and are traced by markers
+//! written to stimulus ports: on entry, the task's ID is written to the
+//! [`enter`](RticConfiguration::software_enter_port) port and on exit
+//! to the [`exit`](RticConfiguration::software_exit_port) port.
+//!
+//! [`RticTracer`] maps these packets to [`TaskEvent`]s with the task's
+//! name and priority, inferring preemption from the order in which
+//! tasks start and stop: a task that starts while a task of lower
+//! priority is running preempts it. A task cannot preempt a task of the
+//! same or higher priority, which must then have stopped unseen.
+
+use super::{ExceptionAction, Timestamp, TimestampedTracePackets, TracePacket, VectActive};
+
+use std::collections::BTreeMap;
+
+/// Description of an RTIC task.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TaskInfo {
+    /// The name of the task.
+    pub name: String,
+
+    /// The priority of the task.
+    pub priority: u8,
+}
+
+/// [`RticTracer`] configuration.
+#[derive(Debug, Clone, Default)]
+pub struct RticConfiguration {
+    /// Hardware tasks and the interrupt each task is bound to.
+    pub hardware_tasks: Vec<(VectActive, TaskInfo)>,
+
+    /// Software tasks and the ID each task writes to the
+    /// [`enter`](Self::software_enter_port) and
+    /// [`exit`](Self::software_exit_port) ports.
+    pub software_tasks: BTreeMap<u32, TaskInfo>,
+
+    /// Stimulus port a software task writes its ID to on entry.
+    pub software_enter_port: u8,
+
+    /// Stimulus port a software task writes its ID to on exit. Must
+    /// differ from the enter port.
+    pub software_exit_port: u8,
+}
+
+/// What happened to a task.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TaskAction {
+    /// The task started executing.
+    Started,
+
+    /// The task was preempted by a task of higher priority.
+    Preempted,
+
+    /// The task resumed execution after the preempting task stopped.
+    Resumed,
+
+    /// The task finished executing.
+    Stopped,
+}
+
+/// An RTIC task event.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TaskEvent {
+    /// The task the event relates to.
+    pub task: TaskInfo,
+
+    /// What happened to the task.
+    pub action: TaskAction,
+
+    /// Timestamp of the packet that generated the event, if decoding
+    /// over [`Timestamps`](crate::Timestamps).
+    pub timestamp: Option<Timestamp>,
+}
+
+/// Possible errors on [`RticTracer`] construction and push.
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+#[non_exhaustive]
+pub enum RticError {
+    #[error("Unknown software task ID {id} written to stimulus port {port}")]
+    UnknownTaskId {
+        /// The stimulus port the ID was written to.
+        port: u8,
+
+        /// The unknown task ID.
+        id: u32,
+    },
+    #[error("Task {0} stopped without having been started")]
+    NotStarted(String),
+    #[error("Task {0} was not seen stopping before a task of the same or lower priority started")]
+    NotStopped(String),
+    #[error("Stimulus port {0} is configured as both the enter and exit port")]
+    SharedPort(u8),
+}
+
+/// Maps trace packets to RTIC [`TaskEvent`]s. See the [module
+/// documentation](self).
+pub struct RticTracer {
+    config: RticConfiguration,
+
+    /// The tasks that have started but not yet stopped, in order of
+    /// start. The last task is the one currently running.
+    running: Vec<TaskInfo>,
+}
+
+impl RticTracer {
+    pub fn new(config: RticConfiguration) -> Result<Self, RticError> {
+        if config.software_enter_port == config.software_exit_port {
+            return Err(RticError::SharedPort(config.software_enter_port));
+        }
+
+        Ok(Self {
+            config,
+            running: vec![],
+        })
+    }
+
+    /// The tasks that have started but not yet stopped, in order of
+    /// start.
+    pub fn running(&self) -> &[TaskInfo] {
+        &self.running
+    }
+
+    /// Maps `packet` to the task events it signifies, if any.
+    /// Exceptions without a bound hardware task (e.g. the dispatchers)
+    /// and instrumentation packets written to other ports than the
+    /// configured ones are ignored.
+    pub fn push(
+        &mut self,
+        packet: &TracePacket,
+        timestamp: Option<&Timestamp>,
+    ) -> Vec<Result<TaskEvent, RticError>> {
+        match packet {
+            TracePacket::ExceptionTrace { exception, action } => {
+                let task = match self
+                    .config
+                    .hardware_tasks
+                    .iter()
+                    .find(|(irq, _)| irq == exception)
+                {
+                    Some((_, task)) => task.clone(),
+                    None => return vec![],
+                };
+
+                match action {
+                    ExceptionAction::Entered => self.start(task, timestamp),
+                    ExceptionAction::Exited => self.stop(task, timestamp),
+                    // Resumption is inferred when the preempting task
+                    // stops.
+                    ExceptionAction::Returned => vec![],
+                }
+            }
+            TracePacket::Instrumentation { port, payload }
+                if *port == self.config.software_enter_port
+                    || *port == self.config.software_exit_port =>
+            {
+                let id = payload
+                    .iter()
+                    .rev()
+                    .fold(0u32, |id, b| (id << 8) | *b as u32);
+                let task = match self.config.software_tasks.get(&id) {
+                    Some(task) => task.clone(),
+                    None => return vec![Err(RticError::UnknownTaskId { port: *port, id })],
+                };
+
+                if *port == self.config.software_enter_port {
+                    self.start(task, timestamp)
+                } else {
+                    self.stop(task, timestamp)
+                }
+            }
+            _ => vec![],
+        }
+    }
+
+    /// Like [`push`](Self::push), but for all packets of a
+    /// [`TimestampedTracePackets`].
+    pub fn push_timestamped(
+        &mut self,
+        packets: &TimestampedTracePackets,
+    ) -> Vec<Result<TaskEvent, RticError>> {
+        packets
+            .packets
+            .iter()
+            .flat_map(|packet| self.push(packet, Some(&packets.timestamp)))
+            .collect()
+    }
+
+    fn start(
+        &mut self,
+        task: TaskInfo,
+        timestamp: Option<&Timestamp>,
+    ) -> Vec<Result<TaskEvent, RticError>> {
+        let mut events = vec![];
+        while self
+            .running
+            .last()
+            .is_some_and(|running| running.priority >= task.priority)
+        {
+            let stopped = self.running.pop().unwrap();
+            events.push(Err(RticError::NotStopped(stopped.name)));
+        }
+        if let Some(preempted) = self.running.last() {
+            events.push(Ok(Self::event(
+                preempted.clone(),
+                TaskAction::Preempted,
+                timestamp,
+            )));
+        }
+        self.running.push(task.clone());
+        events.push(Ok(Self::event(task, TaskAction::Started, timestamp)));
+
+        events
+    }
+
+    fn stop(
+        &mut self,
+        task: TaskInfo,
+        timestamp: Option<&Timestamp>,
+    ) -> Vec<Result<TaskEvent, RticError>> {
+        let pos = match self.running.iter().rposition(|t| *t == task) {
+            Some(pos) => pos,
+            None => return vec![Err(RticError::NotStarted(task.name))],
+        };
+        let was_running = pos == self.running.len() - 1;
+        self.running.remove(pos);
+
+        let mut events = vec![Ok(Self::event(task, TaskAction::Stopped, timestamp))];
+        if was_running {
+            if let Some(resumed) = self.running.last() {
+                events.push(Ok(Self::event(
+                    resumed.clone(),
+                    TaskAction::Resumed,
+                    timestamp,
+                )));
+            }
+        }
+
+        events
+    }
+
+    fn event(task: TaskInfo, action: TaskAction, timestamp: Option<&Timestamp>) -> TaskEvent {
+        TaskEvent {
+            task,
+            action,
+            timestamp: timestamp.cloned(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn preemption() {
+        use TaskAction::*;
+
+        let mut tracer = RticTracer::new(RticConfiguration {
+            hardware_tasks: vec![(
+                VectActive::Interrupt { irqn: 3 },
+                TaskInfo {
+                    name: "uart".to_string(),
+                    priority: 3,
+                },
+            )],
+            software_tasks: [(
+                0,
+                TaskInfo {
+                    name: "blink".to_string(),
+                    priority: 1,
+                },
+            )]
+            .into(),
+            software_enter_port: 1,
+            software_exit_port: 2,
+        })
+        .unwrap();
+        let mut events = vec![];
+        for packet in [
+            TracePacket::Instrumentation {
+                port: 1,
+                payload: vec![0],
+            },
+            // dispatcher: ignored
+            TracePacket::ExceptionTrace {
+                exception: VectActive::Interrupt { irqn: 10 },
+                action: ExceptionAction::Entered,
+            },
+            TracePacket::ExceptionTrace {
+                exception: VectActive::Interrupt { irqn: 3 },
+                action: ExceptionAction::Entered,
+            },
+            TracePacket::ExceptionTrace {
+                exception: VectActive::Interrupt { irqn: 3 },
+                action: ExceptionAction::Exited,
+            },
+            TracePacket::Instrumentation {
+                port: 2,
+                payload: vec![0],
+            },
+        ] {
+            events.extend(tracer.push(&packet, None));
+        }
+
+        let actions: Vec<_> = events
+            .into_iter()
+            .map(|e| e.unwrap())
+            .map(|e| (e.task.name, e.action))
+            .collect();
+        assert_eq!(
+            actions,
+            [
+                ("blink".to_string(), Started),
+                ("blink".to_string(), Preempted),
+                ("uart".to_string(), Started),
+                ("uart".to_string(), Stopped),
+                ("blink".to_string(), Resumed),
+                ("blink".to_string(), Stopped),
+            ]
+        );
+        assert!(tracer.running().is_empty());
+    }
+
+    #[test]
+    fn errors() {
+        let mut tracer = RticTracer::new(RticConfiguration {
+            software_tasks: [(
+                1,
+                TaskInfo {
+                    name: "log".to_string(),
+                    priority: 2,
+                },
+            )]
+            .into(),
+            software_enter_port: 1,
+            software_exit_port: 2,
+            ..Default::default()
+        })
+        .unwrap();
+
+        assert_eq!(
+            tracer.push(
+                &TracePacket::Instrumentation {
+                    port: 1,
+                    payload: vec![0x34, 0x12],
+                },
+                None
+            ),
+            [Err(RticError::UnknownTaskId {
+                port: 1,
+                id: 0x1234
+            })]
+        );
+        assert_eq!(
+            tracer.push(
+                &TracePacket::Instrumentation {
+                    port: 2,
+                    payload: vec![1],
+                },
+                None
+            ),
+            [Err(RticError::NotStarted("log".to_string()))]
+        );
+    }
+
+    #[test]
+    fn missed_stop() {
+        let blink = TaskInfo {
+            name: "blink".to_string(),
+            priority: 1,
+        };
+        let mut tracer = RticTracer::new(RticConfiguration {
+            software_tasks: [
+                (0, blink.clone()),
+                (
+                    1,
+                    TaskInfo {
+                        name: "log".to_string(),
+                        priority: 2,
+                    },
+                ),
+            ]
+            .into(),
+            software_enter_port: 1,
+            software_exit_port: 2,
+            ..Default::default()
+        })
+        .unwrap();
+        let enter = |id| TracePacket::Instrumentation {
+            port: 1,
+            payload: vec![id],
+        };
+
+        tracer.push(&enter(1), None);
+        let events = tracer.push(&enter(0), None);
+        assert_eq!(events[0], Err(RticError::NotStopped("log".to_string())));
+        assert_eq!(
+            events[1].as_ref().map(|e| (&e.task, &e.action)),
+            Ok((&blink, &TaskAction::Started))
+        );
+        assert_eq!(tracer.running(), [blink]);
+    }
+
+    #[test]
+    fn shared_port() {
+        assert!(matches!(
+            RticTracer::new(RticConfiguration {
+                software_enter_port: 1,
+                software_exit_port: 1,
+                ..Default::default()
+            }),
+            Err(RticError::SharedPort(1))
+        ));
+    }
+}
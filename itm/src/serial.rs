@@ -1,27 +1,46 @@
 //! Convenience module for serial device configuration.
 //!
-//! This module exposes a single function, [`configure`], used to
-//! configure a serial device with a wanted baud rate so that the device
-//! can be used with this crate. This functionality is used downstream
-//! in `itm-decode` and `cargo-rtic-scope`.
+//! This module exposes [`configure`], used to configure a serial device
+//! with a wanted baud rate so that the device can be used with this
+//! crate, [`configure_with`], used to also set the other parameters of
+//! the serial line given by a [`SerialConfig`], [`detect_baud`], used
+//! to find the baud rate of a trace when it is not known, and
+//! [`is_tty`], used to check whether a file is a serial device to begin
+//! with. [`SerialSession`] restores the original state of a configured
+//! device when it is no longer used. This functionality is used
+//! downstream in `itm-decode` and `cargo-rtic-scope`.
+//! [`PseudoTerminal`] stands in for a serial device, e.g. to replay a
+//! recorded trace to such tools.
 
 use nix::{
+    errno::Errno,
     fcntl::{self, FcntlArg, OFlag},
     libc,
+    poll::{self, PollFd, PollFlags},
+    pty,
     sys::termios::{
         self, ArbitraryBaudRate, BaudRate, ControlFlags, InputFlags, LocalFlags, OutputFlags,
         SetArg, SpecialCharacterIndices as CC,
     },
+    unistd,
 };
+use signal_hook::SigId;
 use std::fs;
-use std::os::unix::io::AsRawFd;
+use std::io::{self, Read, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
 use thiserror::Error;
 
+use super::{Decoder, DecoderError, DecoderOptions, TracePacket};
+
 mod ioctl {
     use super::libc;
     use nix::{ioctl_none_bad, ioctl_read_bad, ioctl_write_int_bad, ioctl_write_ptr_bad};
 
     ioctl_none_bad!(tiocexcl, libc::TIOCEXCL);
+    ioctl_none_bad!(tiocnxcl, libc::TIOCNXCL);
     ioctl_read_bad!(tiocmget, libc::TIOCMGET, libc::c_int);
     ioctl_read_bad!(fionread, libc::FIONREAD, libc::c_int);
     ioctl_write_ptr_bad!(tiocmset, libc::TIOCMSET, libc::c_int);
@@ -36,26 +55,143 @@ pub enum SerialError {
     General(String),
 }
 
-/// Opens and configures the given `device`.
+/// Number of data bits per character.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataBits {
+    Five,
+    Six,
+    Seven,
+    Eight,
+}
+
+/// Parity bit of each character.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Parity {
+    None,
+    Even,
+    Odd,
+    /// Always set.
+    Mark,
+    /// Always cleared.
+    Space,
+}
+
+/// Number of stop bits per character.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopBits {
+    One,
+    Two,
+}
+
+/// Flow control of the serial line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlowControl {
+    None,
+    /// XON/XOFF. The bytes 0x11 and 0x13 are then consumed by the
+    /// device, which corrupts a binary trace stream.
+    Software,
+    /// RTS/CTS.
+    Hardware,
+}
+
+/// What to do with a modem line of the device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModemLine {
+    /// Leave the line as it is.
+    Keep,
+    Assert,
+    Deassert,
+}
+
+/// Serial line parameters applied by [`configure_with`].
+///
+/// [`SerialConfig::new`] returns the parameters applied by
+/// [`configure`]: 8N1 without flow control, DTR and RTS asserted, and
+/// reads that return after 100 bytes or 0.2 s without a byte.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SerialConfig {
+    pub baud_rate: u32,
+    pub data_bits: DataBits,
+    pub parity: Parity,
+    pub stop_bits: StopBits,
+    pub flow_control: FlowControl,
+
+    /// Number of bytes a read waits for (`VMIN`). At most 255.
+    pub read_min_bytes: u8,
+
+    /// Time a read waits for the next byte (`VTIME`), rounded up to
+    /// whole tenths of a second. At most 25.5 s. If `read_min_bytes` is
+    /// zero, the time a read waits for any byte instead; a read then
+    /// returns immediately if both are zero.
+    pub read_timeout: Duration,
+
+    /// Data Terminal Ready, which e.g. powers some USB-UART bridges.
+    pub dtr: ModemLine,
+
+    /// Request To Send, which e.g. selects the direction of RS-485
+    /// adapters. Driven by the device with
+    /// [hardware flow control](FlowControl::Hardware).
+    pub rts: ModemLine,
+}
+
+impl SerialConfig {
+    /// Returns the default parameters with the given `baud_rate`.
+    pub fn new(baud_rate: u32) -> Self {
+        Self {
+            baud_rate,
+            data_bits: DataBits::Eight,
+            parity: Parity::None,
+            stop_bits: StopBits::One,
+            flow_control: FlowControl::None,
+            read_min_bytes: 100,
+            read_timeout: Duration::from_millis(200),
+            dtr: ModemLine::Assert,
+            rts: ModemLine::Assert,
+        }
+    }
+}
+
+/// Opens and configures the given `device` with the default
+/// [`SerialConfig`] of `baud_rate`.
 ///
 /// Effectively mirrors the behavior of
 /// ```shell,ignore
 /// $ screen <device> <baud rate>
 /// ```
 ///
+/// The file status flags of `device` are kept, except for `O_APPEND`
+/// and `O_ASYNC`, which are cleared. Previously, all status flags were
+/// cleared, so that e.g. a device opened with `O_NONBLOCK` now stays
+/// non-blocking.
+pub fn configure(device: &fs::File, baud_rate: u32) -> Result<(), SerialError> {
+    configure_with(device, &SerialConfig::new(baud_rate))
+}
+
+/// Configures the given `device` with `config`. Keeps the file status
+/// flags of `device` like [`configure`].
+///
 /// TODO ensure POSIX compliance, see termios(3)
 /// TODO We are currently using line disciple 0. Is that correct?
-pub fn configure(device: &fs::File, baud_rate: u32) -> Result<(), SerialError> {
+pub fn configure_with(device: &fs::File, config: &SerialConfig) -> Result<(), SerialError> {
     use SerialError as Error;
 
     // ensure a valid baud rate was requested
-    let baud_rate: BaudRate = ArbitraryBaudRate(baud_rate)
+    let baud_rate: BaudRate = ArbitraryBaudRate(config.baud_rate)
         .try_into()
-        .map_err(|_| Error::General(format!("{} is not a valid baud rate", baud_rate)))?;
+        .map_err(|_| Error::General(format!("{} is not a valid baud rate", config.baud_rate)))?;
     if baud_rate == BaudRate::B0 {
         return Err(Error::General("baud rate cannot be 0".to_string()));
     }
 
+    // NOTE(VTIME) in tenths of a second
+    let read_timeout = (config.read_timeout.as_millis() + 99) / 100;
+    let read_timeout: u8 = read_timeout.try_into().map_err(|_| {
+        Error::General(format!(
+            "read timeout of {:?} exceeds 25.5 s",
+            config.read_timeout
+        ))
+    })?;
+
     unsafe {
         let fd = device.as_raw_fd();
 
@@ -88,6 +224,13 @@ pub fn configure(device: &fs::File, baud_rate: u32) -> Result<(), SerialError> {
             | InputFlags::IXANY
             | InputFlags::IMAXBEL
             | InputFlags::IUTF8);
+        // NOTE(IGNPAR) characters with parity errors are discarded.
+        if config.parity != Parity::None {
+            settings.input_flags |= InputFlags::INPCK;
+        }
+        if config.flow_control == FlowControl::Software {
+            settings.input_flags |= InputFlags::IXON | InputFlags::IXOFF;
+        }
 
         settings.output_flags |= OutputFlags::NL0
             | OutputFlags::CR0
@@ -121,15 +264,11 @@ pub fn configure(device: &fs::File, baud_rate: u32) -> Result<(), SerialError> {
             | OutputFlags::VTDLY
             | OutputFlags::FFDLY);
 
-        settings.control_flags |= ControlFlags::CS6
-            | ControlFlags::CS7
-            | ControlFlags::CS8
-            | ControlFlags::CREAD
-            | ControlFlags::CLOCAL
-            | ControlFlags::CBAUDEX // NOTE also via cfsetspeed below
-            | ControlFlags::CSIZE;
+        // NOTE(CBAUDEX) also set via cfsetspeed below
+        settings.control_flags |=
+            ControlFlags::CREAD | ControlFlags::CLOCAL | ControlFlags::CBAUDEX;
         settings.control_flags &= !(ControlFlags::HUPCL
-            | ControlFlags::CS5
+            | ControlFlags::CSIZE
             | ControlFlags::CSTOPB
             | ControlFlags::PARENB
             | ControlFlags::PARODD
@@ -137,6 +276,25 @@ pub fn configure(device: &fs::File, baud_rate: u32) -> Result<(), SerialError> {
             | ControlFlags::CBAUD // NOTE also set via cfsetspeed below?
             | ControlFlags::CMSPAR
             | ControlFlags::CIBAUD);
+        settings.control_flags |= match config.data_bits {
+            DataBits::Five => ControlFlags::CS5,
+            DataBits::Six => ControlFlags::CS6,
+            DataBits::Seven => ControlFlags::CS7,
+            DataBits::Eight => ControlFlags::CS8,
+        };
+        settings.control_flags |= match config.parity {
+            Parity::None => ControlFlags::empty(),
+            Parity::Even => ControlFlags::PARENB,
+            Parity::Odd => ControlFlags::PARENB | ControlFlags::PARODD,
+            Parity::Mark => ControlFlags::PARENB | ControlFlags::CMSPAR | ControlFlags::PARODD,
+            Parity::Space => ControlFlags::PARENB | ControlFlags::CMSPAR,
+        };
+        if config.stop_bits == StopBits::Two {
+            settings.control_flags |= ControlFlags::CSTOPB;
+        }
+        if config.flow_control == FlowControl::Hardware {
+            settings.control_flags |= ControlFlags::CRTSCTS;
+        }
 
         settings.local_flags |= LocalFlags::ECHOKE
             | LocalFlags::ECHOE
@@ -161,8 +319,8 @@ pub fn configure(device: &fs::File, baud_rate: u32) -> Result<(), SerialError> {
             ))
         })?;
 
-        settings.control_chars[CC::VTIME as usize] = 2;
-        settings.control_chars[CC::VMIN as usize] = 100;
+        settings.control_chars[CC::VTIME as usize] = read_timeout;
+        settings.control_chars[CC::VMIN as usize] = config.read_min_bytes;
 
         // Drain all output, flush all input, and apply settings.
         termios::tcsetattr(fd, SetArg::TCSAFLUSH, &settings).map_err(|e| {
@@ -173,25 +331,48 @@ pub fn configure(device: &fs::File, baud_rate: u32) -> Result<(), SerialError> {
         })?;
 
         let mut flags: libc::c_int = 0;
-        ioctl::tiocmget(fd, &mut flags).map_err(|e| {
+        match ioctl::tiocmget(fd, &mut flags) {
+            // NOTE(ENOTTY) pseudo-terminals have no modem lines.
+            Err(Errno::ENOTTY) => (),
+            result => {
+                result.map_err(|e| {
+                    Error::General(format!(
+                        "Failed to read modem bits of device: tiocmget = {}",
+                        e
+                    ))
+                })?;
+                for (line, bit) in [(config.dtr, libc::TIOCM_DTR), (config.rts, libc::TIOCM_RTS)] {
+                    match line {
+                        ModemLine::Keep => (),
+                        ModemLine::Assert => flags |= bit,
+                        ModemLine::Deassert => flags &= !bit,
+                    }
+                }
+                ioctl::tiocmset(fd, &flags).map_err(|e| {
+                    Error::General(format!(
+                        "Failed to apply modem bits to device: tiocmset = {}",
+                        e
+                    ))
+                })?;
+            }
+        }
+
+        // Disable appending and signal-driven I/O. The other status
+        // flags are kept, e.g. O_NONBLOCK for reads that must not wait.
+        let flags = fcntl::fcntl(fd, FcntlArg::F_GETFL).map_err(|e| {
             Error::General(format!(
-                "Failed to read modem bits of device: tiocmget = {}",
+                "Failed to read status flags of device: fcntl = {}",
                 e
             ))
         })?;
-        flags |= libc::TIOCM_DTR | libc::TIOCM_RTS;
-        ioctl::tiocmset(fd, &flags).map_err(|e| {
+        let flags = OFlag::from_bits_truncate(flags) & !(OFlag::O_APPEND | OFlag::O_ASYNC);
+        fcntl::fcntl(fd, FcntlArg::F_SETFL(flags)).map_err(|e| {
             Error::General(format!(
-                "Failed to apply modem bits to device: tiocmset = {}",
+                "Failed to apply status flags to device: fcntl = {}",
                 e
             ))
         })?;
 
-        // Make the tty read-only.
-        fcntl::fcntl(fd, FcntlArg::F_SETFL(OFlag::O_RDONLY)).map_err(|e| {
-            Error::General(format!("Failed to make device read-only: fcntl = {}", e))
-        })?;
-
         // Flush all pending I/O, just in case.
         ioctl::tcflsh(fd, libc::TCIOFLUSH).map_err(|e| {
             Error::General(format!("Failed to flush I/O of device: tcflsh = {}", e))
@@ -201,6 +382,494 @@ pub fn configure(device: &fs::File, baud_rate: u32) -> Result<(), SerialError> {
     Ok(())
 }
 
+/// Baud rates commonly used for SWO, in increasing order. The default
+/// candidates of [`detect_baud`].
+pub const COMMON_BAUD_RATES: [u32; 12] = [
+    9600, 19200, 38400, 57600, 115200, 230400, 460800, 921600, 1000000, 2000000, 3000000, 4000000,
+];
+
+/// How well a window of bytes read at a baud rate decodes. See
+/// [`detect_baud`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct BaudScore {
+    pub baud_rate: u32,
+
+    /// The number of bytes read.
+    pub bytes: usize,
+
+    /// The number of packets decoded, including synchronization packets.
+    pub packets: usize,
+
+    /// The number of synchronization packets decoded.
+    pub syncs: usize,
+
+    /// The number of malformed packets encountered.
+    pub malformed: usize,
+}
+
+impl BaudScore {
+    /// Decodes `bytes`, read at `baud_rate`, and scores the result.
+    pub fn new(baud_rate: u32, bytes: &[u8]) -> Self {
+        let mut score = Self {
+            baud_rate,
+            bytes: bytes.len(),
+            packets: 0,
+            syncs: 0,
+            malformed: 0,
+        };
+        for packet in Decoder::new(bytes, DecoderOptions { ignore_eof: false }).singles() {
+            match packet {
+                Ok(TracePacket::Sync) => {
+                    score.packets += 1;
+                    score.syncs += 1;
+                }
+                Ok(_) => score.packets += 1,
+                Err(DecoderError::MalformedPacket(_)) => score.malformed += 1,
+                Err(DecoderError::Io(_)) => break,
+            }
+        }
+
+        score
+    }
+
+    /// Returns the ratio of malformed packets to all packets decoded.
+    pub fn malformed_rate(&self) -> f64 {
+        match self.packets + self.malformed {
+            0 => 1.0,
+            total => self.malformed as f64 / total as f64,
+        }
+    }
+
+    /// Returns the score: the ratio of well-formed packets, plus one if
+    /// a synchronization packet was decoded. As most bytes are valid
+    /// packet headers, garbage read at a wrong baud rate also mostly
+    /// decodes into well-formed packets, but is unlikely to contain the
+    /// 47 consecutive zero bits of a synchronization packet. Without
+    /// one, the score barely tells the candidates apart.
+    pub fn score(&self) -> f64 {
+        (1.0 - self.malformed_rate()) + if self.syncs > 0 { 1.0 } else { 0.0 }
+    }
+}
+
+/// Detects the baud rate of the trace received by `device` by
+/// [configuring](configure) it with each of the `candidates` in turn,
+/// reading for `window`, and [scoring](BaudScore::score) the bytes read.
+/// The device is left configured with the best candidate.
+///
+/// Returns the scores of all candidates, best first. Fails if no bytes
+/// were received at any candidate. The target must be tracing
+/// throughout; a window that contains synchronization packets, e.g.
+/// from a periodic synchronization of the DWT, gives the most reliable
+/// result.
+pub fn detect_baud(
+    device: &fs::File,
+    candidates: &[u32],
+    window: Duration,
+) -> Result<Vec<BaudScore>, SerialError> {
+    let mut scores = vec![];
+    for baud_rate in candidates {
+        configure(device, *baud_rate)?;
+        let bytes = read_window(device, window)?;
+        scores.push(BaudScore::new(*baud_rate, &bytes));
+    }
+
+    // NOTE(packets) among equal scores, prefer the baud rate at which
+    // the most was decoded.
+    scores.retain(|score| score.bytes > 0);
+    scores.sort_by(|a, b| {
+        b.score()
+            .total_cmp(&a.score())
+            .then(b.packets.cmp(&a.packets))
+    });
+    match scores.first() {
+        Some(best) => configure(device, best.baud_rate)?,
+        None => {
+            return Err(SerialError::General(
+                "No data was received at any candidate baud rate".to_string(),
+            ))
+        }
+    }
+
+    Ok(scores)
+}
+
+/// Reads the bytes received by `device` during `window`, after
+/// discarding those received before.
+fn read_window(device: &fs::File, window: Duration) -> Result<Vec<u8>, SerialError> {
+    use SerialError as Error;
+
+    let fd = device.as_raw_fd();
+    // SAFETY: fd is a valid file descriptor for the lifetime of device.
+    unsafe { ioctl::tcflsh(fd, libc::TCIFLUSH) }
+        .map_err(|e| Error::General(format!("Failed to flush input of device: tcflsh = {}", e)))?;
+
+    let deadline = Instant::now() + window;
+    let mut bytes = vec![];
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            break;
+        }
+
+        let mut fds = [PollFd::new(fd, PollFlags::POLLIN)];
+        match poll::poll(&mut fds, remaining.as_millis().max(1) as libc::c_int) {
+            Ok(0) => break,
+            Ok(_) => (),
+            Err(Errno::EINTR) => continue,
+            Err(e) => {
+                return Err(Error::General(format!(
+                    "Failed to poll device: poll = {}",
+                    e
+                )))
+            }
+        }
+        if fds[0]
+            .revents()
+            .is_some_and(|revents| revents.contains(PollFlags::POLLHUP))
+        {
+            break;
+        }
+
+        // NOTE(fionread) read only what is available, so that VMIN does
+        // not block the read beyond the window.
+        let mut available: libc::c_int = 0;
+        // SAFETY: as above.
+        unsafe { ioctl::fionread(fd, &mut available) }.map_err(|e| {
+            Error::General(format!(
+                "Failed to read input size of device: fionread = {}",
+                e
+            ))
+        })?;
+        let mut buf = vec![0; available.max(1) as usize];
+        match unistd::read(fd, &mut buf) {
+            Ok(0) => break,
+            Ok(n) => bytes.extend(&buf[..n]),
+            Err(Errno::EINTR | Errno::EAGAIN) => continue,
+            Err(e) => {
+                return Err(Error::General(format!(
+                    "Failed to read device: read = {}",
+                    e
+                )))
+            }
+        }
+    }
+
+    Ok(bytes)
+}
+
+/// The state of a device that [`configure`] changes.
+#[derive(Clone, Copy)]
+struct SavedState {
+    // NOTE(libc) a plain copy, unlike termios::Termios, so that it can
+    // be moved into a signal handler.
+    termios: libc::termios,
+
+    /// `None` if the device has no modem lines.
+    modem_bits: Option<libc::c_int>,
+}
+
+impl SavedState {
+    fn read(fd: RawFd) -> Result<Self, SerialError> {
+        use SerialError as Error;
+
+        let termios = termios::tcgetattr(fd)
+            .map_err(|e| {
+                Error::General(format!(
+                    "Failed to read terminal settings of device: tcgetattr = {}",
+                    e
+                ))
+            })?
+            .into();
+
+        let mut bits: libc::c_int = 0;
+        // SAFETY: fd is a valid file descriptor.
+        let modem_bits = match unsafe { ioctl::tiocmget(fd, &mut bits) } {
+            // NOTE(ENOTTY) pseudo-terminals have no modem lines.
+            Err(Errno::ENOTTY) => None,
+            result => {
+                result.map_err(|e| {
+                    Error::General(format!(
+                        "Failed to read modem bits of device: tiocmget = {}",
+                        e
+                    ))
+                })?;
+                Some(bits)
+            }
+        };
+
+        Ok(Self {
+            termios,
+            modem_bits,
+        })
+    }
+
+    /// Restores the state of the device and disables exclusive mode.
+    /// Only async-signal-safe functions are called, so that this can be
+    /// done from a signal handler.
+    fn restore(&self, fd: RawFd) -> Result<(), Errno> {
+        // SAFETY: fd is a valid file descriptor and the pointers are
+        // valid for the duration of the calls.
+        unsafe {
+            Errno::result(libc::tcsetattr(fd, libc::TCSANOW, &self.termios))?;
+            if let Some(bits) = self.modem_bits {
+                ioctl::tiocmset(fd, &bits)?;
+            }
+            // NOTE(tiocnxcl) the device cannot have been in exclusive
+            // mode before: it could then not have been opened.
+            ioctl::tiocnxcl(fd)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A serial device whose state is restored when the session ends.
+///
+/// [`configure`] changes the terminal settings and modem bits of a
+/// device and puts it into exclusive mode, none of which is undone when
+/// the device is closed; other tools may then be unable to use the
+/// device. A session saves the state of the device before it is
+/// configured, and restores it when the session is dropped or
+/// [closed](Self::close). The process is not unwound on a terminating
+/// signal, so also see [`restore_on_signal`](Self::restore_on_signal)
+/// and [`restore_on_handled_signal`](Self::restore_on_handled_signal).
+pub struct SerialSession {
+    device: fs::File,
+    saved: SavedState,
+    signals: Vec<SigId>,
+    restored: bool,
+}
+
+impl SerialSession {
+    /// Opens the device at `path` and [configures](configure) it with
+    /// `baud_rate`.
+    pub fn open(path: &Path, baud_rate: u32) -> Result<Self, SerialError> {
+        let device = fs::File::open(path).map_err(|e| {
+            SerialError::General(format!("Failed to open {}: {}", path.display(), e))
+        })?;
+        let session = Self::new(device)?;
+        session.configure(baud_rate)?;
+
+        Ok(session)
+    }
+
+    /// Takes ownership of `device` and saves its state, without
+    /// configuring it. Configure it with [`configure`](Self::configure),
+    /// or with [`detect_baud`] on [`device`](Self::device).
+    pub fn new(device: fs::File) -> Result<Self, SerialError> {
+        let saved = SavedState::read(device.as_raw_fd())?;
+
+        Ok(Self {
+            device,
+            saved,
+            signals: vec![],
+            restored: false,
+        })
+    }
+
+    /// [Configures](configure) the device with `baud_rate`.
+    pub fn configure(&self, baud_rate: u32) -> Result<(), SerialError> {
+        configure(&self.device, baud_rate)
+    }
+
+    /// [Configures](configure_with) the device with `config`.
+    pub fn configure_with(&self, config: &SerialConfig) -> Result<(), SerialError> {
+        configure_with(&self.device, config)
+    }
+
+    pub fn device(&self) -> &fs::File {
+        &self.device
+    }
+
+    /// Also restores the state of the device when any of `signals` is
+    /// received, after which the process is terminated as if the
+    /// signal had not been handled. For signals the process handles in
+    /// another way, see
+    /// [`restore_on_handled_signal`](Self::restore_on_handled_signal).
+    ///
+    /// The handlers are unregistered when the session ends.
+    pub fn restore_on_signal(&mut self, signals: &[libc::c_int]) -> Result<(), SerialError> {
+        self.register_restore(signals, true)
+    }
+
+    /// Also restores the state of the device when any of `signals` is
+    /// received, without terminating the process. Use this with signals
+    /// the process handles in another way, e.g. to stop gracefully on
+    /// the first and exit on the second: the handlers registered after
+    /// this call run after the state is restored.
+    ///
+    /// The handlers are unregistered when the session ends.
+    pub fn restore_on_handled_signal(
+        &mut self,
+        signals: &[libc::c_int],
+    ) -> Result<(), SerialError> {
+        self.register_restore(signals, false)
+    }
+
+    fn register_restore(
+        &mut self,
+        signals: &[libc::c_int],
+        terminate: bool,
+    ) -> Result<(), SerialError> {
+        let fd = self.device.as_raw_fd();
+        let saved = self.saved;
+        for signal in signals {
+            let signal = *signal;
+            // SAFETY: the handler only calls async-signal-safe
+            // functions, and is unregistered before fd is closed.
+            let id = unsafe {
+                signal_hook::low_level::register(signal, move || {
+                    let _ = saved.restore(fd);
+                    if terminate {
+                        let _ = signal_hook::low_level::emulate_default_handler(signal);
+                    }
+                })
+            }
+            .map_err(|e| {
+                SerialError::General(format!(
+                    "Failed to register handler of signal {}: {}",
+                    signal, e
+                ))
+            })?;
+            self.signals.push(id);
+        }
+
+        Ok(())
+    }
+
+    /// Restores the state of the device and closes it. Unlike on drop,
+    /// failures to restore are reported.
+    pub fn close(mut self) -> Result<(), SerialError> {
+        self.restored = true;
+        self.saved
+            .restore(self.device.as_raw_fd())
+            .map_err(|e| SerialError::General(format!("Failed to restore state of device: {}", e)))
+    }
+}
+
+impl Read for SerialSession {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.device.read(buf)
+    }
+}
+
+impl Drop for SerialSession {
+    fn drop(&mut self) {
+        for id in self.signals.drain(..) {
+            signal_hook::low_level::unregister(id);
+        }
+        if !self.restored {
+            let _ = self.saved.restore(self.device.as_raw_fd());
+        }
+    }
+}
+
+/// Returns whether `file` refers to a terminal device, e.g. a serial
+/// device, which can be [configured](configure). Regular files, pipes,
+/// and sockets are not.
+pub fn is_tty(file: &fs::File) -> bool {
+    unistd::isatty(file.as_raw_fd()).unwrap_or(false)
+}
+
+/// A pseudo-terminal whose slave device stands in for a serial device.
+///
+/// Bytes written to the pseudo-terminal can be read from the device at
+/// [`path`](Self::path), which is in raw mode and can be
+/// [configured](configure) like a serial device. The baud rate does not
+/// limit the rate at which bytes are transferred.
+pub struct PseudoTerminal {
+    master: fs::File,
+    path: PathBuf,
+}
+
+impl PseudoTerminal {
+    pub fn open() -> Result<Self, SerialError> {
+        use SerialError as Error;
+
+        let pty = pty::openpty(None, None).map_err(|e| {
+            Error::General(format!("Failed to open pseudo-terminal: openpty = {}", e))
+        })?;
+        // SAFETY: openpty returned a new file descriptor, owned by us.
+        let master = unsafe { fs::File::from_raw_fd(pty.master) };
+        // SAFETY: as above.
+        let slave = unsafe { fs::File::from_raw_fd(pty.slave) };
+
+        let path = unistd::ttyname(slave.as_raw_fd()).map_err(|e| {
+            Error::General(format!(
+                "Failed to read pseudo-terminal path: ttyname = {}",
+                e
+            ))
+        })?;
+
+        let mut settings = termios::tcgetattr(slave.as_raw_fd()).map_err(|e| {
+            Error::General(format!(
+                "Failed to read terminal settings of pseudo-terminal: tcgetattr = {}",
+                e
+            ))
+        })?;
+        termios::cfmakeraw(&mut settings);
+        termios::tcsetattr(slave.as_raw_fd(), SetArg::TCSANOW, &settings).map_err(|e| {
+            Error::General(format!(
+                "Failed to apply terminal settings to pseudo-terminal: tcsetattr = {}",
+                e
+            ))
+        })?;
+
+        // NOTE(drop) the slave device is closed so that the master is
+        // hung up until the device is opened; see wait_for_open.
+        drop(slave);
+
+        Ok(Self { master, path })
+    }
+
+    /// Returns the path of the slave device, e.g. `/dev/pts/3`.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Blocks until the slave device is opened. Bytes written before
+    /// then may be lost.
+    pub fn wait_for_open(&self) -> Result<(), SerialError> {
+        while self.is_hung_up()? {
+            thread::sleep(Duration::from_millis(100));
+        }
+
+        Ok(())
+    }
+
+    /// Blocks until the slave device is closed, e.g. to keep the
+    /// pseudo-terminal open until all bytes written have been read.
+    pub fn wait_for_close(&self) -> Result<(), SerialError> {
+        while !self.is_hung_up()? {
+            thread::sleep(Duration::from_millis(100));
+        }
+
+        Ok(())
+    }
+
+    /// Returns whether the slave device is closed.
+    fn is_hung_up(&self) -> Result<bool, SerialError> {
+        let mut fds = [PollFd::new(self.master.as_raw_fd(), PollFlags::empty())];
+        poll::poll(&mut fds, 0).map_err(|e| {
+            SerialError::General(format!("Failed to poll pseudo-terminal: poll = {}", e))
+        })?;
+
+        Ok(fds[0]
+            .revents()
+            .is_some_and(|revents| revents.contains(PollFlags::POLLHUP)))
+    }
+}
+
+impl Write for PseudoTerminal {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.master.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.master.flush()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -212,4 +881,221 @@ mod tests {
             BaudRate::try_from(ArbitraryBaudRate(9600))
         );
     }
+
+    #[test]
+    fn pseudo_terminal() {
+        use std::io::Read;
+
+        let mut pty = PseudoTerminal::open().unwrap();
+        let mut device = fs::File::open(pty.path()).unwrap();
+        assert!(is_tty(&device));
+        configure(&device, 115200).unwrap();
+        pty.wait_for_open().unwrap();
+
+        // NOTE(0x0a) not translated in raw mode
+        pty.write_all(&[0x70, 0x0a, 0x0d]).unwrap();
+        let mut buf = [0; 3];
+        device.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, [0x70, 0x0a, 0x0d]);
+    }
+
+    #[test]
+    fn nonblocking() {
+        use std::io::Read;
+        use std::os::unix::fs::OpenOptionsExt;
+
+        let pty = PseudoTerminal::open().unwrap();
+        let mut device = fs::OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(pty.path())
+            .unwrap();
+        configure(&device, 115200).unwrap();
+        pty.wait_for_open().unwrap();
+
+        let mut buf = [0; 1];
+        assert_eq!(
+            device.read(&mut buf).unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+    }
+
+    #[test]
+    fn serial_config() {
+        let pty = PseudoTerminal::open().unwrap();
+        let device = fs::File::open(pty.path()).unwrap();
+
+        configure(&device, 115200).unwrap();
+        let settings = termios::tcgetattr(device.as_raw_fd()).unwrap();
+        assert_eq!(
+            settings.control_flags
+                & (ControlFlags::CSIZE | ControlFlags::PARENB | ControlFlags::CSTOPB),
+            ControlFlags::CS8
+        );
+        assert!(!settings.input_flags.contains(InputFlags::IXON));
+        assert_eq!(settings.control_chars[CC::VMIN as usize], 100);
+        assert_eq!(settings.control_chars[CC::VTIME as usize], 2);
+
+        configure_with(
+            &device,
+            &SerialConfig {
+                data_bits: DataBits::Seven,
+                parity: Parity::Odd,
+                stop_bits: StopBits::Two,
+                flow_control: FlowControl::Software,
+                read_min_bytes: 0,
+                read_timeout: Duration::from_millis(1050),
+                ..SerialConfig::new(9600)
+            },
+        )
+        .unwrap();
+        let settings = termios::tcgetattr(device.as_raw_fd()).unwrap();
+        // NOTE(CS8) pseudo-terminals force 8 data bits without parity.
+        assert_eq!(termios::cfgetispeed(&settings), BaudRate::B9600);
+        assert!(settings
+            .control_flags
+            .contains(ControlFlags::PARODD | ControlFlags::CSTOPB));
+        assert!(settings
+            .input_flags
+            .contains(InputFlags::IXON | InputFlags::IXOFF | InputFlags::INPCK));
+        assert_eq!(settings.control_chars[CC::VMIN as usize], 0);
+        assert_eq!(settings.control_chars[CC::VTIME as usize], 11);
+
+        assert!(configure_with(
+            &device,
+            &SerialConfig {
+                read_timeout: Duration::from_secs(26),
+                ..SerialConfig::new(9600)
+            }
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn baud_score() {
+        #[rustfmt::skip]
+        let trace: &[u8] = &[
+            // Sync
+            0, 0, 0, 0, 0, 0x80,
+            // Instrumentation, port 1
+            0b0000_1001, b'a',
+            // Overflow
+            0b0111_0000,
+        ];
+        let good = BaudScore::new(115200, trace);
+        assert_eq!((good.packets, good.syncs, good.malformed), (3, 1, 0));
+
+        // NOTE(0xf4) invalid hardware source headers
+        let garbage = BaudScore::new(9600, &[0xf4, 0x70, 0xf4, 0xf4]);
+        assert_eq!(garbage.syncs, 0);
+        assert!(good.score() > garbage.score());
+        assert_eq!(BaudScore::new(9600, &[]).score(), 0.0);
+    }
+
+    #[test]
+    fn detect_baud_reads_window() {
+        let mut pty = PseudoTerminal::open().unwrap();
+        let device = fs::File::open(pty.path()).unwrap();
+        pty.wait_for_open().unwrap();
+
+        let writer = thread::spawn(move || {
+            for _ in 0..20 {
+                pty.write_all(&[0, 0, 0, 0, 0, 0x80, 0b0000_1001, b'a'])
+                    .unwrap();
+                thread::sleep(Duration::from_millis(10));
+            }
+            pty
+        });
+        // NOTE(pty) pseudo-terminals are not limited by the baud rate:
+        // both candidates decode equally well.
+        let scores = detect_baud(&device, &[9600, 115200], Duration::from_millis(50)).unwrap();
+        // NOTE(join) the device is only usable while the pty is open.
+        let _pty = writer.join().unwrap();
+
+        // NOTE(160) each window only holds part of the trace.
+        assert_eq!(scores.len(), 2);
+        for score in &scores {
+            assert!(score.bytes > 0 && score.bytes < 160);
+            assert!(score.syncs > 0);
+            assert_eq!(score.malformed, 0);
+            assert_eq!(score.bytes % 8, 0);
+            assert_eq!(
+                (score.syncs, score.packets),
+                (score.bytes / 8, score.bytes / 8 * 2)
+            );
+        }
+        assert!(scores[0].packets >= scores[1].packets);
+
+        let settings = termios::tcgetattr(device.as_raw_fd()).unwrap();
+        assert_eq!(
+            termios::cfgetispeed(&settings),
+            match scores[0].baud_rate {
+                9600 => BaudRate::B9600,
+                _ => BaudRate::B115200,
+            }
+        );
+    }
+
+    #[test]
+    fn session_restores_state() {
+        fn is_exclusive(device: &fs::File) -> bool {
+            let mut exclusive: libc::c_int = 0;
+            // SAFETY: the fd of device is valid.
+            assert_eq!(
+                unsafe { libc::ioctl(device.as_raw_fd(), libc::TIOCGEXCL, &mut exclusive) },
+                0
+            );
+            exclusive != 0
+        }
+
+        let pty = PseudoTerminal::open().unwrap();
+        // NOTE(observer) terminal settings are shared by all open file
+        // descriptions of the device.
+        let observer = fs::File::open(pty.path()).unwrap();
+        let original = termios::tcgetattr(observer.as_raw_fd()).unwrap();
+
+        let session = SerialSession::open(pty.path(), 115200).unwrap();
+        let configured = termios::tcgetattr(observer.as_raw_fd()).unwrap();
+        assert_eq!(termios::cfgetispeed(&configured), BaudRate::B115200);
+        assert_ne!(configured, original);
+        assert!(is_exclusive(&observer));
+
+        drop(session);
+        assert_eq!(termios::tcgetattr(observer.as_raw_fd()).unwrap(), original);
+        assert!(!is_exclusive(&observer));
+
+        let session = SerialSession::open(pty.path(), 9600).unwrap();
+        session.close().unwrap();
+        assert_eq!(termios::tcgetattr(observer.as_raw_fd()).unwrap(), original);
+    }
+
+    #[test]
+    fn session_restores_on_handled_signal() {
+        use std::sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        };
+
+        let pty = PseudoTerminal::open().unwrap();
+        let observer = fs::File::open(pty.path()).unwrap();
+        let original = termios::tcgetattr(observer.as_raw_fd()).unwrap();
+
+        let mut session = SerialSession::open(pty.path(), 115200).unwrap();
+        session.restore_on_handled_signal(&[libc::SIGUSR1]).unwrap();
+        let handled = Arc::new(AtomicBool::new(false));
+        let id = signal_hook::flag::register(libc::SIGUSR1, handled.clone()).unwrap();
+
+        signal_hook::low_level::raise(libc::SIGUSR1).unwrap();
+        assert!(handled.load(Ordering::SeqCst));
+        assert_eq!(termios::tcgetattr(observer.as_raw_fd()).unwrap(), original);
+
+        signal_hook::low_level::unregister(id);
+        drop(session);
+    }
+
+    #[test]
+    fn regular_file_is_not_tty() {
+        let file = fs::File::open(std::env::current_exe().unwrap()).unwrap();
+        assert!(!is_tty(&file));
+    }
 }
@@ -0,0 +1,505 @@
+//! A virtual ITM/DWT: a model of the trace hardware of a Cortex-M
+//! target that turns simulated events into a trace stream, together
+//! with the ground truth of when each packet was generated.
+//!
+//! The [`Simulator`] models
+//!
+//! - writes to the ITM stimulus ports and exception entry, exit, and
+//! return, each generating a packet;
+//! - periodic PC sampling of the DWT;
+//! - a single trace output FIFO of limited size that drains at a fixed
+//! rate. A packet that does not fit is dropped and signalled by an
+//! [`Overflow`](TracePacket::Overflow) packet once there is room again;
+//! - local timestamps, generated after the packets of a timestamp
+//! counter tick with the [`TimestampDataRelation`] of the conditions
+//! they were generated under, and of the maximum value when the counter
+//! overflows. Global timestamps are not generated;
+//! - periodic synchronization packets.
+//!
+//! Time advances in processor clock cycles, only when
+//! [`advance`](Simulator::advance) is called:
+//!
+//! ```
+//! use itm::{
+//!     sim::{Simulator, SimulatorOptions},
+//!     Decoder, DecoderOptions, LocalTimestampOptions, TimestampsConfiguration,
+//! };
+//!
+//! let mut sim = Simulator::new(SimulatorOptions {
+//!     clock_frequency: 16_000_000,
+//!     lts_prescaler: LocalTimestampOptions::Enabled,
+//!     fifo_size: 32,
+//!     cycles_per_byte: 80,
+//!     pc_sample_interval: None,
+//!     sync_interval: None,
+//! });
+//! sim.advance(100);
+//! assert!(sim.write_stimulus(0, b"a"));
+//! let (stream, truth) = sim.finish();
+//!
+//! let packets = Decoder::new(stream.as_slice(), DecoderOptions { ignore_eof: false })
+//!     .timestamps(TimestampsConfiguration {
+//!         clock_frequency: 16_000_000,
+//!         lts_prescaler: LocalTimestampOptions::Enabled,
+//!         expect_malformed: false,
+//!     })
+//!     .next()
+//!     .unwrap()
+//!     .unwrap();
+//! assert_eq!(packets.packets, [truth[0].packet.clone()]);
+//! ```
+
+use super::{
+    encode::{encode, LTS1_MAX},
+    ExceptionAction, LocalTimestampOptions, TimestampDataRelation, TracePacket, VectActive,
+};
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// Smallest [`SimulatorOptions::fifo_size`]: the size of a
+/// synchronization packet, the largest packet generated.
+pub const MIN_FIFO_SIZE: usize = 6;
+
+/// [`Simulator`] configuration.
+#[derive(Debug, Clone)]
+pub struct SimulatorOptions {
+    /// Frequency of the processor clock, which also drives the local
+    /// timestamp counter.
+    pub clock_frequency: u32,
+
+    /// Prescaler of the local timestamp counter. No local timestamps
+    /// are generated if [`Disabled`](LocalTimestampOptions::Disabled).
+    pub lts_prescaler: LocalTimestampOptions,
+
+    /// Size of the trace output FIFO in bytes. Must be at least
+    /// [`MIN_FIFO_SIZE`], or no synchronization packet would fit.
+    pub fifo_size: usize,
+
+    /// Clock cycles it takes to output a byte from the FIFO, e.g.
+    /// `clock_frequency * 10 / baud_rate` for SWO in UART mode.
+    pub cycles_per_byte: u64,
+
+    /// Interval of PC samples in clock cycles, if PC sampling is
+    /// enabled. Must not be zero.
+    pub pc_sample_interval: Option<u64>,
+
+    /// Interval of synchronization packets in clock cycles, if
+    /// enabled. Must not be zero.
+    pub sync_interval: Option<u64>,
+}
+
+impl SimulatorOptions {
+    fn prescale(&self) -> Option<u64> {
+        match self.lts_prescaler {
+            LocalTimestampOptions::Disabled => None,
+            LocalTimestampOptions::Enabled => Some(1),
+            LocalTimestampOptions::EnabledDiv4 => Some(4),
+            LocalTimestampOptions::EnabledDiv16 => Some(16),
+            LocalTimestampOptions::EnabledDiv64 => Some(64),
+        }
+    }
+}
+
+/// A packet generated by the [`Simulator`], save for local timestamps.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimulatedPacket {
+    /// The clock cycle the packet was generated in.
+    pub cycle: u64,
+
+    /// When the packet was generated, relative to simulation start.
+    pub time: Duration,
+
+    pub packet: TracePacket,
+
+    /// Whether the packet was dropped because the FIFO was full.
+    pub dropped: bool,
+}
+
+/// A simulated ITM/DWT. See the [module documentation](self).
+pub struct Simulator {
+    options: SimulatorOptions,
+    cycle: u64,
+    pc: Option<u32>,
+    fifo: VecDeque<u8>,
+    output: Vec<u8>,
+
+    /// Cycle at which the byte at the front of the FIFO has been output.
+    next_output: u64,
+
+    /// Whether packets were generated since the last local timestamp,
+    /// and if so, whether any of them was delayed behind other packets
+    /// in the FIFO.
+    group: Option<bool>,
+
+    /// A local timestamp that did not fit in the FIFO when it was due,
+    /// and whether its packets were delayed.
+    deferred: Option<bool>,
+
+    /// Whether packets were dropped since the last Overflow packet.
+    overflowed: bool,
+
+    /// Timestamp counter tick of the last local timestamp.
+    lts_tick: u64,
+
+    packets: Vec<SimulatedPacket>,
+}
+
+impl Simulator {
+    /// # Panics
+    ///
+    /// If [`fifo_size`](SimulatorOptions::fifo_size) is smaller than
+    /// [`MIN_FIFO_SIZE`].
+    pub fn new(options: SimulatorOptions) -> Self {
+        assert!(
+            options.fifo_size >= MIN_FIFO_SIZE,
+            "FIFO size must be at least {} bytes",
+            MIN_FIFO_SIZE
+        );
+
+        Self {
+            options,
+            cycle: 0,
+            pc: None,
+            fifo: VecDeque::new(),
+            output: vec![],
+            next_output: 0,
+            group: None,
+            deferred: None,
+            overflowed: false,
+            lts_tick: 0,
+            packets: vec![],
+        }
+    }
+
+    /// Returns the current clock cycle.
+    pub fn cycle(&self) -> u64 {
+        self.cycle
+    }
+
+    /// Returns the current time, relative to simulation start.
+    pub fn time(&self) -> Duration {
+        self.time_of(self.cycle)
+    }
+
+    /// Returns the trace stream output so far.
+    pub fn output(&self) -> &[u8] {
+        &self.output
+    }
+
+    /// Returns the packets generated so far.
+    pub fn packets(&self) -> &[SimulatedPacket] {
+        &self.packets
+    }
+
+    /// Sets the PC sampled by periodic PC sampling. `None` if the
+    /// processor is sleeping.
+    pub fn set_pc(&mut self, pc: Option<u32>) {
+        self.pc = pc;
+    }
+
+    /// Writes `payload` to stimulus `port`. Returns whether the write
+    /// was traced; `false` if the FIFO was full, as would be read from
+    /// the stimulus port before writing.
+    ///
+    /// # Panics
+    ///
+    /// If `port` is larger than 31 or `payload` is not 1, 2, or 4 bytes.
+    pub fn write_stimulus(&mut self, port: u8, payload: &[u8]) -> bool {
+        self.generate(TracePacket::Instrumentation {
+            port,
+            payload: payload.to_vec(),
+        })
+    }
+
+    /// Traces that `exception` was entered, exited, or returned to.
+    /// Returns whether the exception trace packet fit in the FIFO.
+    pub fn exception(&mut self, exception: VectActive, action: ExceptionAction) -> bool {
+        self.generate(TracePacket::ExceptionTrace { exception, action })
+    }
+
+    /// Advances time by `cycles` clock cycles, outputting bytes from the
+    /// FIFO and generating periodic packets on the way. Packets
+    /// generated before the call are timestamped with the current
+    /// cycle.
+    pub fn advance(&mut self, cycles: u64) {
+        let target = self.cycle + cycles;
+        while self.cycle < target {
+            self.end_cycle();
+
+            let mut next = target;
+            if !self.fifo.is_empty() {
+                next = next.min(self.next_output);
+            }
+            for interval in [self.options.pc_sample_interval, self.options.sync_interval]
+                .into_iter()
+                .flatten()
+            {
+                next = next.min((self.cycle / interval + 1) * interval);
+            }
+            if let Some(prescale) = self.options.prescale() {
+                next = next.min((self.lts_tick + LTS1_MAX + 1) * prescale);
+            }
+            self.cycle = next;
+
+            while !self.fifo.is_empty() && self.next_output <= self.cycle {
+                self.output.push(self.fifo.pop_front().unwrap());
+                self.next_output += self.options.cycles_per_byte.max(1);
+            }
+            self.flush();
+            self.wrap();
+
+            if is_due(self.cycle, self.options.sync_interval) {
+                self.sync();
+            }
+            if is_due(self.cycle, self.options.pc_sample_interval) {
+                self.generate(TracePacket::PCSample { pc: self.pc });
+            }
+        }
+    }
+
+    /// Stops periodic packets, advances time until all packets have
+    /// been timestamped and output, and returns the trace stream and
+    /// the generated packets.
+    pub fn finish(mut self) -> (Vec<u8>, Vec<SimulatedPacket>) {
+        self.options.pc_sample_interval = None;
+        self.options.sync_interval = None;
+
+        let step = self
+            .options
+            .cycles_per_byte
+            .max(self.options.prescale().unwrap_or(1));
+        while self.group.is_some()
+            || self.deferred.is_some()
+            || self.overflowed
+            || !self.fifo.is_empty()
+        {
+            self.advance(step);
+        }
+
+        (self.output, self.packets)
+    }
+
+    fn time_of(&self, cycle: u64) -> Duration {
+        Duration::from_nanos(
+            (cycle as u128 * 1_000_000_000 / self.options.clock_frequency as u128) as u64,
+        )
+    }
+
+    fn room(&self) -> usize {
+        self.options.fifo_size.saturating_sub(self.fifo.len())
+    }
+
+    fn push(&mut self, bytes: &[u8]) {
+        if self.fifo.is_empty() {
+            self.next_output = self.cycle + self.options.cycles_per_byte.max(1);
+        }
+        self.fifo.extend(bytes);
+    }
+
+    fn record(&mut self, packet: TracePacket, dropped: bool) {
+        self.packets.push(SimulatedPacket {
+            cycle: self.cycle,
+            time: self.time_of(self.cycle),
+            packet,
+            dropped,
+        });
+    }
+
+    /// Queues `packet` in the FIFO, or drops it if it does not fit or
+    /// if an Overflow packet is pending. Returns whether it was queued.
+    fn generate(&mut self, packet: TracePacket) -> bool {
+        self.flush();
+
+        let bytes = encode(&packet).expect("invalid simulated packet");
+        if self.overflowed || bytes.len() > self.room() {
+            self.overflowed = true;
+            self.record(packet, true);
+            return false;
+        }
+
+        let delayed = !self.fifo.is_empty();
+        self.push(&bytes);
+        self.group = Some(self.group.unwrap_or(false) || delayed);
+        self.record(packet, false);
+
+        true
+    }
+
+    fn sync(&mut self) {
+        let bytes = encode(&TracePacket::Sync).unwrap();
+        if bytes.len() <= self.room() {
+            self.push(&bytes);
+            self.record(TracePacket::Sync, false);
+        }
+    }
+
+    /// Queues the deferred local timestamp and the pending Overflow
+    /// packet, if the FIFO has room for them.
+    fn flush(&mut self) {
+        if let Some(delayed) = self.deferred {
+            if !self.timestamp(delayed, true) {
+                return;
+            }
+            self.deferred = None;
+        }
+
+        if self.overflowed && self.room() > 0 {
+            self.overflowed = false;
+            let delayed = !self.fifo.is_empty();
+            self.push(&encode(&TracePacket::Overflow).unwrap());
+            self.group = Some(self.group.unwrap_or(false) || delayed);
+            self.record(TracePacket::Overflow, false);
+        }
+    }
+
+    /// Ends the current cycle: queues the local timestamp of the packets
+    /// generated since the last one, if the timestamp counter has
+    /// ticked since. Otherwise the packets are timestamped with those of
+    /// a later tick.
+    fn end_cycle(&mut self) {
+        let prescale = match self.options.prescale() {
+            Some(prescale) => prescale,
+            None => {
+                self.group = None;
+                return;
+            }
+        };
+        let delayed = match self.group {
+            // NOTE(deferred) the packets are timestamped with the
+            // deferred timestamp when it is eventually queued.
+            Some(_) if self.deferred.is_some() => return,
+            Some(delayed) if self.cycle / prescale != self.lts_tick => delayed,
+            _ => return,
+        };
+
+        self.group = None;
+        if !self.timestamp(delayed, false) {
+            self.deferred = Some(delayed);
+        }
+    }
+
+    /// Queues the local timestamp of the maximum value that is output
+    /// when the timestamp counter overflows, if no packets await a
+    /// timestamp. If it does not fit in the FIFO, it is dropped.
+    fn wrap(&mut self) {
+        let prescale = match self.options.prescale() {
+            Some(prescale) => prescale,
+            None => return,
+        };
+        if self.cycle / prescale - self.lts_tick <= LTS1_MAX
+            || self.group.is_some()
+            || self.deferred.is_some()
+        {
+            return;
+        }
+
+        if !self.timestamp(false, false) {
+            self.lts_tick += LTS1_MAX;
+            self.overflowed = true;
+        }
+    }
+
+    /// Queues a local timestamp of the current tick. Returns whether it
+    /// fit in the FIFO.
+    ///
+    /// The timestamp is [`Sync`](TimestampDataRelation::Sync) with its
+    /// packets, unless they were `delayed` in the FIFO behind other
+    /// packets, or the timestamp was `deferred` from the tick of its
+    /// packets because the FIFO was full.
+    fn timestamp(&mut self, delayed: bool, deferred: bool) -> bool {
+        let tick = self.cycle / self.options.prescale().unwrap();
+        // NOTE(LTS1_MAX) if the counter overflowed, its maximum value is
+        // output and the remaining ticks are carried over to the next
+        // timestamp.
+        let delta = (tick - self.lts_tick).min(LTS1_MAX);
+
+        let data_relation = match (deferred, delayed) {
+            (false, false) => TimestampDataRelation::Sync,
+            (false, true) => TimestampDataRelation::AssocEventDelay,
+            (true, false) => TimestampDataRelation::UnknownDelay,
+            (true, true) => TimestampDataRelation::UnknownAssocEventDelay,
+        };
+        let lts = match (delta, &data_relation) {
+            (1..=6, TimestampDataRelation::Sync) => {
+                TracePacket::LocalTimestamp2 { ts: delta as u8 }
+            }
+            _ => TracePacket::LocalTimestamp1 {
+                ts: delta as u32,
+                data_relation,
+            },
+        };
+        let bytes = encode(&lts).unwrap();
+        if bytes.len() > self.room() {
+            return false;
+        }
+
+        self.push(&bytes);
+        self.lts_tick += delta;
+        true
+    }
+}
+
+/// Returns whether a periodic packet of `interval` is due at `cycle`.
+fn is_due(cycle: u64, interval: Option<u64>) -> bool {
+    interval.is_some_and(|interval| cycle % interval == 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Decoder, DecoderOptions, Timestamp, TimestampsConfiguration, TracePacketKind};
+
+    #[test]
+    fn counter_overflow() {
+        let mut sim = Simulator::new(SimulatorOptions {
+            clock_frequency: 16_000_000,
+            lts_prescaler: LocalTimestampOptions::Enabled,
+            fifo_size: 32,
+            cycles_per_byte: 1,
+            pc_sample_interval: None,
+            sync_interval: None,
+        });
+        sim.advance(2 * (LTS1_MAX + 1) + 100);
+        assert!(sim.write_stimulus(0, b"a"));
+        let (stream, truth) = sim.finish();
+
+        // NOTE(wrap) the counter overflowed twice while idle.
+        let lts: Vec<TracePacket> =
+            Decoder::new(stream.as_slice(), DecoderOptions { ignore_eof: false })
+                .singles()
+                .map(Result::unwrap)
+                .filter(|packet| packet.kind() == TracePacketKind::LocalTimestamp1)
+                .collect();
+        assert_eq!(
+            lts[..2],
+            vec![
+                TracePacket::LocalTimestamp1 {
+                    ts: LTS1_MAX as u32,
+                    data_relation: TimestampDataRelation::Sync,
+                };
+                2
+            ]
+        );
+
+        let sets: Vec<_> = Decoder::new(stream.as_slice(), DecoderOptions { ignore_eof: false })
+            .timestamps(TimestampsConfiguration {
+                clock_frequency: 16_000_000,
+                lts_prescaler: LocalTimestampOptions::Enabled,
+                expect_malformed: false,
+            })
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(sets.len(), 3);
+        assert_eq!(sets[2].packets, [truth[0].packet.clone()]);
+        match sets[2].timestamp {
+            Timestamp::Sync(offset) => {
+                assert!(
+                    offset.max(truth[0].time) - offset.min(truth[0].time)
+                        < Duration::from_nanos(63)
+                )
+            }
+            ref timestamp => panic!("unexpected timestamp {:?}", timestamp),
+        }
+    }
+}
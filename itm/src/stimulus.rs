@@ -0,0 +1,153 @@
+//! Reassembly of instrumentation packets into per-port text streams.
+//!
+//! The most common use of the ITM is `printf`-style logging over
+//! stimulus port 0. Target-side, a string is written to a stimulus port
+//! as a sequence of 1, 2, or 4-byte writes, each of which yields a
+//! separate [`Instrumentation`](TracePacket::Instrumentation) packet.
+//! [`StimulusLines`] stitches these payloads back together and yields
+//! complete lines of text per port.
+
+use super::TracePacket;
+
+use std::collections::BTreeMap;
+use std::string::FromUtf8Error;
+
+/// A complete line of text written to a stimulus port.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StimulusLine {
+    /// Stimulus port number.
+    pub port: u8,
+
+    /// The line, without its terminating newline. An `Err` if the
+    /// line is not valid UTF-8; the raw bytes can then be recovered via
+    /// [`FromUtf8Error::into_bytes`].
+    pub text: Result<String, FromUtf8Error>,
+}
+
+/// Reassembles [`Instrumentation`](TracePacket::Instrumentation)
+/// payloads into lines of text, separately for each stimulus port.
+///
+/// Payloads are appended to the port's buffer in the order they were
+/// written target-side: a 4-byte write of `"abcd"` is transmitted
+/// little-endian and thus yields the bytes in string order. A line is
+/// complete once a `\n` is written; a trailing `\r` is stripped.
+#[derive(Debug, Default)]
+pub struct StimulusLines {
+    ports: BTreeMap<u8, Vec<u8>>,
+}
+
+impl StimulusLines {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends `payload` to the buffer of stimulus `port` and returns
+    /// the lines this completed, if any.
+    pub fn push(&mut self, port: u8, payload: &[u8]) -> Vec<StimulusLine> {
+        let buffer = self.ports.entry(port).or_default();
+        let mut lines = vec![];
+
+        for b in payload {
+            if *b == b'\n' {
+                lines.push(Self::line(port, std::mem::take(buffer)));
+            } else {
+                buffer.push(*b);
+            }
+        }
+
+        lines
+    }
+
+    /// Like [`push`](Self::push), but for a whole packet. Packets that
+    /// are not [`Instrumentation`](TracePacket::Instrumentation)
+    /// packets are ignored.
+    pub fn push_packet(&mut self, packet: &TracePacket) -> Vec<StimulusLine> {
+        match packet {
+            TracePacket::Instrumentation { port, payload } => self.push(*port, payload),
+            _ => vec![],
+        }
+    }
+
+    /// Returns the incomplete lines of all ports, clearing the
+    /// buffers. Should be called once the trace stream has ended.
+    pub fn flush(&mut self) -> Vec<StimulusLine> {
+        std::mem::take(&mut self.ports)
+            .into_iter()
+            .filter(|(_, buffer)| !buffer.is_empty())
+            .map(|(port, buffer)| Self::line(port, buffer))
+            .collect()
+    }
+
+    fn line(port: u8, mut buffer: Vec<u8>) -> StimulusLine {
+        if buffer.last() == Some(&b'\r') {
+            buffer.pop();
+        }
+
+        StimulusLine {
+            port,
+            text: String::from_utf8(buffer),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mixed_write_sizes() {
+        let mut lines = StimulusLines::new();
+
+        assert!(lines.push(0, b"H").is_empty());
+        assert!(lines.push(0, b"el").is_empty());
+        assert_eq!(
+            lines.push(0, b"lo\r\n"),
+            [StimulusLine {
+                port: 0,
+                text: Ok("Hello".to_string()),
+            }]
+        );
+        assert!(lines.flush().is_empty());
+    }
+
+    #[test]
+    fn interleaved_ports() {
+        let mut lines = StimulusLines::new();
+
+        assert!(lines.push(0, b"ab").is_empty());
+        assert!(lines.push(1, b"cd").is_empty());
+        assert_eq!(
+            lines.push(0, b"\nef"),
+            [StimulusLine {
+                port: 0,
+                text: Ok("ab".to_string()),
+            }]
+        );
+        assert_eq!(
+            lines.push_packet(&TracePacket::Instrumentation {
+                port: 1,
+                payload: b"\n".to_vec(),
+            }),
+            [StimulusLine {
+                port: 1,
+                text: Ok("cd".to_string()),
+            }]
+        );
+        assert_eq!(
+            lines.flush(),
+            [StimulusLine {
+                port: 0,
+                text: Ok("ef".to_string()),
+            }]
+        );
+    }
+
+    #[test]
+    fn invalid_utf8() {
+        let mut lines = StimulusLines::new();
+
+        let line = lines.push(2, &[0xff, 0xfe, b'\n']).pop().unwrap();
+        assert_eq!(line.port, 2);
+        assert_eq!(line.text.unwrap_err().into_bytes(), [0xff, 0xfe]);
+    }
+}
@@ -0,0 +1,124 @@
+//! Reading trace streams from TCP servers, such as the SWO servers of
+//! OpenOCD (`tpiu config ... :<port>`) and other debug probe servers.
+//!
+//! ```no_run
+//! use itm::{tcp::TcpSource, Decoder, DecoderError, DecoderOptions};
+//!
+//! let source = TcpSource::connect("localhost:3443", true)?;
+//! for packet in Decoder::new(source, DecoderOptions { ignore_eof: false }).singles() {
+//!     match packet {
+//!         Err(DecoderError::Io(e)) if itm::tcp::is_reconnect(&e) => continue,
+//!         packet => println!("{:?}", packet),
+//!     }
+//! }
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+use crate::Backoff;
+
+use std::io::{self, Read};
+use std::net::TcpStream;
+
+/// The error returned once by [`TcpSource`] after it reconnected to its
+/// server, wrapped in an [`io::Error`] of kind
+/// [`ConnectionReset`](io::ErrorKind::ConnectionReset). See
+/// [`is_reconnect`].
+#[derive(Debug, thiserror::Error)]
+#[error("connection to {addr} was lost and reestablished")]
+pub struct Reconnected {
+    pub addr: String,
+}
+
+/// Returns whether `e` was returned by [`TcpSource`] after it
+/// reconnected to its server.
+pub fn is_reconnect(e: &io::Error) -> bool {
+    e.get_ref().is_some_and(|e| e.is::<Reconnected>())
+}
+
+/// A trace stream read from a TCP server.
+///
+/// If `reconnect` is set, the source reconnects to the server whenever
+/// the connection is closed or lost, retrying with an increasing delay
+/// of up to 100 ms until the server accepts. The first read after a
+/// reconnect returns a [`Reconnected`] error: a
+/// [`Decoder`](crate::Decoder) reading from the source then drops the
+/// packet it was decoding when the connection was lost and returns the
+/// error, after which decoding continues at the first byte received
+/// over the new connection. [`Timestamps`](crate::Timestamps) drops all
+/// packets since the previous timestamp.
+pub struct TcpSource {
+    addr: String,
+    stream: Option<TcpStream>,
+    reconnect: bool,
+}
+
+impl TcpSource {
+    /// Connects to `addr`, e.g. `localhost:3443`. Fails if the server
+    /// cannot be reached, regardless of `reconnect`.
+    pub fn connect(addr: &str, reconnect: bool) -> io::Result<Self> {
+        Ok(Self {
+            addr: addr.to_string(),
+            stream: Some(TcpStream::connect(addr)?),
+            reconnect,
+        })
+    }
+
+    /// Returns the address of the server.
+    pub fn addr(&self) -> &str {
+        &self.addr
+    }
+
+    fn reestablish(&mut self) -> io::Result<()> {
+        let mut backoff = Backoff::new();
+        loop {
+            match TcpStream::connect(&self.addr) {
+                Ok(stream) => {
+                    self.stream = Some(stream);
+                    return Ok(());
+                }
+                Err(e) if is_lost(&e) || e.kind() == io::ErrorKind::ConnectionRefused => {
+                    backoff.wait()
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+/// Returns whether `e` means that the connection was lost.
+fn is_lost(e: &io::Error) -> bool {
+    use io::ErrorKind::*;
+
+    matches!(
+        e.kind(),
+        ConnectionReset | ConnectionAborted | BrokenPipe | TimedOut | UnexpectedEof
+    )
+}
+
+impl Read for TcpSource {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let stream = match self.stream.as_mut() {
+                Some(stream) => stream,
+                None if self.reconnect => {
+                    self.reestablish()?;
+                    return Err(io::Error::new(
+                        io::ErrorKind::ConnectionReset,
+                        Reconnected {
+                            addr: self.addr.clone(),
+                        },
+                    ));
+                }
+                None => return Ok(0),
+            };
+
+            match stream.read(buf) {
+                Ok(0) => self.stream = None,
+                Ok(n) => return Ok(n),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) if is_lost(&e) => self.stream = None,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
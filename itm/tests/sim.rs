@@ -0,0 +1,286 @@
+use itm::{
+    sim::{SimulatedPacket, Simulator, SimulatorOptions},
+    Decoder, DecoderOptions, ExceptionAction, LocalTimestampOptions, Timestamp,
+    TimestampedTracePackets, TimestampsConfiguration, TracePacket, VectActive,
+};
+use std::time::Duration;
+
+const FREQ: u32 = 16_000_000;
+
+/// Returns the decoded packets, each with the timestamp of its set.
+fn flatten(sets: &[TimestampedTracePackets]) -> Vec<(&Timestamp, &TracePacket)> {
+    sets.iter()
+        .flat_map(|set| {
+            set.packets
+                .iter()
+                .map(move |packet| (&set.timestamp, packet))
+        })
+        .collect()
+}
+
+/// Returns the packets that were output, excluding synchronization
+/// packets.
+fn output(truth: &[SimulatedPacket]) -> Vec<&SimulatedPacket> {
+    truth
+        .iter()
+        .filter(|packet| !packet.dropped && packet.packet != TracePacket::Sync)
+        .collect()
+}
+
+fn cycles(cycles: u64) -> Duration {
+    Duration::from_nanos(cycles * 1_000_000_000 / FREQ as u64)
+}
+
+#[test]
+fn synchronous_timestamps() {
+    let mut sim = Simulator::new(SimulatorOptions {
+        clock_frequency: FREQ,
+        lts_prescaler: LocalTimestampOptions::Enabled,
+        fifo_size: 64,
+        cycles_per_byte: 1,
+        pc_sample_interval: None,
+        sync_interval: None,
+    });
+    let irq = VectActive::Interrupt { irqn: 3 };
+    for i in 1..=20 {
+        sim.advance(i * 37);
+        assert!(sim.exception(irq, ExceptionAction::Entered));
+        sim.advance(10);
+        assert!(sim.write_stimulus(1, &(i as u32).to_le_bytes()));
+        sim.advance(10);
+        assert!(sim.exception(irq, ExceptionAction::Exited));
+    }
+    let (stream, truth) = sim.finish();
+
+    let sets: Vec<_> = Decoder::new(stream.as_slice(), DecoderOptions { ignore_eof: false })
+        .timestamps(TimestampsConfiguration {
+            clock_frequency: FREQ,
+            lts_prescaler: LocalTimestampOptions::Enabled,
+            expect_malformed: false,
+        })
+        .map(Result::unwrap)
+        .collect();
+    let decoded = flatten(&sets);
+    let truth = output(&truth);
+    assert_eq!(decoded.len(), truth.len());
+    for ((timestamp, packet), truth) in decoded.into_iter().zip(truth) {
+        assert_eq!(*packet, truth.packet);
+        match timestamp {
+            // NOTE(ticks) the decoder rounds each local timestamp up to
+            // whole nanoseconds.
+            Timestamp::Sync(offset) => {
+                assert!((*offset).max(truth.time) - (*offset).min(truth.time) < cycles(1))
+            }
+            ts => panic!("expected a synchronous timestamp, got {:?}", ts),
+        }
+    }
+}
+
+#[test]
+fn prescaled_timestamps() {
+    let mut sim = Simulator::new(SimulatorOptions {
+        clock_frequency: FREQ,
+        lts_prescaler: LocalTimestampOptions::EnabledDiv16,
+        fifo_size: 64,
+        cycles_per_byte: 1,
+        pc_sample_interval: None,
+        sync_interval: None,
+    });
+    for i in 0..100u8 {
+        sim.advance(5);
+        sim.write_stimulus(0, &[i]);
+    }
+    let (stream, truth) = sim.finish();
+
+    let sets: Vec<_> = Decoder::new(stream.as_slice(), DecoderOptions { ignore_eof: false })
+        .timestamps(TimestampsConfiguration {
+            clock_frequency: FREQ,
+            lts_prescaler: LocalTimestampOptions::EnabledDiv16,
+            expect_malformed: false,
+        })
+        .map(Result::unwrap)
+        .collect();
+    let decoded = flatten(&sets);
+    let truth = output(&truth);
+    assert_eq!(decoded.len(), truth.len());
+    for ((timestamp, packet), truth) in decoded.into_iter().zip(truth) {
+        assert_eq!(*packet, truth.packet);
+        // NOTE(16, 32) packets are timestamped with the tick of the
+        // prescaled counter they were generated in, or the next one if
+        // the counter had not yet ticked since the previous timestamp.
+        let offset = timestamp.offset();
+        assert!(offset + cycles(16) >= truth.time);
+        assert!(offset < truth.time + cycles(32));
+    }
+}
+
+#[test]
+fn delayed_packets() {
+    let mut sim = Simulator::new(SimulatorOptions {
+        clock_frequency: FREQ,
+        lts_prescaler: LocalTimestampOptions::Enabled,
+        fifo_size: 64,
+        cycles_per_byte: 50,
+        pc_sample_interval: None,
+        sync_interval: None,
+    });
+    sim.advance(10);
+    for i in 0..8u8 {
+        assert!(sim.write_stimulus(2, &[i]));
+        sim.advance(1);
+    }
+    let (stream, truth) = sim.finish();
+
+    let sets: Vec<_> = Decoder::new(stream.as_slice(), DecoderOptions { ignore_eof: false })
+        .timestamps(TimestampsConfiguration {
+            clock_frequency: FREQ,
+            lts_prescaler: LocalTimestampOptions::Enabled,
+            expect_malformed: false,
+        })
+        .map(Result::unwrap)
+        .collect();
+    assert!(matches!(sets[0].timestamp, Timestamp::Sync(_)));
+    assert!(sets[1..]
+        .iter()
+        .all(|set| matches!(set.timestamp, Timestamp::AssocEventDelay(_))));
+
+    // NOTE(AssocEventDelay) the timestamp is that of the generation of
+    // the delayed packet, not of its output.
+    for ((timestamp, packet), truth) in flatten(&sets).into_iter().zip(output(&truth)) {
+        assert_eq!(*packet, truth.packet);
+        let offset = timestamp.offset();
+        assert!(offset.max(truth.time) - offset.min(truth.time) < cycles(1));
+    }
+}
+
+#[test]
+fn overflow() {
+    let mut sim = Simulator::new(SimulatorOptions {
+        clock_frequency: FREQ,
+        lts_prescaler: LocalTimestampOptions::Enabled,
+        fifo_size: 16,
+        cycles_per_byte: 40,
+        pc_sample_interval: None,
+        sync_interval: None,
+    });
+    let mut written = 0;
+    for i in 0..40u32 {
+        sim.advance(20);
+        if sim.write_stimulus(0, &i.to_le_bytes()) {
+            written += 1;
+        }
+    }
+    let (stream, truth) = sim.finish();
+    assert!(written < 40);
+    assert!(truth.iter().any(|packet| packet.dropped));
+
+    let sets: Vec<_> = Decoder::new(stream.as_slice(), DecoderOptions { ignore_eof: false })
+        .timestamps(TimestampsConfiguration {
+            clock_frequency: FREQ,
+            lts_prescaler: LocalTimestampOptions::Enabled,
+            expect_malformed: false,
+        })
+        .map(Result::unwrap)
+        .collect();
+    let decoded = flatten(&sets);
+    let truth = output(&truth);
+    assert!(decoded
+        .iter()
+        .any(|(_, packet)| **packet == TracePacket::Overflow));
+    assert_eq!(
+        decoded
+            .iter()
+            .filter(|(_, packet)| matches!(packet, TracePacket::Instrumentation { .. }))
+            .count(),
+        written
+    );
+    assert_eq!(decoded.len(), truth.len());
+
+    for ((timestamp, packet), truth) in decoded.into_iter().zip(truth) {
+        assert_eq!(*packet, truth.packet);
+        match timestamp {
+            Timestamp::Sync(offset) | Timestamp::AssocEventDelay(offset) => {
+                assert!((*offset).max(truth.time) - (*offset).min(truth.time) < cycles(1))
+            }
+            // NOTE(prev) the packet was generated at some point between
+            // the previous and current timestamp.
+            Timestamp::UnknownDelay { prev, curr }
+            | Timestamp::UnknownAssocEventDelay { prev, curr } => {
+                assert!(*prev <= truth.time + cycles(1) && truth.time <= *curr + cycles(1))
+            }
+        }
+    }
+}
+
+#[test]
+fn periodic_packets() {
+    let mut sim = Simulator::new(SimulatorOptions {
+        clock_frequency: FREQ,
+        lts_prescaler: LocalTimestampOptions::Enabled,
+        fifo_size: 64,
+        cycles_per_byte: 1,
+        pc_sample_interval: Some(1000),
+        sync_interval: Some(4000),
+    });
+    sim.set_pc(Some(0x0800_0100));
+    sim.advance(5000);
+    sim.set_pc(None);
+    sim.advance(5000);
+    let (stream, truth) = sim.finish();
+
+    let packets: Vec<TracePacket> =
+        Decoder::new(stream.as_slice(), DecoderOptions { ignore_eof: false })
+            .singles()
+            .map(Result::unwrap)
+            .filter(|packet| {
+                !matches!(
+                    packet,
+                    TracePacket::LocalTimestamp1 { .. } | TracePacket::LocalTimestamp2 { .. }
+                )
+            })
+            .collect();
+    assert_eq!(
+        packets,
+        truth
+            .iter()
+            .map(|packet| packet.packet.clone())
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(
+        packets
+            .iter()
+            .filter(|packet| **packet
+                == TracePacket::PCSample {
+                    pc: Some(0x0800_0100)
+                })
+            .count(),
+        5
+    );
+    assert_eq!(
+        packets
+            .iter()
+            .filter(|packet| **packet == TracePacket::PCSample { pc: None })
+            .count(),
+        5
+    );
+    assert_eq!(
+        packets
+            .iter()
+            .filter(|packet| **packet == TracePacket::Sync)
+            .count(),
+        2
+    );
+}
+
+#[test]
+#[should_panic(expected = "FIFO size")]
+fn fifo_too_small() {
+    Simulator::new(SimulatorOptions {
+        clock_frequency: FREQ,
+        lts_prescaler: LocalTimestampOptions::Enabled,
+        fifo_size: itm::sim::MIN_FIFO_SIZE - 1,
+        cycles_per_byte: 1,
+        pc_sample_interval: None,
+        sync_interval: None,
+    });
+}
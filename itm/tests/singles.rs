@@ -305,3 +305,20 @@ fn decode_datatracevalue_packet() {
         assert_eq!(decoder.next().unwrap().unwrap(), packet);
     }
 }
+
+#[test]
+fn byte_offsets() {
+    #[rustfmt::skip]
+    let trace: &[u8] = &[
+        0b0111_0000,                         // overflow
+        0b0000_1011, 0b0000_0001, 0b0000_0010, 0b0000_0011, 0b0000_0100, // instrumentation
+        0b0111_1000,                         // extension
+    ];
+    let mut decoder = Decoder::new(trace, DecoderOptions { ignore_eof: false }).singles();
+
+    let mut offsets = vec![];
+    while decoder.next().is_some() {
+        offsets.push(decoder.bytes_consumed());
+    }
+    assert_eq!(offsets, [1, 6, 7]);
+}
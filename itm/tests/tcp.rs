@@ -0,0 +1,58 @@
+use itm::{tcp, tcp::TcpSource, Decoder, DecoderError, DecoderOptions, TracePacket};
+use std::io::Write;
+use std::net::TcpListener;
+use std::thread;
+
+/// Serves each of `sessions` to a client, closing the connection after
+/// each, and returns the address of the server.
+fn replay(sessions: Vec<Vec<u8>>) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    thread::spawn(move || {
+        for session in sessions {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(&session).unwrap();
+        }
+    });
+
+    addr
+}
+
+#[test]
+fn eof_without_reconnect() {
+    let addr = replay(vec![vec![0b0111_0000, 0b0000_1011, 0x01]]);
+    let source = TcpSource::connect(&addr, false).unwrap();
+    let packets: Vec<_> = Decoder::new(source, DecoderOptions { ignore_eof: false })
+        .singles()
+        .map(|packet| packet.unwrap())
+        .collect();
+
+    // NOTE(1) the incomplete instrumentation packet is dropped at EOF
+    assert_eq!(packets, [TracePacket::Overflow]);
+}
+
+#[test]
+fn partial_packet_across_reconnect() {
+    let addr = replay(vec![
+        // Overflow, and an instrumentation packet cut short
+        vec![0b0111_0000, 0b0000_1011, 0x01, 0x02],
+        // A complete instrumentation packet, and Overflow
+        vec![0b0000_1011, 0x01, 0x02, 0x03, 0x04, 0b0111_0000],
+    ]);
+    let source = TcpSource::connect(&addr, true).unwrap();
+    let mut singles = Decoder::new(source, DecoderOptions { ignore_eof: false }).singles();
+
+    assert_eq!(singles.next().unwrap().unwrap(), TracePacket::Overflow);
+    match singles.next().unwrap() {
+        Err(DecoderError::Io(e)) => assert!(tcp::is_reconnect(&e)),
+        packet => panic!("expected a reconnect, got {:?}", packet),
+    }
+    assert_eq!(
+        singles.next().unwrap().unwrap(),
+        TracePacket::Instrumentation {
+            port: 1,
+            payload: vec![0x01, 0x02, 0x03, 0x04],
+        }
+    );
+    assert_eq!(singles.next().unwrap().unwrap(), TracePacket::Overflow);
+}
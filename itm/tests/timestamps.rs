@@ -0,0 +1,55 @@
+use itm::*;
+
+#[test]
+fn global_timestamps() {
+    #[rustfmt::skip]
+    let stream: &[u8] = &[
+        // GTS1 of (0b10101 << 21) | 100, wrapped
+        0b1001_0100, 0b1110_0100, 0b1000_0000, 0b1000_0000, 0b0101_0101,
+        // GTS2 of 1
+        0b1011_0100, 0b1000_0001, 0b1000_0000, 0b1000_0000, 0b0000_0000,
+        // instrumentation, followed by a synchronous LTS2 of one tick
+        0b0000_0001, b'a', 0b0001_0000,
+
+        // NOTE(full-size) a full-size GTS1 carries all lower-order bits,
+        // however small its value.
+        0b1001_0100, 0b1000_0101, 0b1000_0000, 0b1000_0000, 0b0000_0000,
+        0b0000_0001, b'a', 0b0001_0000,
+
+        // NOTE(compressed) omitted bytes are those of the previous GTS1.
+        // GTS1 of (3 << 7) | 7
+        0b1001_0100, 0b1000_0111, 0b0000_0011,
+        0b0000_0001, b'a', 0b0001_0000,
+
+        // NOTE(wrap) the lower-order bits apply once the GTS2 is received.
+        // GTS1 of 2, wrapped
+        0b1001_0100, 0b1000_0010, 0b1000_0000, 0b1000_0000, 0b0100_0000,
+        0b0000_0001, b'a', 0b0001_0000,
+        // GTS2 of 2
+        0b1011_0100, 0b1000_0010, 0b1000_0000, 0b1000_0000, 0b0000_0000,
+        0b0000_0001, b'a', 0b0001_0000,
+    ];
+
+    let ticks: Vec<u64> = Decoder::new(stream, DecoderOptions { ignore_eof: false })
+        .timestamps(TimestampsConfiguration {
+            // one tick per microsecond
+            clock_frequency: 1_000_000,
+            lts_prescaler: LocalTimestampOptions::Enabled,
+            expect_malformed: false,
+        })
+        // NOTE(as_micros) offsets are rounded up to whole nanoseconds
+        .map(|set| set.unwrap().timestamp.offset().as_micros() as u64)
+        .collect();
+
+    let upper = 1 << 26;
+    assert_eq!(
+        ticks,
+        [
+            (upper | (0b10101 << 21) | 100) + 1,
+            (upper | 5) + 1,
+            (upper | (3 << 7) | 7) + 1,
+            (upper | (3 << 7) | 7) + 2,
+            ((2 << 26) | 2) + 1,
+        ]
+    );
+}